@@ -0,0 +1,132 @@
+use crate::cartridge::Cartridge;
+use crate::memory::Memory;
+use crate::model::Model;
+use crate::registers::Registers;
+
+// IO registers as the DMG boot ROM leaves them when it jumps to 0x0100. Used when we skip
+// running a boot ROM entirely, other models only differ in a handful of them.
+const DMG_POST_BOOT_IO: [(u16, u8); 43] = [
+    (0xFF00, 0xCF), // P1
+    (0xFF01, 0x00), // SB
+    (0xFF02, 0x7E), // SC
+    (0xFF04, 0xAB), // DIV
+    (0xFF05, 0x00), // TIMA
+    (0xFF06, 0x00), // TMA
+    (0xFF07, 0xF8), // TAC
+    (0xFF0F, 0xE1), // IF
+    (0xFF10, 0x80), // NR10
+    (0xFF11, 0xBF), // NR11
+    (0xFF12, 0xF3), // NR12
+    (0xFF13, 0xFF), // NR13
+    (0xFF14, 0xBF), // NR14
+    (0xFF16, 0x3F), // NR21
+    (0xFF17, 0x00), // NR22
+    (0xFF18, 0xFF), // NR23
+    (0xFF19, 0xBF), // NR24
+    (0xFF1A, 0x7F), // NR30
+    (0xFF1B, 0xFF), // NR31
+    (0xFF1C, 0x9F), // NR32
+    (0xFF1D, 0xFF), // NR33
+    (0xFF1E, 0xBF), // NR34
+    (0xFF20, 0xFF), // NR41
+    (0xFF21, 0x00), // NR42
+    (0xFF22, 0x00), // NR43
+    (0xFF23, 0xBF), // NR44
+    (0xFF24, 0x77), // NR50
+    (0xFF25, 0xF3), // NR51
+    (0xFF26, 0xF1), // NR52
+    (0xFF40, 0x91), // LCDC
+    (0xFF41, 0x85), // STAT
+    (0xFF42, 0x00), // SCY
+    (0xFF43, 0x00), // SCX
+    (0xFF44, 0x00), // LY
+    (0xFF45, 0x00), // LYC
+    (0xFF46, 0xFF), // DMA
+    (0xFF47, 0xFC), // BGP
+    (0xFF48, 0xFF), // OBP0
+    (0xFF49, 0xFF), // OBP1
+    (0xFF4A, 0x00), // WY
+    (0xFF4B, 0x00), // WX
+    (0xFF50, 0xFF), // Boot ROM unmapped
+    (0xFFFF, 0x00), // IE
+];
+
+pub fn apply_post_boot_state(memory: &mut Memory, model: Model) {
+    for &(addr, val) in DMG_POST_BOOT_IO.iter() {
+        memory.initialize(addr, val);
+    }
+
+    let differences: &[(u16, u8)] = match model {
+        Model::Dmg0 => &[(0xFF04, 0x18)],
+        Model::Dmg | Model::Mgb => &[],
+        Model::Sgb | Model::Sgb2 => &[(0xFF26, 0xF0)],
+        Model::Cgb | Model::Agb => &[(0xFF02, 0x7F), (0xFF46, 0x00)],
+    };
+    for &(addr, val) in differences.iter() {
+        memory.initialize(addr, val);
+    }
+
+    // The CGB boot ROM leaves every palette white for color games.
+    if memory.cgb_mode() {
+        for palette in 0..8 {
+            memory.initialize_palette(false, palette, [0x7FFF; 4]);
+            memory.initialize_palette(true, palette, [0x7FFF; 4]);
+        }
+    }
+}
+
+// CPU registers when the boot ROM hands over to the cartridge. A in particular is what games
+// look at to figure out which hardware they're running on.
+pub fn post_boot_registers(model: Model, cart: &Cartridge) -> Registers {
+    // The DMG and MGB boot ROMs leave H and C set unless the header checksum is 0.
+    let checksum_flags = if cart.header_checksum() == 0 {
+        0x80
+    } else {
+        0xB0
+    };
+
+    let (af, bc, de, hl) = match model {
+        Model::Dmg0 => (0x0100, 0xFF13, 0x00C1, 0x8403),
+        Model::Dmg => (0x0100 | checksum_flags, 0x0013, 0x00D8, 0x014D),
+        Model::Mgb => (0xFF00 | checksum_flags, 0x0013, 0x00D8, 0x014D),
+        Model::Sgb => (0x0100, 0x0014, 0x0000, 0xC060),
+        Model::Sgb2 => (0xFF00, 0x0014, 0x0000, 0xC060),
+        Model::Cgb if cart.supports_cgb() => (0x1180, 0x0000, 0xFF56, 0x000D),
+        Model::Cgb => (0x1180, 0x0000, 0x0008, 0x007C),
+        Model::Agb if cart.supports_cgb() => (0x1100, 0x0100, 0xFF56, 0x000D),
+        Model::Agb => (0x1100, 0x0100, 0x0008, 0x007C),
+    };
+
+    return Registers {
+        af: af,
+        bc: bc,
+        de: de,
+        hl: hl,
+        sp: 0xFFFE,
+        pc: 0x0100,
+    };
+}
+
+// Low byte of the internal counter DIV is the top half of, when the boot ROM hands over. It
+// decides when DIV first ticks, which mooneye's boot_div tests check. It's only been measured
+// for the DMG and MGB, the other models start at the beginning of a DIV period.
+pub fn post_boot_div_phase(model: Model) -> u8 {
+    return match model {
+        Model::Dmg | Model::Mgb => 0xCC,
+        _ => 0x00,
+    };
+}
+
+// Boot ROMs are either 256 bytes (DMG, MGB, SGB) or 2304 bytes (CGB, AGB).
+pub fn validate_boot_rom(data: &[u8], model: Model) -> Result<(), String> {
+    if data.len() != model.boot_rom_size() {
+        return Err(format!(
+            "{:?} boot ROM should be {} bytes, got {}",
+            model,
+            model.boot_rom_size(),
+            data.len()
+        ));
+    }
+
+    return Ok(());
+}
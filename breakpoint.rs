@@ -0,0 +1,411 @@
+use crate::memory::Memory;
+use crate::registers::Registers;
+use crate::watchpoint::Access;
+
+// Conditions are small expressions over registers and memory, like "A == 0x3F && [HL] != 0".
+// Registers are a, f, b, c, d, e, h, l, af, bc, de, hl, sp and pc, flags are zf, nf, hf and cf,
+// [expr] reads a byte of memory, and numbers are decimal unless they start with 0x or $.
+// Watchpoints can also look at the access that triggered them through addr, value and old
+// (what was there before a write). Anything non-zero is true, comparisons give 1 or 0.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitOr,
+    BitAnd,
+    Add,
+    Sub,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Expr {
+    Number(u32),
+    Register(String),
+    Memory(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Number(u32),
+    Ident(String),
+    Op(&'static str),
+}
+
+// Longest first so "<=" doesn't get read as "<"
+const OPERATORS: [&str; 17] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<", ">", "|", "&", "+", "-", "!", "(", ")", "[", "]",
+];
+
+fn variable_value(name: &str, registers: &Registers, access: Option<&Access>) -> Option<u32> {
+    let access = access.copied().unwrap_or_default();
+    let value = match name {
+        "addr" => access.addr,
+        "value" => access.value as u16,
+        "old" => access.old as u16,
+        "a" => registers.af >> 8,
+        "f" => registers.af & 0xFF,
+        "b" => registers.bc >> 8,
+        "c" => registers.bc & 0xFF,
+        "d" => registers.de >> 8,
+        "e" => registers.de & 0xFF,
+        "h" => registers.hl >> 8,
+        "l" => registers.hl & 0xFF,
+        "af" => registers.af,
+        "bc" => registers.bc,
+        "de" => registers.de,
+        "hl" => registers.hl,
+        "sp" => registers.sp,
+        "pc" => registers.pc,
+        "zf" => registers.z_set() as u16,
+        "nf" => registers.n_set() as u16,
+        "hf" => registers.h_set() as u16,
+        "cf" => registers.c_set() as u16,
+        _ => return None,
+    };
+    return Some(value as u32);
+}
+
+// Hex with a 0x or $ prefix, decimal otherwise.
+pub fn parse_number(text: &str) -> Option<u32> {
+    if let Some(hex) = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .or_else(|| text.strip_prefix("$"))
+    {
+        return u32::from_str_radix(hex, 16).ok();
+    }
+    return text.parse::<u32>().ok();
+}
+
+fn tokenize(text: &str) -> Result<std::vec::Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut rest = text.trim_start();
+
+    while !rest.is_empty() {
+        if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '$' || c == '_'))
+                .unwrap_or(rest.len());
+            if end == 0 {
+                return Err(format!("Unexpected character in {}", rest));
+            }
+
+            let word = &rest[..end];
+            if word.starts_with(|c: char| c.is_ascii_digit() || c == '$') {
+                let number = parse_number(word).ok_or(format!("Invalid number {}", word))?;
+                tokens.push(Token::Number(number));
+            } else {
+                let name = word.to_lowercase();
+                if variable_value(&name, &Registers::default(), None).is_none() {
+                    return Err(format!("Unknown register {}", word));
+                }
+                tokens.push(Token::Ident(name));
+            }
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+
+    return Ok(tokens);
+}
+
+struct Parser {
+    tokens: std::vec::Vec<Token>,
+    pos: usize,
+}
+
+// Binary operators from lowest to highest precedence, each level parsed by parse_level.
+const LEVELS: [&[(&str, BinaryOp)]; 6] = [
+    &[("||", BinaryOp::Or)],
+    &[("&&", BinaryOp::And)],
+    &[
+        ("==", BinaryOp::Eq),
+        ("!=", BinaryOp::Ne),
+        ("<=", BinaryOp::Le),
+        (">=", BinaryOp::Ge),
+        ("<", BinaryOp::Lt),
+        (">", BinaryOp::Gt),
+    ],
+    &[("|", BinaryOp::BitOr)],
+    &[("&", BinaryOp::BitAnd)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+];
+
+impl Parser {
+    fn peek_op(&self) -> Option<&'static str> {
+        return match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        };
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        if self.peek_op() != Some(op) {
+            return Err(format!("Expected {}", op));
+        }
+        self.pos += 1;
+        return Ok(());
+    }
+
+    fn parse_level(&mut self, level: usize) -> Result<Expr, String> {
+        if level == LEVELS.len() {
+            return self.parse_unary();
+        }
+
+        let mut lhs = self.parse_level(level + 1)?;
+        loop {
+            let op = match self.peek_op() {
+                Some(op) => op,
+                None => return Ok(lhs),
+            };
+            let binary_op = match LEVELS[level].iter().find(|(text, _)| *text == op) {
+                Some((_, binary_op)) => *binary_op,
+                None => return Ok(lhs),
+            };
+            self.pos += 1;
+            let rhs = self.parse_level(level + 1)?;
+            lhs = Expr::Binary(binary_op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        return match token {
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Ident(name)) => Ok(Expr::Register(name)),
+            Some(Token::Op("!")) => Ok(Expr::Not(Box::new(self.parse_unary()?))),
+            Some(Token::Op("(")) => {
+                let expr = self.parse_level(0)?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Some(Token::Op("[")) => {
+                let expr = self.parse_level(0)?;
+                self.expect("]")?;
+                Ok(Expr::Memory(Box::new(expr)))
+            }
+            Some(Token::Op(op)) => Err(format!("Unexpected {}", op)),
+            None => Err("Unexpected end of expression".to_string()),
+        };
+    }
+}
+
+impl Expr {
+    pub fn parse(text: &str) -> Result<Expr, String> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            pos: 0,
+        };
+        let expr = parser.parse_level(0)?;
+        if parser.pos != parser.tokens.len() {
+            return Err(format!("Unexpected {:?}", parser.tokens[parser.pos]));
+        }
+        return Ok(expr);
+    }
+
+    // Memory is read the way the debugger sees it, without going through the CPU bus.
+    pub fn eval(&self, registers: &Registers, memory: &Memory, access: Option<&Access>) -> u32 {
+        return match self {
+            Expr::Number(n) => *n,
+            Expr::Register(name) => variable_value(name, registers, access).unwrap(),
+            Expr::Memory(addr) => memory[addr.eval(registers, memory, access) as u16] as u32,
+            Expr::Not(expr) => (expr.eval(registers, memory, access) == 0) as u32,
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = lhs.eval(registers, memory, access);
+                // || and && don't look at the right side unless they have to
+                match op {
+                    BinaryOp::Or if lhs != 0 => return 1,
+                    BinaryOp::And if lhs == 0 => return 0,
+                    _ => {}
+                }
+                let rhs = rhs.eval(registers, memory, access);
+                match op {
+                    BinaryOp::Or | BinaryOp::And => (rhs != 0) as u32,
+                    BinaryOp::Eq => (lhs == rhs) as u32,
+                    BinaryOp::Ne => (lhs != rhs) as u32,
+                    BinaryOp::Lt => (lhs < rhs) as u32,
+                    BinaryOp::Le => (lhs <= rhs) as u32,
+                    BinaryOp::Gt => (lhs > rhs) as u32,
+                    BinaryOp::Ge => (lhs >= rhs) as u32,
+                    BinaryOp::BitOr => lhs | rhs,
+                    BinaryOp::BitAnd => lhs & rhs,
+                    BinaryOp::Add => lhs.wrapping_add(rhs),
+                    BinaryOp::Sub => lhs.wrapping_sub(rhs),
+                }
+            }
+        };
+    }
+}
+
+// The expression along with how it was written, to show it back to the user.
+#[derive(Clone, Debug)]
+pub struct Condition {
+    pub text: String,
+    pub expr: Expr,
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Condition, String> {
+        return Ok(Condition {
+            text: text.trim().to_string(),
+            expr: Expr::parse(text)?,
+        });
+    }
+}
+
+// Addresses are always hex, with or without 0x/$.
+pub fn parse_address(text: &str) -> Result<u16, String> {
+    let hex = text.trim_start_matches("0x").trim_start_matches('$');
+    return u16::from_str_radix(hex, 16).map_err(|_| format!("Invalid address {}", text));
+}
+
+// "4000" or "3:4000", the bank being the ROM bank that has to be mapped for the address to
+// match.
+pub fn parse_location(text: &str) -> Result<(u16, Option<usize>), String> {
+    return match text.split_once(':') {
+        Some((bank, addr)) => Ok((parse_address(addr)?, Some(parse_address(bank)? as usize))),
+        None => Ok((parse_address(text)?, None)),
+    };
+}
+
+pub fn format_location(addr: u16, bank: Option<usize>) -> String {
+    return match bank {
+        Some(bank) => format!("{:02X}:{:04X}", bank, addr),
+        None => format!("{:04X}", addr),
+    };
+}
+
+#[derive(Clone, Debug)]
+pub struct Breakpoint {
+    // Given out by the console when the breakpoint is added.
+    pub id: u32,
+    pub addr: u16,
+    pub bank: Option<usize>,
+    pub condition: Option<Condition>,
+    // Hits left to skip before actually stopping.
+    pub ignore_count: u32,
+    // Every time the breakpoint was reached with its condition true, skipped or not.
+    pub hit_count: u32,
+}
+
+impl Breakpoint {
+    pub fn new(addr: u16, bank: Option<usize>, condition: Option<Condition>) -> Breakpoint {
+        return Breakpoint {
+            id: 0,
+            addr: addr,
+            bank: bank,
+            condition: condition,
+            ignore_count: 0,
+            hit_count: 0,
+        };
+    }
+
+    // Called when the CPU is about to run the instruction at |pc|, which is in ROM bank |bank|
+    // if it's in ROM at all. True when execution should stop.
+    pub fn check(
+        &mut self,
+        pc: u16,
+        bank: Option<usize>,
+        registers: &Registers,
+        memory: &Memory,
+    ) -> bool {
+        if pc != self.addr || (self.bank.is_some() && self.bank != bank) {
+            return false;
+        }
+        if let Some(condition) = &self.condition {
+            if condition.expr.eval(registers, memory, None) == 0 {
+                return false;
+            }
+        }
+
+        self.hit_count += 1;
+        if self.ignore_count > 0 {
+            self.ignore_count -= 1;
+            return false;
+        }
+        return true;
+    }
+
+    pub fn describe(&self) -> String {
+        let mut ret = format!("{}: {}", self.id, format_location(self.addr, self.bank));
+        if let Some(condition) = &self.condition {
+            ret += &format!(" if {}", condition.text);
+        }
+        ret += &format!(", hit {} times", self.hit_count);
+        if self.ignore_count > 0 {
+            ret += &format!(", ignoring the next {}", self.ignore_count);
+        }
+        return ret;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::breakpoint::Breakpoint;
+    use crate::breakpoint::Condition;
+    use crate::breakpoint::Expr;
+    use crate::memory::Memory;
+    use crate::registers::Registers;
+
+    fn registers() -> Registers {
+        return Registers {
+            af: 0x3F80,
+            hl: 0xC000,
+            ..Registers::default()
+        };
+    }
+
+    #[test]
+    fn expressions() {
+        let mut memory = Memory::empty();
+        memory.set(0xC000, 0x12);
+        memory.set(0xC001, 0x34);
+        let registers = registers();
+        let eval = |text: &str| Expr::parse(text).unwrap().eval(&registers, &memory, None);
+
+        assert_eq!(1, eval("A == 0x3F && [HL] != 0"));
+        assert_eq!(0, eval("a == $3F && [hl] == 0"));
+        assert_eq!(0x34, eval("[HL + 1]"));
+        assert_eq!(1, eval("zf && !cf"));
+        assert_eq!(1, eval("a < 64 || [0]"));
+        assert_eq!(1, eval("(f & 0x80) == 0x80"));
+
+        assert!(Expr::parse("A ==").is_err());
+        assert!(Expr::parse("[HL").is_err());
+        assert!(Expr::parse("X == 1").is_err());
+        assert!(Expr::parse("A = 1").is_err());
+    }
+
+    #[test]
+    fn ignore_count_and_banks() {
+        let memory = Memory::empty();
+        let registers = registers();
+
+        let condition = Some(Condition::parse("A == 0x3F").unwrap());
+        let mut breakpoint = Breakpoint::new(0x4000, Some(2), condition);
+        breakpoint.ignore_count = 1;
+
+        assert!(!breakpoint.check(0x4000, Some(1), &registers, &memory));
+        assert!(!breakpoint.check(0x4001, Some(2), &registers, &memory));
+        assert_eq!(0, breakpoint.hit_count);
+
+        assert!(!breakpoint.check(0x4000, Some(2), &registers, &memory));
+        assert!(breakpoint.check(0x4000, Some(2), &registers, &memory));
+        assert_eq!(2, breakpoint.hit_count);
+
+        breakpoint.condition = Some(Condition::parse("A == 0").unwrap());
+        assert!(!breakpoint.check(0x4000, Some(2), &registers, &memory));
+    }
+}
@@ -0,0 +1,146 @@
+use crate::breakpoint;
+use crate::symbols::Symbols;
+
+// Deep enough for any real game, keeps a game that never returns from growing it forever.
+const MAX_DEPTH: usize = 256;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FrameKind {
+    Call,
+    Rst,
+    Interrupt,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Frame {
+    pub kind: FrameKind,
+    // The CALL or RST, or the instruction the interrupt came in before.
+    pub caller: u16,
+    pub caller_bank: Option<usize>,
+    pub target: u16,
+    pub target_bank: Option<usize>,
+    // Where the return address went, SP right after the call.
+    pub sp: u16,
+}
+
+impl Frame {
+    // "00:0153 (Main+$3) call to 01:4000 (Helper), SP DFFC"
+    pub fn describe(&self, symbols: &Symbols) -> String {
+        let kind = match self.kind {
+            FrameKind::Call => "call",
+            FrameKind::Rst => "rst",
+            FrameKind::Interrupt => "interrupt",
+        };
+        return format!(
+            "{}{} {} to {}{}, SP {:04X}",
+            breakpoint::format_location(self.caller, self.caller_bank),
+            symbols.suffix(self.caller, self.caller_bank),
+            kind,
+            breakpoint::format_location(self.target, self.target_bank),
+            symbols.suffix(self.target, self.target_bank),
+            self.sp
+        );
+    }
+}
+
+// What the CPU called and hasn't returned from yet, innermost last. Games don't always return
+// the way they called: some pop the return address and jump, some load SP with something new.
+// Frames are matched to returns by where their return address is on the stack, so anything
+// that got left above SP is dropped instead of throwing off the frames below it.
+#[derive(Clone, Default)]
+pub struct CallStack {
+    frames: std::vec::Vec<Frame>,
+}
+
+impl CallStack {
+    pub fn push(&mut self, frame: Frame) {
+        // Whatever has its return address at or past the new one was unwound some other way.
+        while self.frames.last().is_some_and(|top| top.sp <= frame.sp) {
+            self.frames.pop();
+        }
+        if self.frames.len() == MAX_DEPTH {
+            self.frames.remove(0);
+        }
+        self.frames.push(frame);
+    }
+
+    // A return that took its address from |sp|.
+    pub fn ret(&mut self, sp: u16) {
+        while self.frames.last().is_some_and(|top| top.sp < sp) {
+            self.frames.pop();
+        }
+        // Otherwise it's a jump done with PUSH and RET, which doesn't end a frame.
+        if self.frames.last().is_some_and(|top| top.sp == sp) {
+            self.frames.pop();
+        }
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        return &self.frames;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::call_stack::FrameKind;
+    use crate::cpu::Cpu;
+    use crate::memory::Memory;
+
+    #[test]
+    fn calls_and_returns() {
+        let mut cpu = Cpu::new();
+        let mut memory = Memory::empty();
+        cpu.registers.sp = 0xDFFE;
+        // 0100: CALL 0150
+        memory[0x0100] = 0xCD;
+        memory[0x0101] = 0x50;
+        memory[0x0102] = 0x01;
+        // 0150: RST 38, 0151: RET
+        memory[0x0150] = 0xFF;
+        memory[0x0151] = 0xC9;
+        // 0038: PUSH BC, LD HL 0160, PUSH HL, RET (jumps to 0160)
+        memory[0x0038] = 0xC5;
+        memory[0x0039] = 0x21;
+        memory[0x003A] = 0x60;
+        memory[0x003B] = 0x01;
+        memory[0x003C] = 0xE5;
+        memory[0x003D] = 0xC9;
+        // 0160: POP BC, RET
+        memory[0x0160] = 0xC1;
+        memory[0x0161] = 0xC9;
+
+        cpu.tick(&mut memory, false);
+        cpu.tick(&mut memory, false);
+        let frames = cpu.call_stack.frames();
+        assert_eq!(2, frames.len());
+        assert_eq!(FrameKind::Call, frames[0].kind);
+        assert_eq!(0x0100, frames[0].caller);
+        assert_eq!(0x0150, frames[0].target);
+        assert_eq!(0xDFFC, frames[0].sp);
+        assert_eq!(FrameKind::Rst, frames[1].kind);
+        assert_eq!(0x0150, frames[1].caller);
+        assert_eq!(0x0038, frames[1].target);
+
+        // The PUSH/RET jump leaves both frames alone
+        for _ in 0..4 {
+            cpu.tick(&mut memory, false);
+        }
+        assert_eq!(0x0160, cpu.registers.pc);
+        assert_eq!(2, cpu.call_stack.frames().len());
+
+        cpu.tick(&mut memory, false);
+        cpu.tick(&mut memory, false);
+        assert_eq!(0x0151, cpu.registers.pc);
+        assert_eq!(1, cpu.call_stack.frames().len());
+
+        // A call after SP got moved up past the first frame replaces it
+        cpu.registers.sp = 0xDFFE;
+        cpu.registers.pc = 0x0100;
+        cpu.tick(&mut memory, false);
+        assert_eq!(1, cpu.call_stack.frames().len());
+
+        cpu.registers.pc = 0x0151;
+        cpu.tick(&mut memory, false);
+        assert!(cpu.call_stack.frames().is_empty());
+    }
+}
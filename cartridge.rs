@@ -27,4 +27,35 @@ impl Cartridge {
             data: data,
         };
     }
+
+    // 0x80 means the game also runs on a DMG, 0xC0 that it's CGB only.
+    pub fn supports_cgb(&self) -> bool {
+        return self.data[0x0143] & 0x80 != 0;
+    }
+
+    // The SGB ignores packets unless the header asks for SGB functions and uses the new
+    // licensee code.
+    pub fn supports_sgb(&self) -> bool {
+        return self.data[0x0146] == 0x03 && self.data[0x014B] == 0x33;
+    }
+
+    // Sum of the title bytes, which is how the CGB boot ROM recognizes DMG games.
+    pub fn title_checksum(&self) -> u8 {
+        return self.data[0x0134..0x0144]
+            .iter()
+            .fold(0u8, |sum, b| sum.wrapping_add(*b));
+    }
+
+    // Either the old licensee code is 0x01, or it's 0x33 and the new one is "01".
+    pub fn licensed_by_nintendo(&self) -> bool {
+        return match self.data[0x014B] {
+            0x01 => true,
+            0x33 => &self.data[0x0144..0x0146] == b"01",
+            _ => false,
+        };
+    }
+
+    pub fn header_checksum(&self) -> u8 {
+        return self.data[0x014D];
+    }
 }
@@ -0,0 +1,420 @@
+use crate::cartridge::Cartridge;
+use crate::joypad::Button;
+use crate::memory::Memory;
+use crate::palette;
+use crate::palette::Palette;
+
+// The 30 palettes in the CGB boot ROM, in RGB555 like they go to palette RAM. What DMG games
+// get is put together from these, see COMBINATIONS.
+const PALETTES: [[u16; 4]; 30] = [
+    [0x7FFF, 0x32BF, 0x00D0, 0x0000],
+    [0x639F, 0x4279, 0x15B0, 0x04CB],
+    [0x7FFF, 0x6E31, 0x454A, 0x0000],
+    [0x7FFF, 0x1BEF, 0x0200, 0x0000],
+    [0x7FFF, 0x421F, 0x1CF2, 0x0000],
+    [0x7FFF, 0x5294, 0x294A, 0x0000],
+    [0x7FFF, 0x03FF, 0x012F, 0x0000],
+    [0x7FFF, 0x03EF, 0x01D6, 0x0000],
+    [0x7FFF, 0x42B5, 0x3DC8, 0x0000],
+    [0x7E74, 0x03FF, 0x0180, 0x0000],
+    [0x67FF, 0x77AC, 0x1A13, 0x2D6B],
+    [0x7ED6, 0x4BFF, 0x2175, 0x0000],
+    [0x53FF, 0x4A5F, 0x7E52, 0x0000],
+    [0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0],
+    [0x03ED, 0x7FFF, 0x255F, 0x0000],
+    [0x036A, 0x021F, 0x03FF, 0x7FFF],
+    [0x7FFF, 0x01DF, 0x0112, 0x0000],
+    [0x231F, 0x035F, 0x00F2, 0x0009],
+    [0x7FFF, 0x03EA, 0x011F, 0x0000],
+    [0x299F, 0x001A, 0x000C, 0x0000],
+    [0x7FFF, 0x027F, 0x001F, 0x0000],
+    [0x7FFF, 0x03E0, 0x0206, 0x0120],
+    [0x7FFF, 0x7EEB, 0x001F, 0x7C00],
+    [0x7FFF, 0x3FFF, 0x7E00, 0x001F],
+    [0x7FFF, 0x03FF, 0x001F, 0x0000],
+    [0x03FF, 0x001F, 0x000C, 0x0000],
+    [0x7FFF, 0x033F, 0x0193, 0x0000],
+    [0x0000, 0x4200, 0x037F, 0x7FFF],
+    [0x7FFF, 0x7E8C, 0x7C00, 0x0000],
+    [0x7FFF, 0x1BEF, 0x6180, 0x0000],
+];
+
+// OBJ0, OBJ1 and BG palettes for a DMG game, as offsets into the colors of PALETTES in the
+// same order the boot ROM has them. A few start at the last color of a palette and run into
+// the next one, which is how e.g. SUPER MARIOLAND ends up with black objects on white.
+const fn pal(index: usize) -> usize {
+    return index * 4;
+}
+
+const COMBINATIONS: [(usize, usize, usize); 51] = [
+    (pal(4), pal(4), pal(29)),         // 0, right+a, the default
+    (pal(18), pal(18), pal(18)),       // 1, right
+    (pal(20), pal(20), pal(20)),       // 2
+    (pal(24), pal(24), pal(24)),       // 3, down+a
+    (pal(9), pal(9), pal(9)),          // 4
+    (pal(0), pal(0), pal(0)),          // 5, up
+    (pal(27), pal(27), pal(27)),       // 6, right+b
+    (pal(5), pal(5), pal(5)),          // 7, left+b
+    (pal(12), pal(12), pal(12)),       // 8, down
+    (pal(26), pal(26), pal(26)),       // 9
+    (pal(16), pal(8), pal(8)),         // 10
+    (pal(4), pal(28), pal(28)),        // 11
+    (pal(4), pal(2), pal(2)),          // 12
+    (pal(3), pal(4), pal(4)),          // 13
+    (pal(4), pal(29), pal(29)),        // 14
+    (pal(28), pal(4), pal(28)),        // 15
+    (pal(2), pal(17), pal(2)),         // 16
+    (pal(16), pal(16), pal(8)),        // 17
+    (pal(4), pal(4), pal(7)),          // 18
+    (pal(4), pal(4), pal(18)),         // 19
+    (pal(4), pal(4), pal(20)),         // 20
+    (pal(19), pal(19), pal(9)),        // 21
+    (pal(4) - 1, pal(4) - 1, pal(11)), // 22
+    (pal(17), pal(17), pal(2)),        // 23
+    (pal(4), pal(4), pal(2)),          // 24
+    (pal(4), pal(4), pal(3)),          // 25
+    (pal(28), pal(28), pal(0)),        // 26
+    (pal(3), pal(3), pal(0)),          // 27
+    (pal(0), pal(0), pal(1)),          // 28, up+b
+    (pal(18), pal(22), pal(18)),       // 29
+    (pal(20), pal(22), pal(20)),       // 30
+    (pal(24), pal(22), pal(24)),       // 31
+    (pal(16), pal(22), pal(8)),        // 32
+    (pal(17), pal(4), pal(13)),        // 33
+    (pal(28) - 1, pal(0), pal(14)),    // 34
+    (pal(28) - 1, pal(4), pal(15)),    // 35
+    (pal(19), pal(22), pal(9)),        // 36
+    (pal(16), pal(28), pal(10)),       // 37
+    (pal(4), pal(23), pal(28)),        // 38
+    (pal(17), pal(22), pal(2)),        // 39
+    (pal(4), pal(0), pal(2)),          // 40, left+a
+    (pal(4), pal(28), pal(3)),         // 41
+    (pal(28), pal(3), pal(0)),         // 42
+    (pal(3), pal(28), pal(4)),         // 43, up+a
+    (pal(21), pal(28), pal(4)),        // 44
+    (pal(3), pal(28), pal(0)),         // 45
+    (pal(25), pal(3), pal(28)),        // 46
+    (pal(0), pal(28), pal(8)),         // 47
+    (pal(4), pal(3), pal(28)),         // 48, left
+    (pal(28), pal(3), pal(6)),         // 49, down+b
+    (pal(4), pal(28), pal(29)),        // 50
+];
+
+const fn color(offset: usize) -> u32 {
+    return palette::rgb555_to_rgb(PALETTES[offset / 4][offset % 4]);
+}
+
+const fn colors(offset: usize) -> [u32; 4] {
+    return [
+        color(offset),
+        color(offset + 1),
+        color(offset + 2),
+        color(offset + 3),
+    ];
+}
+
+const fn combination(index: usize) -> Palette {
+    let (obj0, obj1, bg) = COMBINATIONS[index];
+    return Palette {
+        bg: colors(bg),
+        obj0: colors(obj0),
+        obj1: colors(obj1),
+    };
+}
+
+// Used for every game the boot ROM doesn't recognize. Same as holding Right + A.
+pub const DEFAULT: Palette = combination(0);
+
+// The palettes that can be picked by holding a direction (and optionally A or B) while the
+// boot logo is showing.
+const BUTTON_COMBOS: [(&str, Palette); 12] = [
+    ("up", combination(5)),
+    ("up+a", combination(43)),
+    ("up+b", combination(28)),
+    ("left", combination(48)),
+    ("left+a", combination(40)),
+    ("left+b", combination(7)),
+    ("down", combination(8)),
+    ("down+a", combination(3)),
+    ("down+b", combination(49)),
+    ("right", combination(1)),
+    ("right+a", DEFAULT),
+    ("right+b", combination(6)),
+];
+
+// Games the boot ROM gives their own palette: the title checksum, the 4th letter of the title
+// when the checksum is shared, and an index into COMBINATIONS. The first match wins.
+const TITLES: [(u8, Option<u8>, usize); 94] = [
+    (0x00, None, 0),
+    (0x88, None, 4),  // ALLEY WAY
+    (0x16, None, 5),  // YAKUMAN
+    (0x36, None, 35), // BASEBALL
+    (0xD1, None, 34), // TENNIS
+    (0xDB, None, 3),  // TETRIS
+    (0xF2, None, 31), // QIX
+    (0x3C, None, 15), // DR.MARIO
+    (0x8C, None, 10), // RADARMISSION
+    (0x92, None, 5),  // F1RACE
+    (0x3D, None, 19), // YOSSY NO TAMAGO
+    (0x5C, None, 36),
+    (0x58, None, 7),  // X
+    (0xC9, None, 37), // MARIOLAND2
+    (0x3E, None, 30), // YOSSY NO COOKIE
+    (0x70, None, 44), // ZELDA
+    (0x1D, None, 21),
+    (0x59, None, 32),
+    (0x69, None, 31), // TETRIS FLASH
+    (0x19, None, 20), // DONKEY KONG
+    (0x35, None, 5),  // MARIO'S PICROSS
+    (0xA8, None, 33),
+    (0x14, None, 13), // POKEMON RED
+    (0xAA, None, 14), // POKEMON GREEN
+    (0x75, None, 5),  // PICROSS 2
+    (0x95, None, 29), // YOSSY NO PANEPON
+    (0x99, None, 5),  // KIRAKIRA KIDS
+    (0x34, None, 18), // GAMEBOY GALLERY
+    (0x6F, None, 9),  // POCKETCAMERA
+    (0x15, None, 3),
+    (0xFF, None, 2),  // BALLOON KID
+    (0x97, None, 26), // KINGOFTHEZOO
+    (0x4B, None, 25), // DMG FOOTBALL
+    (0x90, None, 25), // WORLD CUP
+    (0x17, None, 41), // OTHELLO
+    (0x10, None, 42), // SUPER RC PRO-AM
+    (0x39, None, 26), // DYNABLASTER
+    (0xF7, None, 45), // BOY AND BLOB GB2
+    (0xF6, None, 42), // MEGAMAN
+    (0xA2, None, 45), // STAR WARS-NOA
+    (0x49, None, 36),
+    (0x4E, None, 38), // WAVERACE
+    (0x43, None, 26),
+    (0x68, None, 42), // LOLO2
+    (0xE0, None, 30), // YOSHI'S COOKIE
+    (0x8B, None, 41), // MYSTIC QUEST
+    (0xF0, None, 34),
+    (0xCE, None, 34), // TOPRANKINGTENNIS
+    (0x0C, None, 5),  // MANSELL
+    (0x29, None, 42), // MEGAMAN3
+    (0xE8, None, 6),  // SPACE INVADERS
+    (0xB7, None, 5),  // GAME&WATCH
+    (0x86, None, 33), // DONKEYKONGLAND95
+    (0x9A, None, 25), // ASTEROIDS/MISCMD
+    (0x52, None, 42), // STREET FIGHTER 2
+    (0x01, None, 42), // DEFENDER/JOUST
+    (0x9D, None, 40), // KILLERINSTINCT95
+    (0x71, None, 2),  // TETRIS BLAST
+    (0x9C, None, 16), // PINOCCHIO
+    (0xBD, None, 25),
+    (0x5D, None, 42), // BA.TOSHINDEN
+    (0x6D, None, 42), // NETTOU KOF 95
+    (0x67, None, 5),
+    (0x3F, None, 0),  // TETRIS PLUS
+    (0x6B, None, 39), // DONKEYKONGLAND 3
+    // From here on checksums are shared, the 4th letter of the title has to match too.
+    (0xB3, Some(b'B'), 36),
+    (0x46, Some(b'E'), 22), // SUPER MARIOLAND
+    (0x28, Some(b'F'), 25), // GOLF
+    (0xA5, Some(b'A'), 6),
+    (0xC6, Some(b'A'), 32),
+    (0xD3, Some(b'R'), 12),
+    (0x27, Some(b'B'), 36),
+    (0x61, Some(b'E'), 11), // POKEMON BLUE
+    (0x18, Some(b'K'), 39), // DONKEY KONG LAND
+    (0x66, Some(b'E'), 18), // GAMEBOY GALLERY2
+    (0x6A, Some(b'K'), 39), // DONKEYKONGLAND 2
+    (0xBF, Some(b' '), 24), // KID ICARUS
+    (0x0D, Some(b'R'), 31), // TETRIS2
+    (0xF4, Some(b'-'), 50),
+    (0xB3, Some(b'U'), 17), // MOGURANYA
+    (0x46, Some(b'R'), 46), // METROID2
+    (0x28, Some(b'A'), 6),  // GALAGA&GALAXIAN
+    (0xA5, Some(b'R'), 27), // BT2RAGNAROKWORLD
+    (0xC6, Some(b' '), 0),  // KEN GRIFFEY JR
+    (0xD3, Some(b'I'), 47),
+    (0x27, Some(b'N'), 41), // MAGNETIC SOCCER
+    (0x61, Some(b'A'), 41), // VEGAS STAKES
+    (0x18, Some(b'I'), 0),
+    (0x66, Some(b'L'), 0),  // MILLI/CENTI/PEDE
+    (0x6A, Some(b'I'), 19), // MARIO & YOSHI
+    (0xBF, Some(b'C'), 34), // SOCCER
+    (0x0D, Some(b'E'), 23), // POKEBOM
+    (0xF4, Some(b' '), 18), // G&W GALLERY
+    (0xB3, Some(b'R'), 29), // TETRIS ATTACK
+];
+
+pub fn button_combo_names() -> std::vec::Vec<&'static str> {
+    return BUTTON_COMBOS.iter().map(|(name, _)| *name).collect();
+}
+
+// |combo| is written like "left+b".
+pub fn from_button_combo(combo: &str) -> Option<Palette> {
+    let combo = combo.to_lowercase();
+    return BUTTON_COMBOS
+        .iter()
+        .find(|(name, _)| *name == combo)
+        .map(|(_, palette)| *palette);
+}
+
+// Like the boot ROM reads them at the end of the logo: exactly one direction, and A or B but
+// not both. |pressed| has bit N set for joypad::Button N.
+pub fn from_buttons(pressed: u8) -> Option<Palette> {
+    let held = |button: Button| pressed & (1 << button as u8) != 0;
+    let directions = [
+        (Button::Up, "up"),
+        (Button::Left, "left"),
+        (Button::Down, "down"),
+        (Button::Right, "right"),
+    ];
+    let mut direction = None;
+    for (button, name) in directions {
+        if held(button) {
+            if direction.is_some() {
+                return None;
+            }
+            direction = Some(name);
+        }
+    }
+
+    let combo = match (direction?, held(Button::A), held(Button::B)) {
+        (_, true, true) => return None,
+        (direction, true, false) => format!("{}+a", direction),
+        (direction, false, true) => format!("{}+b", direction),
+        (direction, false, false) => direction.to_string(),
+    };
+    return from_button_combo(&combo);
+}
+
+// Only games published by Nintendo get a palette of their own.
+pub fn for_cartridge(cart: &Cartridge) -> Palette {
+    if !cart.licensed_by_nintendo() {
+        return DEFAULT;
+    }
+
+    let checksum = cart.title_checksum();
+    let fourth_letter = cart.data[0x0137];
+    for &(title_checksum, letter, combination_index) in TITLES.iter() {
+        if title_checksum == checksum && letter.is_none_or(|l| l == fourth_letter) {
+            return combination(combination_index);
+        }
+    }
+
+    return DEFAULT;
+}
+
+fn rgb_to_rgb555(color: u32) -> u16 {
+    let r = ((color >> 16) & 0xFF) as u16 >> 3;
+    let g = ((color >> 8) & 0xFF) as u16 >> 3;
+    let b = (color & 0xFF) as u16 >> 3;
+    return r | (g << 5) | (b << 10);
+}
+
+// DMG compatibility mode only ever uses BG palette 0 and object palettes 0 and 1.
+pub fn apply(memory: &mut Memory, palette: Palette) {
+    let convert = |colors: [u32; 4]| {
+        let mut ret = [0u16; 4];
+        for i in 0..4 {
+            ret[i] = rgb_to_rgb555(colors[i]);
+        }
+        return ret;
+    };
+
+    memory.initialize_palette(false, 0, convert(palette.bg));
+    memory.initialize_palette(true, 0, convert(palette.obj0));
+    memory.initialize_palette(true, 1, convert(palette.obj1));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cartridge::Cartridge;
+    use crate::cartridge::CartridgeType;
+    use crate::compat_palette;
+
+    fn cartridge(title: &str, licensee: u8) -> Cartridge {
+        let mut data = vec![0; 0x8000];
+        data[0x0134..0x0134 + title.len()].copy_from_slice(title.as_bytes());
+        data[0x014B] = licensee;
+        return Cartridge {
+            title: title.to_string(),
+            cartridge_type: CartridgeType::RomOnly,
+            data: data,
+        };
+    }
+
+    // Colors as Pan Docs lists them. Those round a little differently from rgb555_to_rgb, what
+    // has to match is what ends up in palette RAM.
+    fn assert_colors(expected: [u32; 4], actual: [u32; 4]) {
+        assert_eq!(
+            expected.map(compat_palette::rgb_to_rgb555),
+            actual.map(compat_palette::rgb_to_rgb555)
+        );
+    }
+
+    #[test]
+    fn title_lookup() {
+        let tetris = compat_palette::for_cartridge(&cartridge("TETRIS", 0x01));
+        assert_colors([0xFFFFFF, 0xFFFF00, 0xFF0000, 0x000000], tetris.bg);
+        assert_eq!(tetris.bg, tetris.obj0);
+        assert_eq!(tetris.bg, tetris.obj1);
+
+        let zelda = compat_palette::for_cartridge(&cartridge("ZELDA", 0x01));
+        assert_colors([0xFFFFFF, 0xFF8484, 0x943A3A, 0x000000], zelda.bg);
+        assert_colors([0xFFFFFF, 0x00FF00, 0x318400, 0x004A00], zelda.obj0);
+        assert_colors([0xFFFFFF, 0x63A5FF, 0x0000FF, 0x000000], zelda.obj1);
+
+        let dr_mario = compat_palette::for_cartridge(&cartridge("DR.MARIO", 0x01));
+        assert_colors([0xFFFFFF, 0x63A5FF, 0x0000FF, 0x000000], dr_mario.bg);
+        assert_colors([0xFFFFFF, 0xFF8484, 0x943A3A, 0x000000], dr_mario.obj1);
+
+        // Shares its checksum with SUPER MARIOLAND, told apart by the 4th letter
+        let metroid = compat_palette::for_cartridge(&cartridge("METROID2", 0x01));
+        assert_colors([0xFFFFFF, 0x63A5FF, 0x0000FF, 0x000000], metroid.bg);
+        assert_colors([0xFFFF00, 0xFF0000, 0x630000, 0x000000], metroid.obj0);
+        assert_colors([0xFFFFFF, 0x7BFF31, 0x008400, 0x000000], metroid.obj1);
+
+        // Objects starting from the last color of one palette
+        let mario_land = compat_palette::for_cartridge(&cartridge("SUPER MARIOLAND", 0x01));
+        assert_colors([0x000000, 0xFFFFFF, 0xFF8484, 0x943A3A], mario_land.obj0);
+        assert_colors([0xB5B5FF, 0xFFFF94, 0xAD5A42, 0x000000], mario_land.bg);
+
+        let red = compat_palette::for_cartridge(&cartridge("POKEMON RED", 0x01));
+        assert_colors([0xFFFFFF, 0xFF8484, 0x943A3A, 0x000000], red.bg);
+        let blue = compat_palette::for_cartridge(&cartridge("POKEMON BLUE", 0x01));
+        let vegas = compat_palette::for_cartridge(&cartridge("VEGAS STAKES", 0x01));
+        assert_colors([0xFFFFFF, 0x63A5FF, 0x0000FF, 0x000000], blue.bg);
+        assert_colors([0xFFFFFF, 0x7BFF31, 0x008400, 0x000000], vegas.bg);
+
+        // Right checksum, wrong 4th letter
+        let unknown = compat_palette::for_cartridge(&cartridge("SUEPR MARIOLAND", 0x01));
+        assert_eq!(compat_palette::DEFAULT, unknown);
+
+        let unlicensed = compat_palette::for_cartridge(&cartridge("ZELDA", 0x00));
+        assert_eq!(compat_palette::DEFAULT, unlicensed);
+    }
+
+    #[test]
+    fn button_combos() {
+        assert_eq!(12, compat_palette::button_combo_names().len());
+        assert_eq!(
+            Some(compat_palette::DEFAULT),
+            compat_palette::from_button_combo("Right+A")
+        );
+        assert_eq!(None, compat_palette::from_button_combo("a+b"));
+        let up_b = compat_palette::from_button_combo("up+b").unwrap();
+        assert_colors([0xFFE6C5, 0xCE9C84, 0x846B29, 0x5A3108], up_b.bg);
+        assert_colors([0xFFFFFF, 0xFFAD63, 0x843100, 0x000000], up_b.obj0);
+
+        // Right + A, then Left + B
+        assert_eq!(
+            Some(compat_palette::DEFAULT),
+            compat_palette::from_buttons(0b0010_0001)
+        );
+        assert_eq!(
+            compat_palette::from_button_combo("left+b"),
+            compat_palette::from_buttons(0b1000_0010)
+        );
+        // No direction, two directions, A and B together
+        assert_eq!(None, compat_palette::from_buttons(0b0000_0001));
+        assert_eq!(None, compat_palette::from_buttons(0b1001_0000));
+        assert_eq!(None, compat_palette::from_buttons(0b0001_0011));
+    }
+}
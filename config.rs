@@ -0,0 +1,79 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+// The config file holds command line options, one per line and without the leading "--":
+//
+//   # Green background, red sprites
+//   bg-palette = pea-green
+//   obp0-palette = #FFFFFF,FF8484,943A3A,000000
+//
+// Options given on the command line win over the ones from the file.
+pub fn default_path() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("XDG_CONFIG_HOME") {
+        return Some(PathBuf::from(dir).join("yagbe").join("config"));
+    }
+
+    return env::var_os("HOME").map(|home| {
+        PathBuf::from(home)
+            .join(".config")
+            .join("yagbe")
+            .join("config")
+    });
+}
+
+// Turns the file into the equivalent command line arguments.
+pub fn load(path: &Path) -> Result<std::vec::Vec<String>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    return Ok(parse(&contents));
+}
+
+fn parse(contents: &str) -> std::vec::Vec<String> {
+    let mut args = vec![];
+
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        match line.find('=') {
+            Some(eq) => {
+                args.push(format!("--{}", line[..eq].trim()));
+                args.push(line[eq + 1..].trim().to_string());
+            }
+            None => {
+                args.push(format!("--{}", line));
+            }
+        }
+    }
+
+    return args;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config;
+
+    #[test]
+    fn parse() {
+        let args = config::parse(
+            "# comment\n\
+             palette = pocket\n\
+             \n\
+             obp0-palette=#FFFFFF, FF8484, 943A3A, 000000\n\
+             no-access-restrictions\n",
+        );
+        assert_eq!(
+            vec![
+                "--palette",
+                "pocket",
+                "--obp0-palette",
+                "#FFFFFF, FF8484, 943A3A, 000000",
+                "--no-access-restrictions"
+            ],
+            args
+        );
+    }
+}
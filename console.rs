@@ -1,90 +1,294 @@
+use crate::boot;
+use crate::breakpoint;
+use crate::breakpoint::Breakpoint;
+use crate::breakpoint::Condition;
+use crate::call_stack::Frame;
 use crate::cartridge::Cartridge;
+use crate::compat_palette;
 use crate::cpu::Cpu;
 use crate::debug::Debuggable;
+use crate::debug::Stop;
+use crate::debug::StopReason;
+use crate::disasm;
+use crate::disasm::Instruction;
 use crate::display::Display;
 use crate::joypad::Joypad;
 use crate::memory::Memory;
+use crate::model::Model;
+use crate::palette;
+use crate::palette::Palette;
+use crate::png;
 use crate::ppu::Ppu;
+use crate::recorder::Recorder;
 use crate::registers::Registers;
+use crate::sgb;
+use crate::sgb::Sgb;
+use crate::symbols::Symbols;
+use crate::trace::Trace;
+use crate::utils;
+use crate::viewer::View;
+use crate::viewer::Viewer;
+use crate::watchpoint::Watchpoint;
 
 use std::collections::HashSet;
+use std::fs;
 use std::path::Path;
+use std::path::PathBuf;
 use std::sync::mpsc;
 use std::thread;
 use std::time::Duration;
+use std::time::SystemTime;
 
 use sdl2::event::Event;
+use sdl2::event::WindowEvent;
 use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
+use sdl2::mouse::MouseButton;
 
 #[derive(PartialEq)]
 enum DebugState {
     Running,
     Stopped,
     Stepping,
+    // Running until PC gets to this address, for run to cursor.
+    RunningTo(u16),
+    // Running until PC gets to the address after a CALL or RST with SP back where it was
+    // before it. A recursive call comes back to the same address but with SP lower.
+    SteppingOver(u16, u16),
+    // Running until a return pops the return address from this slot on the stack.
+    SteppingOut(u16),
+    // Running until LY changes, or gets to this line.
+    RunningToLine(Option<u8>),
+    // How many are left to run and how many there were to begin with.
+    RunningInstructions(u64, u64),
+    RunningCycles(u64, u64),
+}
+
+// About as long as the CGB boot logo stays up. Without a boot ROM, holding a button combination
+// during this many frames picks the compatibility palette like it would during the logo.
+const BOOT_LOGO_FRAMES: u64 = 120;
+
+// RET, RETI and the conditional RETs
+fn is_return(opcode: u8) -> bool {
+    return matches!(opcode, 0xC9 | 0xD9 | 0xC0 | 0xC8 | 0xD0 | 0xD8);
 }
 
 pub enum ConsoleSignal {
     Quit,
 }
 
+pub struct ConsoleOptions {
+    pub debugged: bool,
+    pub ppu_access_restrictions: bool,
+    pub boot_rom: Option<Vec<u8>>,
+    // Boot ROMs named after the model are picked from here when boot_rom isn't set.
+    pub boot_rom_dir: Option<PathBuf>,
+    // Picked from the cartridge header when not set, by whoever creates the console.
+    pub model: Option<Model>,
+    // Colors for DMG games on a CGB. Picked from the title like the boot ROM does when not set,
+    // and ignored when an actual boot ROM runs.
+    pub compat_palette: Option<Palette>,
+    // DMG shades, with a separate palette for BG, OBP0 and OBP1.
+    pub palette: Palette,
+    // Screenshots taken with the hotkey also get the raw color indices saved next to them.
+    pub raw_screenshots: bool,
+    // No windows and no input, for running in CI.
+    pub headless: bool,
+    // Quits after this many frames when set.
+    pub frames: Option<u64>,
+    pub recorder: Option<Recorder>,
+    // Debug with the full-screen terminal UI instead of the prompt.
+    pub tui: bool,
+    // Debug from gdb, connecting on this port.
+    pub gdb_port: Option<u16>,
+    // Labels for the debugger's output
+    pub symbols: Symbols,
+    pub trace: Option<Trace>,
+}
+
+impl ConsoleOptions {
+    pub fn new() -> ConsoleOptions {
+        return ConsoleOptions {
+            debugged: false,
+            ppu_access_restrictions: true,
+            boot_rom: None,
+            boot_rom_dir: None,
+            model: None,
+            compat_palette: None,
+            palette: Palette::default(),
+            raw_screenshots: false,
+            headless: false,
+            frames: None,
+            recorder: None,
+            tui: false,
+            gdb_port: None,
+            symbols: Symbols::default(),
+            trace: None,
+        };
+    }
+}
+
+// Everything SDL, which isn't there when running headless.
+struct Frontend {
+    main_display: Display,
+    viewer: Viewer,
+    event_pump: sdl2::EventPump,
+}
+
+impl Frontend {
+    fn new(sgb: bool) -> Frontend {
+        let sdl_context = sdl2::init().unwrap();
+        let video_subsystem = sdl_context.video().unwrap();
+        // The SGB picture has room for the border around the Game Boy screen
+        let main_display = if sgb {
+            Display::new(
+                &video_subsystem,
+                "Main",
+                sgb::SCREEN_WIDTH * 2,
+                sgb::SCREEN_HEIGHT * 2,
+                2.0,
+                2.0,
+            )
+        } else {
+            Display::new(&video_subsystem, "Main", 320, 288, 2.0, 2.0)
+        };
+        let event_pump = sdl_context.event_pump().expect("start event_pump");
+
+        return Frontend {
+            main_display: main_display,
+            viewer: Viewer::new(video_subsystem),
+            event_pump: event_pump,
+        };
+    }
+}
+
 pub struct Console {
     memory: Memory,
     cpu: Cpu,
     ppu: Ppu,
     joypad: Joypad,
+    sgb: Option<Sgb>,
 
-    main_display: Display,
-    tilemap_display: Display,
-
-    event_pump: sdl2::EventPump,
+    frontend: Option<Frontend>,
+    recorder: Option<Recorder>,
 
     instr_breakpoints: HashSet<u8>,
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    next_breakpoint_id: u32,
+    // Set when resuming, so whatever we stopped on doesn't stop us again right away.
+    skip_breakpoints: bool,
     debug_state: DebugState,
+    // Why it last stopped, until the debugger gets told.
+    stop: Option<Stop>,
+    // Opcode of the last instruction that started and SP before it, to tell when step out is
+    // done.
+    previous_instruction: Option<(u8, u16)>,
+    // Set by the debugger to stop the emulator.
+    quit: bool,
+    // CPU cycles of the current dot that already ran, when it stopped halfway through one in
+    // double speed.
+    cpu_cycles_done: u8,
+    symbols: Symbols,
 
     tx: mpsc::Sender<ConsoleSignal>,
 
     current_timer_tick: u64,
     current_div_tick: u64,
+
+    raw_screenshots: bool,
+    frames: u64,
+    max_frames: Option<u64>,
+    // Set until a button combination picks the compatibility palette, or it's too late to.
+    compat_palette_from_buttons: bool,
 }
 
 impl Console {
-    pub fn new(cart_path: &Path, tx: mpsc::Sender<ConsoleSignal>, debugged: bool) -> Console {
-        let cart = Cartridge::load(cart_path);
-        let sdl_context = sdl2::init().unwrap();
-        let video_subsystem = sdl_context.video().unwrap();
-        let tilemap_display = Display::new(&video_subsystem, "Tilemap", 256, 384, 2.0, 2.0);
-        let main_display = Display::new(&video_subsystem, "Main", 320, 288, 2.0, 2.0);
-        let event_pump = sdl_context.event_pump().expect("start event_pump");
-        let mut mem = Memory::new(&cart.data);
-        mem.initialize(0xFF0F, 0xE1); // Interrupt request
-        mem.initialize(0xFFFF, 0x00); // Interrupt mask
-        mem.initialize(0xFF00, 0xFF); // joypad
-        mem.initialize(0xFF40, 0x91); // LCDC
-        mem.initialize(0xFF47, 0xFC); // BGP
+    pub fn new(
+        cart: Cartridge,
+        model: Model,
+        tx: mpsc::Sender<ConsoleSignal>,
+        options: ConsoleOptions,
+    ) -> Console {
+        let sgb = if model.is_sgb() && cart.supports_sgb() {
+            Some(Sgb::new())
+        } else {
+            None
+        };
+        let frontend = if options.headless {
+            None
+        } else {
+            Some(Frontend::new(sgb.is_some()))
+        };
+        let mut mem = Memory::new(&cart.data, model);
+        mem.set_ppu_access_restrictions(options.ppu_access_restrictions);
+
+        // Without a boot ROM, start at 0x0100 with whatever state it would have left behind.
+        let div_phase = match options.boot_rom {
+            Some(_) => 0,
+            None => boot::post_boot_div_phase(model),
+        };
+        let compat_palette_from_buttons =
+            mem.cgb_compat_mode() && options.boot_rom.is_none() && options.compat_palette.is_none();
+        let mut cpu = match options.boot_rom {
+            Some(boot_rom) => {
+                mem.load_boot_rom(boot_rom);
+                Cpu::at_power_on()
+            }
+            None => {
+                boot::apply_post_boot_state(&mut mem, model);
+                if mem.cgb_compat_mode() {
+                    let palette = options
+                        .compat_palette
+                        .unwrap_or_else(|| compat_palette::for_cartridge(&cart));
+                    compat_palette::apply(&mut mem, palette);
+                }
+                Cpu::from_registers(boot::post_boot_registers(model, &cart))
+            }
+        };
+
+        cpu.trace = options.trace;
 
         return Console {
             memory: mem,
-            cpu: Cpu::new(),
-            ppu: Ppu::new(),
-            joypad: Joypad::new(),
+            cpu: cpu,
+            ppu: Ppu::new(options.palette),
+            joypad: Joypad::new(sgb.is_some()),
+            sgb: sgb,
             tx: tx,
-            main_display: main_display,
-            tilemap_display: tilemap_display,
-            event_pump: event_pump,
+            frontend: frontend,
+            recorder: options.recorder,
             instr_breakpoints: HashSet::new(),
-            debug_state: if debugged {
+            breakpoints: vec![],
+            watchpoints: vec![],
+            next_breakpoint_id: 1,
+            skip_breakpoints: false,
+            stop: None,
+            previous_instruction: None,
+            quit: false,
+            cpu_cycles_done: 0,
+            symbols: options.symbols,
+            debug_state: if options.debugged {
                 DebugState::Stopped
             } else {
                 DebugState::Running
             },
-            current_timer_tick: 0,
-            current_div_tick: 0,
+            current_timer_tick: div_phase as u64,
+            current_div_tick: div_phase as u64,
+            raw_screenshots: options.raw_screenshots,
+            frames: 0,
+            max_frames: options.frames,
+            compat_palette_from_buttons: compat_palette_from_buttons,
         };
     }
 
     pub fn check_for_input(&mut self) -> bool {
-        for event in self.event_pump.poll_iter() {
+        let frontend = match self.frontend.as_mut() {
+            Some(frontend) => frontend,
+            None => return true,
+        };
+
+        let mut take_screenshot = false;
+        for event in frontend.event_pump.poll_iter() {
             match event {
                 Event::Quit { .. }
                 | Event::KeyDown {
@@ -96,6 +300,75 @@ impl Console {
                         .expect("sending quit signal");
                     return false;
                 }
+                // Closing a debug view only closes it, closing the main window quits.
+                Event::Window {
+                    window_id,
+                    win_event: WindowEvent::Close,
+                    ..
+                } if !frontend.viewer.close(window_id) => {
+                    self.tx
+                        .send(ConsoleSignal::Quit)
+                        .expect("sending quit signal");
+                    return false;
+                }
+                Event::MouseButtonDown {
+                    window_id,
+                    mouse_btn: MouseButton::Left,
+                    x,
+                    y,
+                    ..
+                } => {
+                    frontend.viewer.click(window_id, x, y, &self.memory);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F1),
+                    ..
+                } => {
+                    frontend.viewer.toggle(View::BgMaps);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F2),
+                    ..
+                } => {
+                    frontend.viewer.toggle(View::Tiles);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F3),
+                    ..
+                } => {
+                    frontend.viewer.toggle(View::Oam);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F4),
+                    ..
+                } => {
+                    frontend.viewer.toggle(View::Palettes);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::P),
+                    ..
+                } => {
+                    // Cycle through the palette presets, starting from the one in use. Layers
+                    // given colors of their own (--obp0-palette etc.) keep them.
+                    let current = self.ppu.palette.bg;
+                    let next = palette::PRESETS
+                        .iter()
+                        .position(|(_, colors)| *colors == current)
+                        .map_or(0, |i| (i + 1) % palette::PRESETS.len());
+                    let colors = palette::PRESETS[next].1;
+                    let palette = &mut self.ppu.palette;
+                    for layer in [&mut palette.bg, &mut palette.obj0, &mut palette.obj1] {
+                        if *layer == current {
+                            *layer = colors;
+                        }
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    ..
+                } => {
+                    take_screenshot = true;
+                }
                 Event::KeyDown {
                     keycode: Some(code),
                     ..
@@ -112,12 +385,227 @@ impl Console {
             }
         }
 
+        if take_screenshot {
+            self.save_screenshot();
+        }
+
         return true;
     }
 
+    // Hotkey screenshots go in the working directory, named after the current time.
+    fn save_screenshot(&self) {
+        let secs = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        // Several in the same second get numbered instead of overwriting each other.
+        let stamp = format!("yagbe-{}", utils::file_timestamp(secs));
+        let mut name = stamp.clone();
+        let mut n = 1;
+        while Path::new(&format!("{}.png", name)).exists() {
+            n += 1;
+            name = format!("{}-{}", stamp, n);
+        }
+
+        let mut paths = vec![(format!("{}.png", name), false)];
+        if self.raw_screenshots {
+            paths.push((format!("{}-raw.png", name), true));
+        }
+        for (path, raw) in paths {
+            match self.screenshot(Path::new(&path), raw) {
+                Ok(()) => println!("Saved {}", path),
+                Err(e) => println!("Screenshot failed: {}", e),
+            }
+        }
+    }
+
+    // Saves the last frame, without any SGB border. With |raw| the PNG holds the color indices
+    // instead, which don't change with the palette and are easier to compare in tests.
+    pub fn screenshot(&self, path: &Path, raw: bool) -> Result<(), String> {
+        let data = if raw {
+            png::encode_gray2(160, 144, self.ppu.frame_indices())
+        } else {
+            png::encode_rgb(160, 144, self.ppu.frame())
+        };
+        return fs::write(path, data).map_err(|e| format!("{}: {}", path.display(), e));
+    }
+
+    // Only checked when an instruction is about to start, so each breakpoint gets looked at once
+    // per instruction instead of once per dot. Also where running to an address and stepping
+    // out stop.
+    fn breakpoint_hit(&mut self) -> Option<StopReason> {
+        let pc = self.cpu.next_instruction(&self.memory)?;
+        let previous_instruction = self
+            .previous_instruction
+            .replace((self.memory[pc], self.cpu.registers.sp));
+        if self.skip_breakpoints {
+            self.skip_breakpoints = false;
+            return None;
+        }
+
+        if self.instr_breakpoints.contains(&self.memory[pc]) {
+            println!(
+                "Instruction breakpoint {:02X} hit at {:04X}",
+                self.memory[pc], pc
+            );
+            return Some(StopReason::InstructionBreakpoint(self.memory[pc]));
+        }
+
+        let bank = self.memory.rom_bank(pc);
+        for breakpoint in self.breakpoints.iter_mut() {
+            if breakpoint.check(pc, bank, &self.cpu.registers, &self.memory) {
+                println!(
+                    "Breakpoint {} hit at {}{}",
+                    breakpoint.id,
+                    breakpoint::format_location(pc, bank),
+                    self.symbols.suffix(pc, bank)
+                );
+                return Some(StopReason::Breakpoint(breakpoint.id));
+            }
+        }
+
+        return match self.debug_state {
+            DebugState::RunningTo(addr) if pc == addr => Some(StopReason::Reached),
+            DebugState::SteppingOver(addr, sp) if pc == addr && self.cpu.registers.sp >= sp => {
+                Some(StopReason::Reached)
+            }
+            // Only a return that was taken moved SP off the slot. A RET cc that wasn't, or one
+            // further down the stack, leaves it alone.
+            DebugState::SteppingOut(slot)
+                if previous_instruction.is_some_and(|(opcode, sp)| {
+                    is_return(opcode) && sp == slot && self.cpu.registers.sp == slot.wrapping_add(2)
+                }) =>
+            {
+                Some(StopReason::StepOut)
+            }
+            _ => None,
+        };
+    }
+
+    // Looks at what the instruction at |pc| just read and wrote. Execution stops right after
+    // it, with the CPU state showing what it did. All the hits get printed, the first one is
+    // what it stopped for.
+    fn watchpoint_hit(&mut self, pc: u16) -> Option<StopReason> {
+        if self.watchpoints.is_empty() {
+            return None;
+        }
+
+        let mut hit = None;
+        let bank = self.memory.rom_bank(pc);
+        for access in self.memory.take_accesses() {
+            for watchpoint in self.watchpoints.iter_mut() {
+                if watchpoint.check(&access, &self.cpu.registers, &self.memory) {
+                    println!(
+                        "Watchpoint {} hit: {}{} by the instruction at {:04X}{}",
+                        watchpoint.id,
+                        access.describe(),
+                        self.symbols
+                            .suffix(access.addr, self.memory.rom_bank(access.addr)),
+                        pc,
+                        self.symbols.suffix(pc, bank)
+                    );
+                    if hit.is_none() {
+                        hit = Some(StopReason::Watchpoint(watchpoint.id, access, pc));
+                    }
+                }
+            }
+        }
+        return hit;
+    }
+
+    // Counts down what the debugger asked to run for, after a dot where the CPU got |cycles|
+    // cycles and LY was |ly| before it.
+    fn update_run(&mut self, instr_run: bool, cycles: u64, ly: u8) {
+        match self.debug_state {
+            DebugState::Stepping if instr_run => self.stop(StopReason::Step),
+            DebugState::RunningInstructions(left, total) if instr_run => {
+                if left <= 1 {
+                    self.stop(StopReason::Instructions(total));
+                } else {
+                    self.debug_state = DebugState::RunningInstructions(left - 1, total);
+                }
+            }
+            DebugState::RunningCycles(left, total) => {
+                if left <= cycles {
+                    self.stop(StopReason::Cycles(total));
+                } else {
+                    self.debug_state = DebugState::RunningCycles(left - cycles, total);
+                }
+            }
+            DebugState::RunningToLine(line) => {
+                let new_ly = self.memory[0xFF44];
+                if new_ly != ly && line.is_none_or(|line| line == new_ly) {
+                    self.stop(StopReason::Line(new_ly));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn stop(&mut self, reason: StopReason) {
+        let pc = self.cpu.registers.pc;
+        self.debug_state = DebugState::Stopped;
+        self.stop = Some(Stop {
+            reason: reason,
+            pc: pc,
+            bank: self.memory.rom_bank(pc),
+        });
+    }
+
+    fn update_watched_ranges(&mut self) {
+        let ranges = self
+            .watchpoints
+            .iter()
+            .map(|w| (w.start, w.end, w.kind))
+            .collect();
+        self.memory.set_watched_ranges(ranges);
+    }
+
+    fn find_breakpoint(&mut self, id: u32) -> Option<&mut Breakpoint> {
+        let breakpoint = self.breakpoints.iter_mut().find(|b| b.id == id);
+        if breakpoint.is_none() {
+            println!("No breakpoint {}", id);
+        }
+        return breakpoint;
+    }
+
+    // Stands in for the boot ROM reading the joypad at the end of the logo.
+    fn pick_compat_palette(&mut self) {
+        if self.frames >= BOOT_LOGO_FRAMES {
+            self.compat_palette_from_buttons = false;
+            return;
+        }
+        if let Some(palette) = compat_palette::from_buttons(self.joypad.pressed()) {
+            compat_palette::apply(&mut self.memory, palette);
+            self.compat_palette_from_buttons = false;
+        }
+    }
+
+    // Shows the frame that was just drawn and records it.
+    fn end_frame(&mut self) {
+        let (width, height, pixels) = match &self.sgb {
+            Some(sgb) => (
+                sgb::SCREEN_WIDTH,
+                sgb::SCREEN_HEIGHT,
+                sgb.compose(self.ppu.frame()),
+            ),
+            None => (160, 144, self.ppu.frame().to_vec()),
+        };
+
+        if let Some(frontend) = self.frontend.as_mut() {
+            frontend.viewer.update(&self.memory, &self.ppu.palette);
+            frontend.main_display.draw_frame(width, height, &pixels);
+        }
+        if let Some(recorder) = self.recorder.as_mut() {
+            // A full disk shouldn't take the emulator down with it, what's written so far stays.
+            if let Err(e) = recorder.frame(width, height, &pixels, self.joypad.pressed()) {
+                println!("Recording failed, stopping it: {}", e);
+                self.recorder = None;
+            }
+        }
+    }
+
     fn update_timer_registers(&mut self) {
         // TODO: simultaneous TMA writes and TIMA overflows are well defined but not well implemented here, see pandocs
-        // TODO: any write to DIV resets it to 0
         let tac = self.memory[0xFF07];
         let timer_enabled = tac & 0b100 != 0;
         let clock_select = tac & 0b11;
@@ -132,20 +620,28 @@ impl Console {
             }
         };
 
+        // DIV and the timer share the same internal counter, so resetting one resets both.
+        if self.memory.take_div_reset() {
+            self.current_div_tick = 0;
+            self.current_timer_tick = 0;
+        }
+
         self.current_div_tick = (self.current_div_tick + 1) % 256;
         if self.current_div_tick == 0 {
-            // TODO: This is reset when executing a stop instruction
-            self.memory.set(0xFF04, self.memory[0xFF04].wrapping_add(1));
+            self.memory
+                .set_internal(0xFF04, self.memory[0xFF04].wrapping_add(1));
         }
 
         if timer_enabled {
             self.current_timer_tick = (self.current_timer_tick + 1) % divider;
             if self.current_timer_tick == 0 {
                 if self.memory[0xFF05] == 0xFF {
-                    self.memory.set(0xFF05, self.memory[0xFF06]);
-                    self.memory.set(0xFF0F, self.memory[0xFF0F] | 0b100);
+                    self.memory.set_internal(0xFF05, self.memory[0xFF06]);
+                    self.memory
+                        .set_internal(0xFF0F, self.memory[0xFF0F] | 0b100);
                 } else {
-                    self.memory.set(0xFF05, self.memory[0xFF05].wrapping_add(1));
+                    self.memory
+                        .set_internal(0xFF05, self.memory[0xFF05].wrapping_add(1));
                 }
             }
         }
@@ -153,29 +649,67 @@ impl Console {
 
     // Returning true here means "keep console alive". False will kill it.
     pub fn tick(&mut self) -> bool {
+        if self.quit {
+            return false;
+        }
         if self.debug_state == DebugState::Stopped {
             if !self.check_for_input() {
                 return false;
             } // TODO: might not be correct when input is supported, but still need to poll at least for the "quit" event.
-            thread::sleep(Duration::from_millis(100));
-            return true;
-        } else if self.debug_state == DebugState::Running
-            && self
-                .instr_breakpoints
-                .contains(&self.memory[self.cpu.registers.pc])
-        {
-            self.debug_state = DebugState::Stopped;
+
+            // Short enough that debuggers asking for one thing after the other don't crawl.
+            thread::sleep(Duration::from_millis(10));
             return true;
         }
 
-        self.update_timer_registers();
-        self.memory.tick();
-        self.joypad.tick(&mut self.memory);
-        let instr_run = self.cpu.tick(&mut self.memory, true);
-        let has_frame = self.ppu.tick(&mut self.memory, &mut self.main_display);
+        // Each tick is one dot, which is what the PPU runs on. In double speed the CPU and
+        // everything clocked with it (timer, DIV, OAM DMA) gets two cycles for every dot.
+        let cpu_cycles = if self.memory.double_speed() { 2 } else { 1 };
+        let first_cycle = self.cpu_cycles_done;
+        if first_cycle == 0 {
+            if let Some(command) = self.joypad.tick(&mut self.memory) {
+                if let Some(sgb) = self.sgb.as_mut() {
+                    sgb.handle_command(&command, &self.memory);
+                }
+            }
+        }
+        let mut instr_run = false;
+        let mut watchpoint_hit = None;
+        let ly = self.memory[0xFF44];
+        // Instructions can start on either CPU cycle of a dot, so breakpoints get checked before
+        // each one. Stopping in the middle of a dot leaves the rest of it for when it resumes.
+        for cycle in first_cycle..cpu_cycles {
+            if self.debug_state != DebugState::Stepping {
+                if let Some(reason) = self.breakpoint_hit() {
+                    self.cpu_cycles_done = cycle;
+                    self.stop(reason);
+                    return true;
+                }
+            }
+
+            self.update_timer_registers();
+            self.memory.tick();
+            let pc = self
+                .cpu
+                .next_instruction(&self.memory)
+                .unwrap_or(self.cpu.registers.pc);
+            instr_run |= self.cpu.tick(&mut self.memory, true);
+            if let Some(reason) = self.watchpoint_hit(pc) {
+                if cycle + 1 < cpu_cycles {
+                    self.cpu_cycles_done = cycle + 1;
+                    self.stop(reason);
+                    return true;
+                }
+                watchpoint_hit = Some(reason);
+            }
+        }
+        self.cpu_cycles_done = 0;
+        let has_frame = self.ppu.tick(&mut self.memory, self.sgb.as_ref());
 
-        if self.debug_state == DebugState::Stepping && instr_run {
-            self.debug_state = DebugState::Stopped;
+        if let Some(reason) = watchpoint_hit {
+            self.stop(reason);
+        } else {
+            self.update_run(instr_run, (cpu_cycles - first_cycle) as u64, ly);
         }
 
         if has_frame {
@@ -183,58 +717,22 @@ impl Console {
                 return false;
             } // TODO: does joypad poll more often? Probably.
 
-            for i in (0x8000..0x97FF).step_by(16) {
-                let tile_index = (i - 0x8000) / 16;
-                let tile_row = tile_index / 16;
-                let tile_col = tile_index % 16;
-
-                for b in (i..(i + 16)).step_by(2) {
-                    let row = (b - i) / 2;
-                    let lsb = self.memory[b];
-                    let msb = self.memory[b + 1];
-
-                    for j in 0..8 {
-                        let bit_mask = 0b10000000 >> j;
-                        let pix =
-                            (((msb & bit_mask) >> (7 - j)) << 1) | ((lsb & bit_mask) >> (7 - j));
-
-                        let p: sdl2::rect::Point = sdl2::rect::Point::new(
-                            (tile_col * 8 + j).into(),
-                            (tile_row * 8 + row).into(),
-                        );
-                        match pix {
-                            0b00 => {
-                                self.tilemap_display
-                                    .c
-                                    .set_draw_color(Color::RGB(0xFF, 0xFF, 0xFF));
-                                self.tilemap_display.c.draw_point(p).unwrap();
-                            }
-                            0b01 => {
-                                self.tilemap_display
-                                    .c
-                                    .set_draw_color(Color::RGB(0xAA, 0xAA, 0xAA));
-                                self.tilemap_display.c.draw_point(p).unwrap();
-                            }
-                            0b10 => {
-                                self.tilemap_display
-                                    .c
-                                    .set_draw_color(Color::RGB(0x55, 0x55, 0x55));
-                                self.tilemap_display.c.draw_point(p).unwrap();
-                            }
-                            0b11 => {
-                                self.tilemap_display
-                                    .c
-                                    .set_draw_color(Color::RGB(0x00, 0x00, 0x00));
-                                self.tilemap_display.c.draw_point(p).unwrap();
-                            }
-                            _ => unimplemented!(),
-                        };
-                    }
-                }
+            self.end_frame();
+            if let Some(trace) = self.cpu.trace.as_mut() {
+                trace.end_frame();
             }
 
-            self.tilemap_display.present();
-            self.main_display.present();
+            if self.compat_palette_from_buttons {
+                self.pick_compat_palette();
+            }
+
+            self.frames += 1;
+            if self.max_frames == Some(self.frames) {
+                self.tx
+                    .send(ConsoleSignal::Quit)
+                    .expect("sending quit signal");
+                return false;
+            }
         }
 
         return true;
@@ -242,23 +740,108 @@ impl Console {
 }
 
 impl Debuggable for Console {
+    // While running, this stops after the next instruction.
     fn step(&mut self) {
-        match self.debug_state {
-            DebugState::Stopped => {
-                self.debug_state = DebugState::Stepping;
-            }
-            _ => {}
-        }
+        self.debug_state = DebugState::Stepping;
     }
 
     fn resume(&mut self) {
         self.debug_state = DebugState::Running;
+        self.skip_breakpoints = true;
+    }
+
+    fn pause(&mut self) {
+        if self.debug_state != DebugState::Stopped {
+            self.stop(StopReason::Paused);
+        }
+    }
+
+    fn run_to(&mut self, addr: u16) {
+        self.debug_state = DebugState::RunningTo(addr);
+        self.skip_breakpoints = true;
+    }
+
+    fn step_over(&mut self) {
+        let pc = self.cpu.registers.pc;
+        // CALL, the conditional CALLs and the RSTs
+        let len = match self.memory[pc] {
+            0xCD | 0xC4 | 0xCC | 0xD4 | 0xDC => 3,
+            0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => 1,
+            _ => {
+                self.step();
+                return;
+            }
+        };
+        self.debug_state = DebugState::SteppingOver(pc.wrapping_add(len), self.cpu.registers.sp);
+        self.skip_breakpoints = true;
+    }
+
+    fn step_out(&mut self) {
+        // Where the current function's return address is, which is at SP when the call stack
+        // doesn't know about it.
+        let slot = match self.cpu.call_stack.frames().last() {
+            Some(frame) => frame.sp,
+            None => self.cpu.registers.sp,
+        };
+        self.debug_state = DebugState::SteppingOut(slot);
+        self.skip_breakpoints = true;
+        self.previous_instruction = None;
+    }
+
+    fn run_to_line(&mut self, line: Option<u8>) {
+        self.debug_state = DebugState::RunningToLine(line);
+        self.skip_breakpoints = true;
+    }
+
+    fn run_instructions(&mut self, count: u64) {
+        self.debug_state = DebugState::RunningInstructions(count, count);
+        self.skip_breakpoints = true;
+    }
+
+    fn run_cycles(&mut self, count: u64) {
+        self.debug_state = DebugState::RunningCycles(count, count);
+        self.skip_breakpoints = true;
+    }
+
+    fn take_stop(&mut self) -> Option<Stop> {
+        return self.stop.take();
+    }
+
+    fn quit(&mut self) {
+        self.quit = true;
+    }
+
+    fn request_running(&mut self) -> Option<bool> {
+        return Some(self.debug_state != DebugState::Stopped);
+    }
+
+    fn request_memory(&mut self, addr: u16, len: usize) -> Option<Vec<u8>> {
+        let bytes = (0..len)
+            .map(|i| self.memory[addr.wrapping_add(i as u16)])
+            .collect();
+        return Some(bytes);
+    }
+
+    fn write_memory(&mut self, addr: u16, bytes: Vec<u8>) {
+        for (i, byte) in bytes.into_iter().enumerate() {
+            self.memory.poke(addr.wrapping_add(i as u16), byte);
+        }
     }
 
     fn request_registers(&mut self) -> Option<Registers> {
         return Some(self.cpu.registers);
     }
 
+    fn set_registers(&mut self, registers: Registers) {
+        self.cpu.registers = registers;
+    }
+
+    fn request_call_stack(&mut self) -> Option<(u16, Option<usize>, Vec<Frame>)> {
+        let pc = self.cpu.registers.pc;
+        let frames = self.cpu.call_stack.frames().to_vec();
+        return Some((pc, self.memory.rom_bank(pc), frames));
+    }
+
     fn request_next_instruction(&mut self) -> Option<[u8; 3]> {
         let mut ret: [u8; 3] = [0; 3];
         let pc = self.cpu.registers.pc;
@@ -268,7 +851,155 @@ impl Debuggable for Console {
         return Some(ret);
     }
 
+    fn request_disassembly(
+        &mut self,
+        addr: Option<u16>,
+        count: usize,
+    ) -> Option<(u16, Vec<Instruction>)> {
+        let read = |addr: u16| self.memory[addr];
+        let labels = |addr: u16| {
+            self.symbols
+                .lookup(addr, self.memory.rom_bank(addr))
+                .map(|(name, offset)| (name.to_string(), offset))
+        };
+        let pc = self.cpu.registers.pc;
+        let instructions = match addr {
+            Some(addr) => disasm::decode_range(addr, count, &read, &labels),
+            None => disasm::decode_around(pc, 4, count, &read, &labels),
+        };
+        return Some((pc, instructions));
+    }
+
     fn set_breakpoint_on_instr(&mut self, instr: u8) {
         self.instr_breakpoints.insert(instr);
     }
+
+    fn add_breakpoint(&mut self, mut breakpoint: Breakpoint) {
+        breakpoint.id = self.next_breakpoint_id;
+        self.next_breakpoint_id += 1;
+        println!("Added {}", breakpoint.describe());
+        self.breakpoints.push(breakpoint);
+    }
+
+    fn remove_breakpoint(&mut self, id: u32) {
+        if self.watchpoints.iter().any(|w| w.id == id) {
+            self.watchpoints.retain(|w| w.id != id);
+            self.update_watched_ranges();
+        } else if self.find_breakpoint(id).is_some() {
+            self.breakpoints.retain(|b| b.id != id);
+        }
+    }
+
+    fn set_breakpoint_condition(&mut self, id: u32, condition: Option<Condition>) {
+        if let Some(watchpoint) = self.watchpoints.iter_mut().find(|w| w.id == id) {
+            watchpoint.condition = condition;
+        } else if let Some(breakpoint) = self.find_breakpoint(id) {
+            breakpoint.condition = condition;
+        }
+    }
+
+    fn set_breakpoint_ignore_count(&mut self, id: u32, count: u32) {
+        if let Some(watchpoint) = self.watchpoints.iter_mut().find(|w| w.id == id) {
+            watchpoint.ignore_count = count;
+        } else if let Some(breakpoint) = self.find_breakpoint(id) {
+            breakpoint.ignore_count = count;
+        }
+    }
+
+    fn request_breakpoints(&mut self) -> Option<Vec<Breakpoint>> {
+        return Some(self.breakpoints.clone());
+    }
+
+    fn add_watchpoint(&mut self, mut watchpoint: Watchpoint) {
+        watchpoint.id = self.next_breakpoint_id;
+        self.next_breakpoint_id += 1;
+        println!("Added {}", watchpoint.describe());
+        self.watchpoints.push(watchpoint);
+        self.update_watched_ranges();
+    }
+
+    fn request_watchpoints(&mut self) -> Option<Vec<Watchpoint>> {
+        return Some(self.watchpoints.clone());
+    }
+
+    fn dump_memory(
+        &mut self,
+        addr: u16,
+        bank: Option<usize>,
+        len: usize,
+    ) -> Option<Result<Vec<u8>, String>> {
+        // Stops at the end of memory, or of the bank, instead of wrapping around.
+        let bank = match bank {
+            Some(bank) => bank,
+            None => {
+                let len = len.min(0x10000 - addr as usize);
+                return Some(Ok(self.request_memory(addr, len).unwrap()));
+            }
+        };
+        let len = len.min(Memory::bank_end(addr) - addr as usize);
+        let bytes: Option<Vec<u8>> = (0..len)
+            .map(|i| self.memory.peek_bank(addr.wrapping_add(i as u16), bank))
+            .collect();
+        return Some(bytes.ok_or(format!(
+            "No bank {:02X} covering {:04X}-{:04X}",
+            bank,
+            addr,
+            addr.wrapping_add(len as u16).wrapping_sub(1)
+        )));
+    }
+
+    fn set_register(&mut self, name: String, value: u16) {
+        if let Err(e) = self.cpu.registers.write_named(&name, value) {
+            println!("{}", e);
+        }
+    }
+
+    fn fill_memory(&mut self, start: u16, end: u16, pattern: Vec<u8>) {
+        // Nothing to fill with, and the modulo below would divide by zero.
+        if pattern.is_empty() {
+            return;
+        }
+        for (i, addr) in (start..=end).enumerate() {
+            self.memory.poke(addr, pattern[i % pattern.len()]);
+        }
+    }
+
+    fn search_memory(&mut self, start: u16, end: u16, pattern: Vec<u8>) -> Option<Vec<u16>> {
+        if pattern.is_empty() {
+            return Some(vec![]);
+        }
+        let bytes: Vec<u8> = (start..=end).map(|addr| self.memory[addr]).collect();
+        let found = bytes
+            .windows(pattern.len())
+            .enumerate()
+            .filter(|(_, window)| *window == pattern.as_slice())
+            .map(|(i, _)| start + i as u16)
+            .collect();
+        return Some(found);
+    }
+
+    fn save_memory(&mut self, start: u16, end: u16, path: PathBuf) {
+        let bytes: Vec<u8> = (start..=end).map(|addr| self.memory[addr]).collect();
+        match fs::write(&path, &bytes) {
+            Ok(()) => println!("Saved {} bytes to {}", bytes.len(), path.display()),
+            Err(e) => println!("{}: {}", path.display(), e),
+        }
+    }
+
+    fn load_memory(&mut self, addr: u16, path: PathBuf) {
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                println!("{}: {}", path.display(), e);
+                return;
+            }
+        };
+        if addr as usize + bytes.len() > 0x10000 {
+            println!("{} bytes don't fit at {:04X}", bytes.len(), addr);
+            return;
+        }
+        let len = bytes.len();
+        self.write_memory(addr, bytes);
+        println!("Loaded {} bytes at {:04X}", len, addr);
+    }
 }
@@ -1,18 +1,30 @@
+use crate::call_stack::CallStack;
+use crate::call_stack::Frame;
+use crate::call_stack::FrameKind;
 use crate::memory::Memory;
 use crate::memory_utils::Location;
 use crate::opcodes;
 use crate::registers::RegisterName;
 use crate::registers::Registers;
+use crate::trace::Trace;
 use crate::utils;
 
 pub struct Cpu {
     pub registers: Registers,
     pub ime: bool,
-    pub cycles_stalled: u8,
+    pub cycles_stalled: u16,
     pub halted: bool,
+    // Set by STOP, only a button press gets the CPU going again.
+    pub stopped: bool,
+    pub call_stack: CallStack,
+    // Logs every instruction when set, see --trace.
+    pub trace: Option<Trace>,
+    // Address of the instruction being run, or the one an interrupt is about to come in before.
+    instruction_pc: u16,
 }
 
 impl Cpu {
+    #[cfg(test)]
     pub fn new() -> Cpu {
         return Cpu {
             registers: Registers {
@@ -26,12 +38,41 @@ impl Cpu {
             ime: false,
             cycles_stalled: 0,
             halted: false,
+            stopped: false,
+            call_stack: CallStack::default(),
+            trace: None,
+            instruction_pc: 0,
         };
     }
 
+    pub fn from_registers(registers: Registers) -> Cpu {
+        return Cpu {
+            registers: registers,
+            ime: false,
+            cycles_stalled: 0,
+            halted: false,
+            stopped: false,
+            call_stack: CallStack::default(),
+            trace: None,
+            instruction_pc: 0,
+        };
+    }
+
+    // State at power on, before the boot ROM has run.
+    pub fn at_power_on() -> Cpu {
+        return Cpu::from_registers(Registers {
+            af: 0x0000,
+            bc: 0x0000,
+            de: 0x0000,
+            hl: 0x0000,
+            sp: 0x0000,
+            pc: 0x0000,
+        });
+    }
+
     // Reads the byte at (|pc|) then increments |pc|
     pub fn pc_read(&mut self, memory: &Memory) -> u8 {
-        let ret = memory[self.registers.pc];
+        let ret = memory.fetch(self.registers.pc);
         self.registers.pc = self.registers.pc.wrapping_add(1);
         return ret;
     }
@@ -95,47 +136,85 @@ impl Cpu {
         return ((h as u16) << 8) | (l as u16);
     }
 
+    // Where the instruction run on the next tick comes from, taking a pending interrupt into
+    // account. None when the next tick won't start an instruction at all.
+    pub fn next_instruction(&self, memory: &Memory) -> Option<u16> {
+        if memory.vram_dma_stalls_cpu() || self.stopped || self.cycles_stalled > 0 {
+            return None;
+        }
+
+        let pending = memory[0xFF0F] & memory[0xFFFF] & 0x1F;
+        if self.halted && pending == 0 {
+            return None;
+        }
+        if self.ime && pending != 0 {
+            return Some(0x40 + 8 * pending.trailing_zeros() as u16);
+        }
+        return Some(self.registers.pc);
+    }
+
     pub fn tick(&mut self, memory: &mut Memory, stall: bool) -> bool {
-        if self.halted && (memory[0xFF0F] & memory[0xFFFF] == 0) {
+        if memory.vram_dma_stalls_cpu() {
+            return false;
+        }
+
+        if self.halted && (memory[0xFF0F] & memory[0xFFFF] & 0x1F == 0) {
             return false;
         }
 
+        if self.stopped {
+            // Pressed buttons pull their P1 line low
+            if memory[0xFF00] & 0x0F == 0x0F {
+                return false;
+            }
+            self.stopped = false;
+        }
+
         if stall && self.cycles_stalled > 0 {
             self.cycles_stalled = self.cycles_stalled - 1;
             return false;
         }
 
+        self.instruction_pc = self.registers.pc;
         if self.ime {
             // TODO: The interrupt handling routing wastes some cycles
             if (memory[0xFF0F] & 0x01 != 0) && (memory[0xFFFF] & 0x01 != 0) {
                 // VBLANK
                 memory.set(0xFF0F, memory[0xFF0F] & 0xFE);
                 self.ime = false;
-                self.call(Location::from_immediate(0x40), memory, true, true);
+                self.enter(0x40, memory, FrameKind::Interrupt);
             } else if (memory[0xFF0F] & 0b10 != 0) && (memory[0xFFFF] & 0b10 != 0) {
                 // STAT
                 // println!("STAT at LY={:02X}", memory[0xFF44]);
                 memory.set(0xFF0F, memory[0xFF0F] & !0b10);
                 self.ime = false;
-                self.call(Location::from_immediate(0x48), memory, true, true);
+                self.enter(0x48, memory, FrameKind::Interrupt);
             } else if (memory[0xFF0F] & 0b100 != 0) && (memory[0xFFFF] & 0b100 != 0) {
                 // TIMER
                 memory.set(0xFF0F, memory[0xFF0F] & !0b100);
                 self.ime = false;
-                self.call(Location::from_immediate(0x50), memory, true, true);
+                self.enter(0x50, memory, FrameKind::Interrupt);
             } else if (memory[0xFF0F] & 0b1000 != 0) && (memory[0xFFFF] & 0b1000 != 0) {
                 // SERIAL
                 memory.set(0xFF0F, memory[0xFF0F] & !0b1000);
                 self.ime = false;
-                self.call(Location::from_immediate(0x58), memory, true, true);
+                self.enter(0x58, memory, FrameKind::Interrupt);
             } else if (memory[0xFF0F] & 0b10000 != 0) && (memory[0xFFFF] & 0b10000 != 0) {
                 // JOYPAD
                 memory.set(0xFF0F, memory[0xFF0F] & !0b10000);
                 self.ime = false;
-                self.call(Location::from_immediate(0x60), memory, true, true);
+                self.enter(0x60, memory, FrameKind::Interrupt);
             }
         }
 
+        self.instruction_pc = self.registers.pc;
+        if let Some(trace) = self.trace.as_mut() {
+            // Same as recording, running out of disk only ends the trace.
+            if let Err(e) = trace.instruction(&self.registers, memory) {
+                println!("Tracing failed, stopping it: {}", e);
+                self.trace = None;
+            }
+        }
         let instr: u8 = self.pc_read(memory);
 
         // If it's the CB prefix byte, fetch the next one
@@ -143,12 +222,13 @@ impl Cpu {
             let arg = self.pc_read(memory);
             self.exec_pref(arg, memory);
             if stall {
-                self.cycles_stalled = 8 + 4; // 4 for the prefix, 8 for the instruction
+                self.cycles_stalled += 8 + 4; // 4 for the prefix, 8 for the instruction
             }
         } else {
             let stall_len = opcodes::exec_unpref(instr, memory, self);
             if stall {
-                self.cycles_stalled = stall_len;
+                // Some instructions (STOP) add their own stall on top
+                self.cycles_stalled += stall_len as u16;
             }
         }
 
@@ -160,19 +240,33 @@ impl Cpu {
             return;
         }
 
-        let pc = self.registers.pc;
-        self.push_word(pc, memory);
-        self.registers.pc = arg.read_word(memory, &self.registers);
+        let target = arg.read_word(memory, &self.registers);
+        self.enter(target, memory, FrameKind::Call);
     }
 
-    pub fn rst(&mut self, arg: Location, memory: &mut Memory, cond: bool, is_16: bool) {
+    pub fn rst(&mut self, arg: Location, memory: &mut Memory, cond: bool, _is_16: bool) {
         if !cond {
             panic!("rst can't have cond");
         }
 
-        // No cond check because 1/ RST has no conditional variant and 2/ CALL checks it anyway
         // TODO: eventually this will probably need to do something different to emulate that RST is faster than CALL
-        self.call(arg, memory, cond, is_16);
+        let target = arg.read_word(memory, &self.registers);
+        self.enter(target, memory, FrameKind::Rst);
+    }
+
+    // Pushes the return address and jumps, for CALL, RST and interrupts.
+    fn enter(&mut self, target: u16, memory: &mut Memory, kind: FrameKind) {
+        let pc = self.registers.pc;
+        self.push_word(pc, memory);
+        self.registers.pc = target;
+        self.call_stack.push(Frame {
+            kind: kind,
+            caller: self.instruction_pc,
+            caller_bank: memory.rom_bank(self.instruction_pc),
+            target: target,
+            target_bank: memory.rom_bank(target),
+            sp: self.registers.sp,
+        });
     }
 
     pub fn push(&mut self, arg: Location, memory: &mut Memory, cond: bool, is_16: bool) {
@@ -476,7 +570,9 @@ impl Cpu {
         }
 
         if is_16 {
-            let res = d.read_word(memory, &self.registers).wrapping_sub(1);
+            let value = d.read_word(memory, &self.registers);
+            memory.idu_access(value);
+            let res = value.wrapping_sub(1);
             d.write_word(memory, &mut self.registers, res);
         } else {
             let byte = d.read_byte(memory, &self.registers);
@@ -504,7 +600,9 @@ impl Cpu {
         }
 
         if is_16 {
-            let res = d.read_word(memory, &self.registers).wrapping_add(1);
+            let value = d.read_word(memory, &self.registers);
+            memory.idu_access(value);
+            let res = value.wrapping_add(1);
             d.write_word(memory, &mut self.registers, res);
         } else {
             let byte = d.read_byte(memory, &self.registers);
@@ -531,7 +629,9 @@ impl Cpu {
             return;
         }
 
+        let sp = self.registers.sp;
         self.registers.pc = self.pop_word(memory);
+        self.call_stack.ret(sp);
     }
 
     pub fn reti(&mut self, memory: &mut Memory, cond: bool, is_16: bool) {
@@ -548,9 +648,18 @@ impl Cpu {
         self.halted = true;
     }
 
-    pub fn stop(&mut self, _d: Location, _memory: &mut Memory, _cond: bool, _is_16: bool) {
+    pub fn stop(&mut self, _d: Location, memory: &mut Memory, _cond: bool, _is_16: bool) {
         // d is ignored
-        unimplemented!();
+        memory.reset_div();
+
+        // On CGB, STOP is how the speed switch armed through KEY1 happens. The CPU sits there
+        // for 2050 M-cycles while the clock settles, instead of actually stopping.
+        if memory.switch_speed() {
+            self.cycles_stalled += 2050 * 4;
+            return;
+        }
+
+        self.stopped = true;
     }
 
     pub fn ccf(&mut self, _memory: &mut Memory, _cond: bool, _is_16: bool) {
@@ -1036,18 +1145,18 @@ mod tests {
         assert_eq!(0x13, cpu.registers.read_byte(RegisterName::A));
 
         cpu.ld(
-            Location::from_address(0x00),
+            Location::from_address(0xC000),
             Location::from_immediate_register(RegisterName::C),
             &mut memory,
             true,
             false,
         );
-        assert_eq!(0x13, memory[0x00]);
+        assert_eq!(0x13, memory[0xC000]);
 
-        assert_eq!(0x00, memory[0x01]);
+        assert_eq!(0x00, memory[0xC001]);
         cpu.ld(
             Location::from_immediate_register(RegisterName::A),
-            Location::from_address(0x01),
+            Location::from_address(0xC001),
             &mut memory,
             true,
             false,
@@ -1082,15 +1191,15 @@ mod tests {
         assert_eq!(0xFF, cpu.registers.read_byte(RegisterName::A));
         assert_eq!(0x1233, cpu.registers.hl);
 
-        cpu.registers.hl = 0x1234;
-        memory[0x1234] = 0xFF;
+        cpu.registers.hl = 0xC234;
+        memory[0xC234] = 0xFF;
         cpu.registers.pc = 0x00;
         memory[0x00] = 0x32;
         cpu.registers.af = 0x00;
 
         cpu.tick(&mut memory, false);
-        assert_eq!(0x00, memory[0x1234]);
-        assert_eq!(0x1233, cpu.registers.hl);
+        assert_eq!(0x00, memory[0xC234]);
+        assert_eq!(0xC233, cpu.registers.hl);
     }
 
     #[test]
@@ -1098,7 +1207,7 @@ mod tests {
         let mut cpu = Cpu::new();
         let mut memory = Memory::empty();
 
-        cpu.registers.sp = 0x10;
+        cpu.registers.sp = 0xC010;
 
         cpu.registers.bc = 0x1234;
         cpu.push(
@@ -1108,9 +1217,9 @@ mod tests {
             true,
         );
 
-        assert_eq!(0x0E, cpu.registers.sp);
-        assert_eq!(0x34, memory[0x0E]);
-        assert_eq!(0x12, memory[0x0F]);
+        assert_eq!(0xC00E, cpu.registers.sp);
+        assert_eq!(0x34, memory[0xC00E]);
+        assert_eq!(0x12, memory[0xC00F]);
 
         cpu.registers.de = 0x00;
         cpu.pop(
@@ -1120,9 +1229,9 @@ mod tests {
             true,
         );
         assert_eq!(0x1234, cpu.registers.de);
-        assert_eq!(0x10, cpu.registers.sp);
+        assert_eq!(0xC010, cpu.registers.sp);
 
-        cpu.registers.sp = 0x80;
+        cpu.registers.sp = 0xC080;
         cpu.registers.bc = 0x1200;
         cpu.push(
             Location::from_immediate_register(RegisterName::Bc),
@@ -1291,7 +1400,7 @@ mod tests {
         let mut cpu = Cpu::new();
         let mut memory = Memory::empty();
 
-        cpu.registers.sp = 0xFF00;
+        cpu.registers.sp = 0xD000;
         cpu.registers.pc = 0x1234;
         memory[0x1234] = 0xCD;
         memory[0x1235] = 0x78;
@@ -1300,11 +1409,11 @@ mod tests {
 
         cpu.tick(&mut memory, false);
         assert_eq!(0x5678, cpu.registers.pc);
-        assert_eq!(0xFEFE, cpu.registers.sp);
+        assert_eq!(0xCFFE, cpu.registers.sp);
 
         cpu.tick(&mut memory, false);
         assert_eq!(0x1237, cpu.registers.pc);
-        assert_eq!(0xFF00, cpu.registers.sp);
+        assert_eq!(0xD000, cpu.registers.sp);
     }
 
     #[test]
@@ -1313,7 +1422,7 @@ mod tests {
         let mut memory = Memory::empty();
 
         cpu.ime = false;
-        cpu.registers.sp = 0xFF00;
+        cpu.registers.sp = 0xD000;
         cpu.registers.pc = 0x1234;
         memory[0x1234] = 0xCD;
         memory[0x1235] = 0x78;
@@ -1322,11 +1431,11 @@ mod tests {
 
         cpu.tick(&mut memory, false);
         assert_eq!(0x5678, cpu.registers.pc);
-        assert_eq!(0xFEFE, cpu.registers.sp);
+        assert_eq!(0xCFFE, cpu.registers.sp);
 
         cpu.tick(&mut memory, false);
         assert_eq!(0x1237, cpu.registers.pc);
-        assert_eq!(0xFF00, cpu.registers.sp);
+        assert_eq!(0xD000, cpu.registers.sp);
         assert!(cpu.ime);
     }
 
@@ -1740,4 +1849,32 @@ mod tests {
         assert!(!cpu.registers.z_set());
         assert!(!cpu.registers.n_set());
     }
+
+    #[test]
+    fn stop_switches_speed() {
+        let mut memory = Memory::empty_cgb();
+        let mut cpu = Cpu::new();
+
+        memory.set(0xFF4D, 0x01);
+        cpu.stop(Location::from_immediate_byte(0), &mut memory, true, false);
+        assert!(memory.double_speed());
+        assert!(!cpu.stopped);
+        assert_eq!(2050 * 4, cpu.cycles_stalled);
+    }
+
+    #[test]
+    fn stop_waits_for_button() {
+        let mut memory = Memory::empty();
+        let mut cpu = Cpu::new();
+        cpu.registers.pc = 0xC000;
+        memory[0xFF00] = 0x2F;
+
+        cpu.stop(Location::from_immediate_byte(0), &mut memory, true, false);
+        assert!(cpu.stopped);
+        assert!(!cpu.tick(&mut memory, true));
+
+        memory[0xFF00] = 0x2E;
+        assert!(cpu.tick(&mut memory, true));
+        assert!(!cpu.stopped);
+    }
 }
@@ -1,49 +1,316 @@
 use std::io;
 use std::io::Write;
+use std::path::PathBuf;
 use std::sync::mpsc;
+use std::time::Duration;
 
+use crate::breakpoint;
+use crate::breakpoint::Breakpoint;
+use crate::breakpoint::Condition;
+use crate::call_stack::Frame;
+use crate::disasm::Instruction;
 use crate::registers::Registers;
+use crate::symbols::Symbols;
+use crate::watchpoint;
+use crate::watchpoint::Access;
+use crate::watchpoint::WatchKind;
+use crate::watchpoint::Watchpoint;
 
+#[derive(Clone, Debug)]
+pub enum StopReason {
+    Step,
+    Paused,
+    Breakpoint(u32),
+    InstructionBreakpoint(u8),
+    // What the watchpoint caught and the instruction that did it
+    Watchpoint(u32, Access, u16),
+    // Run to and step over
+    Reached,
+    StepOut,
+    // The LY it got to, 144 being the start of VBlank
+    Line(u8),
+    Instructions(u64),
+    Cycles(u64),
+}
+
+// Where the console stopped and why.
+#[derive(Clone, Debug)]
+pub struct Stop {
+    pub reason: StopReason,
+    pub pc: u16,
+    pub bank: Option<usize>,
+}
+
+impl Stop {
+    pub fn describe(&self, symbols: &Symbols) -> String {
+        let location = format!(
+            "{}{}",
+            breakpoint::format_location(self.pc, self.bank),
+            symbols.suffix(self.pc, self.bank)
+        );
+        return match &self.reason {
+            StopReason::Step => format!("Stepped to {}", location),
+            StopReason::Paused => format!("Paused at {}", location),
+            StopReason::Breakpoint(id) => format!("Stopped at breakpoint {}, {}", id, location),
+            StopReason::InstructionBreakpoint(opcode) => format!(
+                "Stopped at instruction breakpoint {:02X}, {}",
+                opcode, location
+            ),
+            StopReason::Watchpoint(id, access, pc) => format!(
+                "Stopped by watchpoint {}: {} by the instruction at {:04X}, now at {}",
+                id,
+                access.describe(),
+                pc,
+                location
+            ),
+            StopReason::Reached => format!("Reached {}", location),
+            StopReason::StepOut => format!("Returned to {}", location),
+            StopReason::Line(144) => format!("VBlank started, at {}", location),
+            StopReason::Line(ly) => format!("LY is {}, at {}", ly, location),
+            StopReason::Instructions(count) => {
+                format!("Ran {} instructions, at {}", count, location)
+            }
+            StopReason::Cycles(count) => format!("Ran {} cycles, at {}", count, location),
+        };
+    }
+}
+
+// The commands that run the console (other than resume) get a Stopped response with the
+// reason once it stops again.
 pub trait Debuggable {
     fn step(&mut self);
     fn resume(&mut self);
+    fn pause(&mut self);
+    // Runs until |addr| is about to run, unless a breakpoint stops it first.
+    fn run_to(&mut self, addr: u16);
+    // Runs calls and RSTs until they return, other instructions just get stepped.
+    fn step_over(&mut self);
+    // Runs until the current function returns.
+    fn step_out(&mut self);
+    // Runs until LY changes, or until it gets to |line| when given.
+    fn run_to_line(&mut self, line: Option<u8>);
+    fn run_instructions(&mut self, count: u64);
+    // CPU cycles at 4 MHz, so twice as many go by per dot in double speed.
+    fn run_cycles(&mut self, count: u64);
+    // Why the console last stopped, once.
+    fn take_stop(&mut self) -> Option<Stop>;
+    fn quit(&mut self);
+    // False while stopped.
+    fn request_running(&mut self) -> Option<bool>;
+    // Memory as the debugger sees it, without side effects or access restrictions.
+    fn request_memory(&mut self, addr: u16, len: usize) -> Option<Vec<u8>>;
+    // Same view as request_memory, writing to ROM changes the loaded ROM.
+    fn write_memory(&mut self, addr: u16, bytes: Vec<u8>);
     fn request_registers(&mut self) -> Option<Registers>;
+    fn set_registers(&mut self, registers: Registers);
+    // The shadow call stack, outermost frame first, along with where PC is now.
+    fn request_call_stack(&mut self) -> Option<(u16, Option<usize>, Vec<Frame>)>;
     fn request_next_instruction(&mut self) -> Option<[u8; 3]>;
+    // |count| instructions from |addr|, or around PC without one. Comes with the current PC.
+    fn request_disassembly(
+        &mut self,
+        addr: Option<u16>,
+        count: usize,
+    ) -> Option<(u16, Vec<Instruction>)>;
     fn set_breakpoint_on_instr(&mut self, instr: u8);
+    // The id the breakpoint is given is printed by the console.
+    fn add_breakpoint(&mut self, breakpoint: Breakpoint);
+    fn remove_breakpoint(&mut self, id: u32);
+    fn set_breakpoint_condition(&mut self, id: u32, condition: Option<Condition>);
+    fn set_breakpoint_ignore_count(&mut self, id: u32, count: u32);
+    fn request_breakpoints(&mut self) -> Option<Vec<Breakpoint>>;
+    // Watchpoints share ids with breakpoints, remove_breakpoint works on both.
+    fn add_watchpoint(&mut self, watchpoint: Watchpoint);
+    fn request_watchpoints(&mut self) -> Option<Vec<Watchpoint>>;
+    // Like request_memory, but from |bank| whether it's mapped or not when one is given.
+    fn dump_memory(
+        &mut self,
+        addr: u16,
+        bank: Option<usize>,
+        len: usize,
+    ) -> Option<Result<Vec<u8>, String>>;
+    // Names are the ones breakpoint conditions use, "a" or "hl".
+    fn set_register(&mut self, name: String, value: u16);
+    // Repeats |pattern| over start~end, both included. Same writes as write_memory.
+    fn fill_memory(&mut self, start: u16, end: u16, pattern: Vec<u8>);
+    // Where |pattern| starts within start~end.
+    fn search_memory(&mut self, start: u16, end: u16, pattern: Vec<u8>) -> Option<Vec<u16>>;
+    // The console reads and writes the files, and prints how it went.
+    fn save_memory(&mut self, start: u16, end: u16, path: PathBuf);
+    fn load_memory(&mut self, addr: u16, path: PathBuf);
+}
+
+// Hex bytes separated by spaces, "3E 01" or "$3E 0x01"
+fn parse_bytes(text: &str) -> Result<Vec<u8>, String> {
+    return text
+        .split_whitespace()
+        .map(|byte| {
+            let hex = byte.trim_start_matches("0x").trim_start_matches('$');
+            u8::from_str_radix(hex, 16).map_err(|_| format!("Invalid byte {}", byte))
+        })
+        .collect();
+}
+
+// 16 bytes per row, each starting with where it is
+fn print_hex_dump(addr: u16, bank: Option<usize>, bytes: &[u8]) {
+    for (i, row) in bytes.chunks(16).enumerate() {
+        let row: std::vec::Vec<String> = row.iter().map(|b| format!("{:02X}", b)).collect();
+        println!(
+            "{}  {}",
+            breakpoint::format_location(addr.wrapping_add(i as u16 * 16), bank),
+            row.join(" ")
+        );
+    }
 }
 
 pub enum RemoteDebugMessage {
     Step,
     Resume,
+    // Resume, but with a Stopped response like the other commands that run the console.
+    Continue,
+    Pause,
+    RunTo(u16),
+    StepOver,
+    StepOut,
+    RunToLine(Option<u8>),
+    RunInstructions(u64),
+    RunCycles(u64),
+    Quit,
+    RequestRunning,
+    RequestMemory(u16, usize),
+    WriteMemory(u16, Vec<u8>),
+    SetRegisters(Registers),
     RequestRegisters,
+    RequestCallStack,
     RequestNextInstr,
+    RequestDisassembly(Option<u16>, usize),
     SetBreakpointOnInstr(u8),
+    AddBreakpoint(Breakpoint),
+    RemoveBreakpoint(u32),
+    SetBreakpointCondition(u32, Option<Condition>),
+    SetBreakpointIgnoreCount(u32, u32),
+    RequestBreakpoints,
+    AddWatchpoint(Watchpoint),
+    RequestWatchpoints,
+    DumpMemory(u16, Option<usize>, usize),
+    SetRegister(String, u16),
+    FillMemory(u16, u16, Vec<u8>),
+    SearchMemory(u16, u16, Vec<u8>),
+    SaveMemory(u16, u16, PathBuf),
+    LoadMemory(u16, PathBuf),
 }
 
 pub enum RemoteDebugMessageResponse {
+    Stopped(Stop),
+    RequestRunning(bool),
+    RequestMemory(u16, Vec<u8>),
     RequestRegisters(Registers),
+    RequestCallStack(u16, Option<usize>, Vec<Frame>),
     RequestNextInstr([u8; 3]),
+    RequestDisassembly(u16, Vec<Instruction>),
+    RequestBreakpoints(Vec<Breakpoint>),
+    RequestWatchpoints(Vec<Watchpoint>),
+    DumpMemory(u16, Option<usize>, Result<Vec<u8>, String>),
+    SearchMemory(Vec<u16>),
 }
 
+// How long the CLI waits on a command that runs the console before pausing it.
+const RUN_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub struct DebuggerRemote {
     sender: mpsc::Sender<RemoteDebugMessage>,
     receiver: mpsc::Receiver<RemoteDebugMessageResponse>,
+    // Lets addresses be given as labels
+    symbols: Symbols,
 }
 
 impl DebuggerRemote {
     pub fn new(
         sender: mpsc::Sender<RemoteDebugMessage>,
         receiver: mpsc::Receiver<RemoteDebugMessageResponse>,
+        symbols: Symbols,
     ) -> DebuggerRemote {
         return DebuggerRemote {
             sender: sender,
             receiver: receiver,
+            symbols: symbols,
         };
     }
 
+    // A label, "bank:addr" or "addr". Labels go first since plenty of them are valid hex too,
+    // e.g. "Fade" or "Add".
+    fn parse_location(&self, text: &str) -> Result<(u16, Option<usize>), String> {
+        if let Some(location) = self.symbols.resolve(text) {
+            return Ok(location);
+        }
+        return breakpoint::parse_location(text);
+    }
+
+    // A label, "start-end" or "addr"
+    fn parse_range(&self, text: &str) -> Result<(u16, u16), String> {
+        if let Some((addr, _)) = self.symbols.resolve(text) {
+            return Ok((addr, addr));
+        }
+        return watchpoint::parse_range(text);
+    }
+
     pub fn wait_for_response(&mut self) {
-        let msg = self.receiver.recv().unwrap();
+        // The console can quit while running, e.g. when --frames is done.
+        let msg = match self.receiver.recv() {
+            Ok(msg) => msg,
+            Err(_) => return,
+        };
+        self.show_response(msg);
+    }
+
+    // For the commands that run until something happens, which might never: a step on a HALT
+    // with interrupts off, "until" an address that doesn't get run. Rather than leaving the
+    // prompt stuck, the console gets paused after a while.
+    fn wait_for_stop(&mut self) {
+        let msg = match self.receiver.recv_timeout(RUN_TIMEOUT) {
+            Ok(msg) => msg,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                println!(
+                    "Still running after {} seconds, pausing",
+                    RUN_TIMEOUT.as_secs()
+                );
+                // If it stopped in the meantime, that's the stop that comes back instead.
+                self.sender.send(RemoteDebugMessage::Pause).unwrap();
+                self.wait_for_response();
+                return;
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => return,
+        };
+        self.show_response(msg);
+    }
+
+    fn show_response(&mut self, msg: RemoteDebugMessageResponse) {
         match msg {
+            RemoteDebugMessageResponse::Stopped(stop) => {
+                println!("{}", stop.describe(&self.symbols));
+            }
+            RemoteDebugMessageResponse::RequestRunning(running) => {
+                println!("{}", if running { "Running" } else { "Stopped" });
+            }
+            RemoteDebugMessageResponse::RequestMemory(addr, bytes) => {
+                print_hex_dump(addr, None, &bytes);
+            }
+            RemoteDebugMessageResponse::DumpMemory(addr, bank, bytes) => match bytes {
+                Ok(bytes) => print_hex_dump(addr, bank, &bytes),
+                Err(e) => println!("{}", e),
+            },
+            RemoteDebugMessageResponse::SearchMemory(found) => {
+                if found.is_empty() {
+                    println!("Not found");
+                }
+                // Searching for something common could print the whole address space
+                for addr in found.iter().take(32) {
+                    println!("Found at {:04X}{}", addr, self.symbols.suffix(*addr, None));
+                }
+                if found.len() > 32 {
+                    println!("And {} more", found.len() - 32);
+                }
+            }
             RemoteDebugMessageResponse::RequestRegisters(registers) => {
                 println!("PC: {:04X}", registers.pc);
                 println!("SP: {:04X}", registers.sp);
@@ -52,9 +319,44 @@ impl DebuggerRemote {
                 println!("DE: {:04X}", registers.de);
                 println!("HL: {:04X}", registers.hl);
             }
+            RemoteDebugMessageResponse::RequestCallStack(pc, bank, frames) => {
+                println!(
+                    "#0 {}{}",
+                    breakpoint::format_location(pc, bank),
+                    self.symbols.suffix(pc, bank)
+                );
+                for (i, frame) in frames.iter().rev().enumerate() {
+                    println!("#{} {}", i + 1, frame.describe(&self.symbols));
+                }
+            }
             RemoteDebugMessageResponse::RequestNextInstr(bytes) => {
                 println!("{:02X} {:02X} {:02X}", bytes[0], bytes[1], bytes[2]);
             }
+            RemoteDebugMessageResponse::RequestDisassembly(pc, instructions) => {
+                for instruction in instructions {
+                    if let Some(label) = &instruction.label {
+                        println!("{}:", label);
+                    }
+                    let marker = if instruction.addr == pc { "=>" } else { "  " };
+                    println!("{} {}", marker, instruction.describe());
+                }
+            }
+            RemoteDebugMessageResponse::RequestBreakpoints(breakpoints) => {
+                if breakpoints.is_empty() {
+                    println!("No breakpoints");
+                }
+                for breakpoint in breakpoints {
+                    println!("{}", breakpoint.describe());
+                }
+            }
+            RemoteDebugMessageResponse::RequestWatchpoints(watchpoints) => {
+                if watchpoints.is_empty() {
+                    println!("No watchpoints");
+                }
+                for watchpoint in watchpoints {
+                    println!("{}", watchpoint.describe());
+                }
+            }
         }
     }
 
@@ -64,22 +366,232 @@ impl DebuggerRemote {
         let _ = std::io::stdout().flush();
         io::stdin().read_line(&mut buffer).unwrap();
 
-        if buffer.starts_with("reg") {
-            self.request_registers();
-        } else if buffer.starts_with("c") {
-            self.resume();
-        } else if buffer.starts_with("l") {
-            self.request_next_instruction();
-        } else if buffer.starts_with("s") {
-            self.step();
-        } else if buffer.starts_with("bi") {
-            if buffer.len() < 5 {
-                println!("Invalid argument");
-            } else {
-                let mut bytes = [0u8; 1];
-                hex::decode_to_slice(&buffer[3..5], &mut bytes as &mut [u8]).unwrap();
-
-                self.set_breakpoint_on_instr(bytes[0]);
+        let (command, args) = match buffer.trim().split_once(' ') {
+            Some((command, args)) => (command, args.trim()),
+            None => (buffer.trim(), ""),
+        };
+        let id = || {
+            args.split_whitespace()
+                .next()
+                .and_then(|id| id.parse::<u32>().ok())
+        };
+
+        match command {
+            "reg" => {
+                self.request_registers();
+            }
+            "bt" => {
+                self.request_call_stack();
+            }
+            "c" => {
+                self.resume();
+            }
+            "l" => {
+                // l [addr] [count], around PC when no address is given
+                let mut words = args.split_whitespace();
+                let addr = match words.next().map(|w| self.parse_location(w)).transpose() {
+                    Ok(location) => location.map(|(addr, _)| addr),
+                    Err(e) => {
+                        println!("{}", e);
+                        return;
+                    }
+                };
+                let count = words.next().and_then(|c| c.parse().ok()).unwrap_or(8);
+                self.request_disassembly(addr, count);
+            }
+            "s" => {
+                // s [count]
+                match args.parse::<u64>() {
+                    Ok(count) if count > 1 => self.run_instructions(count),
+                    _ => self.step(),
+                }
+            }
+            "n" => {
+                self.step_over();
+            }
+            "out" => {
+                self.step_out();
+            }
+            "until" => match self.parse_location(args) {
+                Ok((addr, _)) => self.run_to(addr),
+                Err(e) => println!("{}", e),
+            },
+            "vblank" => {
+                self.run_to_line(Some(144));
+            }
+            "line" => {
+                // line [ly], the next one when not given
+                if args.is_empty() {
+                    self.run_to_line(None);
+                } else {
+                    match breakpoint::parse_number(args) {
+                        Some(ly) if ly < 154 => self.run_to_line(Some(ly as u8)),
+                        _ => println!("Usage: line [ly], with ly under 154"),
+                    }
+                }
+            }
+            "cycles" => match args.parse::<u64>() {
+                Ok(count) if count > 0 => self.run_cycles(count),
+                _ => println!("Usage: cycles <count>"),
+            },
+            "bi" => {
+                if args.len() < 2 {
+                    println!("Invalid argument");
+                } else {
+                    let mut bytes = [0u8; 1];
+                    hex::decode_to_slice(&args[0..2], &mut bytes as &mut [u8]).unwrap();
+
+                    self.set_breakpoint_on_instr(bytes[0]);
+                }
+            }
+            "b" => {
+                // b [bank:]addr|label [if condition]
+                let (location, condition) = match args.split_once(" if ") {
+                    Some((location, condition)) => (location.trim(), Some(condition)),
+                    None => (args, None),
+                };
+                let condition = match condition.map(Condition::parse).transpose() {
+                    Ok(condition) => condition,
+                    Err(e) => {
+                        println!("{}", e);
+                        return;
+                    }
+                };
+                match self.parse_location(location) {
+                    Ok((addr, bank)) => self.add_breakpoint(Breakpoint::new(addr, bank, condition)),
+                    Err(e) => println!("{}", e),
+                }
+            }
+            "watch" | "rwatch" | "awatch" => {
+                // watch <addr>[-<end>]|<label> [if condition], for writes, reads or both
+                let kind = match command {
+                    "watch" => WatchKind::Write,
+                    "rwatch" => WatchKind::Read,
+                    _ => WatchKind::Access,
+                };
+                let (range, condition) = match args.split_once(" if ") {
+                    Some((range, condition)) => (range.trim(), Some(condition)),
+                    None => (args, None),
+                };
+                let condition = match condition.map(Condition::parse).transpose() {
+                    Ok(condition) => condition,
+                    Err(e) => {
+                        println!("{}", e);
+                        return;
+                    }
+                };
+                match self.parse_range(range) {
+                    Ok((start, end)) => {
+                        self.add_watchpoint(Watchpoint::new(start, end, kind, condition))
+                    }
+                    Err(e) => println!("{}", e),
+                }
+            }
+            "bl" => {
+                self.request_breakpoints();
+                self.request_watchpoints();
+            }
+            "d" => match id() {
+                Some(id) => self.remove_breakpoint(id),
+                None => println!("Usage: d <id>"),
+            },
+            "cond" => {
+                // cond <id> [condition], no condition makes it unconditional again
+                let condition = args.split_once(' ').map(|(_, c)| c);
+                match (id(), condition.map(Condition::parse).transpose()) {
+                    (Some(id), Ok(condition)) => self.set_breakpoint_condition(id, condition),
+                    (None, _) => println!("Usage: cond <id> [condition]"),
+                    (_, Err(e)) => println!("{}", e),
+                }
+            }
+            "ignore" => {
+                let count = args.split_whitespace().nth(1).and_then(|c| c.parse().ok());
+                match (id(), count) {
+                    (Some(id), Some(count)) => self.set_breakpoint_ignore_count(id, count),
+                    _ => println!("Usage: ignore <id> <count>"),
+                }
+            }
+            "x" => {
+                // x <[bank:]addr|label> [len], or x <start-end>
+                let mut words = args.split_whitespace();
+                let target = words.next().unwrap_or("");
+                let dump = if target.contains('-') {
+                    self.parse_range(target)
+                        .map(|(start, end)| (start, None, end.wrapping_sub(start) as usize + 1))
+                } else {
+                    let len = words.next().map_or(Some(64), breakpoint::parse_number);
+                    match (self.parse_location(target), len) {
+                        (Ok((addr, bank)), Some(len)) if len > 0 => Ok((addr, bank, len as usize)),
+                        (Err(e), _) if !target.is_empty() => Err(e),
+                        _ => {
+                            Err("Usage: x <[bank:]addr|label> [len], or x <start-end>".to_string())
+                        }
+                    }
+                };
+                match dump {
+                    Ok((addr, bank, len)) => {
+                        self.dump_memory(addr, bank, len);
+                    }
+                    Err(e) => println!("{}", e),
+                }
+            }
+            "w" => {
+                // w <addr|label> <bytes>, to whatever is mapped there
+                let (location, bytes) = args.split_once(' ').unwrap_or((args, ""));
+                match (self.parse_location(location), parse_bytes(bytes)) {
+                    (Ok((addr, None)), Ok(bytes)) if !bytes.is_empty() => {
+                        self.write_memory(addr, bytes)
+                    }
+                    (Ok(_), Ok(_)) => println!("Usage: w <addr|label> <bytes>, without a bank"),
+                    (Err(e), _) | (_, Err(e)) => println!("{}", e),
+                }
+            }
+            "set" => {
+                // set <register> <value>, the value in hex like addresses
+                let (name, value) = args.split_once(' ').unwrap_or((args, ""));
+                match breakpoint::parse_address(value.trim()) {
+                    Ok(value) => self.set_register(name.to_lowercase(), value),
+                    Err(_) => println!("Usage: set <register> <value>"),
+                }
+            }
+            "fill" | "find" => {
+                // fill <start-end> <bytes>, find <start-end> <bytes>
+                let (range, bytes) = args.split_once(' ').unwrap_or((args, ""));
+                match (self.parse_range(range), parse_bytes(bytes)) {
+                    (Ok((start, end)), Ok(bytes)) if !bytes.is_empty() => {
+                        if command == "fill" {
+                            self.fill_memory(start, end, bytes);
+                        } else {
+                            self.search_memory(start, end, bytes);
+                        }
+                    }
+                    (Ok(_), Ok(_)) => println!("Usage: {} <start-end> <bytes>", command),
+                    (Err(e), _) | (_, Err(e)) => println!("{}", e),
+                }
+            }
+            "save" => {
+                let (range, path) = args.split_once(' ').unwrap_or((args, ""));
+                match self.parse_range(range) {
+                    Ok((start, end)) if !path.trim().is_empty() => {
+                        self.save_memory(start, end, PathBuf::from(path.trim()))
+                    }
+                    Ok(_) => println!("Usage: save <start-end> <file>"),
+                    Err(e) => println!("{}", e),
+                }
+            }
+            "load" => {
+                let (location, path) = args.split_once(' ').unwrap_or((args, ""));
+                match self.parse_location(location) {
+                    Ok((addr, None)) if !path.trim().is_empty() => {
+                        self.load_memory(addr, PathBuf::from(path.trim()))
+                    }
+                    Ok(_) => println!("Usage: load <addr|label> <file>, without a bank"),
+                    Err(e) => println!("{}", e),
+                }
+            }
+            "" => {}
+            _ => {
+                println!("Unknown command {}", command);
             }
         }
     }
@@ -88,12 +600,80 @@ impl DebuggerRemote {
 impl Debuggable for DebuggerRemote {
     fn step(&mut self) {
         self.sender.send(RemoteDebugMessage::Step).unwrap();
+        self.wait_for_stop();
     }
 
     fn resume(&mut self) {
         self.sender.send(RemoteDebugMessage::Resume).unwrap();
     }
 
+    fn pause(&mut self) {
+        self.sender.send(RemoteDebugMessage::Pause).unwrap();
+    }
+
+    fn run_to(&mut self, addr: u16) {
+        self.sender.send(RemoteDebugMessage::RunTo(addr)).unwrap();
+        self.wait_for_stop();
+    }
+
+    fn step_over(&mut self) {
+        self.sender.send(RemoteDebugMessage::StepOver).unwrap();
+        self.wait_for_stop();
+    }
+
+    fn step_out(&mut self) {
+        self.sender.send(RemoteDebugMessage::StepOut).unwrap();
+        self.wait_for_stop();
+    }
+
+    fn run_to_line(&mut self, line: Option<u8>) {
+        self.sender
+            .send(RemoteDebugMessage::RunToLine(line))
+            .unwrap();
+        self.wait_for_stop();
+    }
+
+    fn run_instructions(&mut self, count: u64) {
+        self.sender
+            .send(RemoteDebugMessage::RunInstructions(count))
+            .unwrap();
+        self.wait_for_stop();
+    }
+
+    fn run_cycles(&mut self, count: u64) {
+        self.sender
+            .send(RemoteDebugMessage::RunCycles(count))
+            .unwrap();
+        self.wait_for_stop();
+    }
+
+    // Stops come back as responses, see wait_for_response.
+    fn take_stop(&mut self) -> Option<Stop> {
+        return None;
+    }
+
+    fn quit(&mut self) {
+        self.sender.send(RemoteDebugMessage::Quit).unwrap();
+    }
+
+    fn request_running(&mut self) -> Option<bool> {
+        self.sender
+            .send(RemoteDebugMessage::RequestRunning)
+            .unwrap();
+        self.wait_for_response();
+
+        return None;
+    }
+
+    fn request_memory(&mut self, addr: u16, len: usize) -> Option<Vec<u8>> {
+        self.sender
+            .send(RemoteDebugMessage::RequestMemory(addr, len))
+            .unwrap();
+        self.wait_for_response();
+
+        return None;
+    }
+
     fn request_registers(&mut self) -> Option<Registers> {
         self.sender
             .send(RemoteDebugMessage::RequestRegisters)
@@ -103,6 +683,21 @@ impl Debuggable for DebuggerRemote {
         return None;
     }
 
+    fn set_registers(&mut self, registers: Registers) {
+        self.sender
+            .send(RemoteDebugMessage::SetRegisters(registers))
+            .unwrap();
+    }
+
+    fn request_call_stack(&mut self) -> Option<(u16, Option<usize>, Vec<Frame>)> {
+        self.sender
+            .send(RemoteDebugMessage::RequestCallStack)
+            .unwrap();
+        self.wait_for_response();
+
+        return None;
+    }
+
     fn request_next_instruction(&mut self) -> Option<[u8; 3]> {
         self.sender
             .send(RemoteDebugMessage::RequestNextInstr)
@@ -112,16 +707,132 @@ impl Debuggable for DebuggerRemote {
         return None;
     }
 
+    fn request_disassembly(
+        &mut self,
+        addr: Option<u16>,
+        count: usize,
+    ) -> Option<(u16, Vec<Instruction>)> {
+        self.sender
+            .send(RemoteDebugMessage::RequestDisassembly(addr, count))
+            .unwrap();
+        self.wait_for_response();
+
+        return None;
+    }
+
+    fn write_memory(&mut self, addr: u16, bytes: Vec<u8>) {
+        self.sender
+            .send(RemoteDebugMessage::WriteMemory(addr, bytes))
+            .unwrap();
+    }
+
     fn set_breakpoint_on_instr(&mut self, instr: u8) {
         self.sender
             .send(RemoteDebugMessage::SetBreakpointOnInstr(instr))
             .unwrap();
     }
+
+    fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        self.sender
+            .send(RemoteDebugMessage::AddBreakpoint(breakpoint))
+            .unwrap();
+    }
+
+    fn remove_breakpoint(&mut self, id: u32) {
+        self.sender
+            .send(RemoteDebugMessage::RemoveBreakpoint(id))
+            .unwrap();
+    }
+
+    fn set_breakpoint_condition(&mut self, id: u32, condition: Option<Condition>) {
+        self.sender
+            .send(RemoteDebugMessage::SetBreakpointCondition(id, condition))
+            .unwrap();
+    }
+
+    fn set_breakpoint_ignore_count(&mut self, id: u32, count: u32) {
+        self.sender
+            .send(RemoteDebugMessage::SetBreakpointIgnoreCount(id, count))
+            .unwrap();
+    }
+
+    fn request_breakpoints(&mut self) -> Option<Vec<Breakpoint>> {
+        self.sender
+            .send(RemoteDebugMessage::RequestBreakpoints)
+            .unwrap();
+        self.wait_for_response();
+
+        return None;
+    }
+
+    fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.sender
+            .send(RemoteDebugMessage::AddWatchpoint(watchpoint))
+            .unwrap();
+    }
+
+    fn request_watchpoints(&mut self) -> Option<Vec<Watchpoint>> {
+        self.sender
+            .send(RemoteDebugMessage::RequestWatchpoints)
+            .unwrap();
+        self.wait_for_response();
+
+        return None;
+    }
+
+    fn dump_memory(
+        &mut self,
+        addr: u16,
+        bank: Option<usize>,
+        len: usize,
+    ) -> Option<Result<Vec<u8>, String>> {
+        self.sender
+            .send(RemoteDebugMessage::DumpMemory(addr, bank, len))
+            .unwrap();
+        self.wait_for_response();
+
+        return None;
+    }
+
+    fn set_register(&mut self, name: String, value: u16) {
+        self.sender
+            .send(RemoteDebugMessage::SetRegister(name, value))
+            .unwrap();
+    }
+
+    fn fill_memory(&mut self, start: u16, end: u16, pattern: Vec<u8>) {
+        self.sender
+            .send(RemoteDebugMessage::FillMemory(start, end, pattern))
+            .unwrap();
+    }
+
+    fn search_memory(&mut self, start: u16, end: u16, pattern: Vec<u8>) -> Option<Vec<u16>> {
+        self.sender
+            .send(RemoteDebugMessage::SearchMemory(start, end, pattern))
+            .unwrap();
+        self.wait_for_response();
+
+        return None;
+    }
+
+    fn save_memory(&mut self, start: u16, end: u16, path: PathBuf) {
+        self.sender
+            .send(RemoteDebugMessage::SaveMemory(start, end, path))
+            .unwrap();
+    }
+
+    fn load_memory(&mut self, addr: u16, path: PathBuf) {
+        self.sender
+            .send(RemoteDebugMessage::LoadMemory(addr, path))
+            .unwrap();
+    }
 }
 
 pub struct DebuggerHost {
     receiver: mpsc::Receiver<RemoteDebugMessage>,
     sender: mpsc::Sender<RemoteDebugMessageResponse>,
+    // Set by the commands that get told when the console stops.
+    waiting_for_stop: bool,
 }
 
 impl DebuggerHost {
@@ -132,41 +843,183 @@ impl DebuggerHost {
         return DebuggerHost {
             receiver: receiver,
             sender: sender,
+            waiting_for_stop: false,
         };
     }
 
+    // Handles everything that came in since the last update, so a remote can send a few
+    // requests at once and get all the answers back without waiting on the console between them.
     pub fn update(&mut self, debuggable: &mut impl Debuggable) {
-        let msg = self.receiver.try_recv();
-        match msg {
-            Ok(msg) => match msg {
-                RemoteDebugMessage::Step => {
-                    debuggable.step();
-                }
-                RemoteDebugMessage::Resume => {
-                    debuggable.resume();
-                }
-                RemoteDebugMessage::RequestRegisters => {
-                    let regs = debuggable.request_registers().unwrap();
-                    self.sender
-                        .send(RemoteDebugMessageResponse::RequestRegisters(regs))
-                        .unwrap();
-                }
-                RemoteDebugMessage::RequestNextInstr => {
-                    let bytes = debuggable.request_next_instruction().unwrap();
-                    self.sender
-                        .send(RemoteDebugMessageResponse::RequestNextInstr(bytes))
-                        .unwrap();
-                }
-                RemoteDebugMessage::SetBreakpointOnInstr(instr) => {
-                    debuggable.set_breakpoint_on_instr(instr);
-                }
-            },
-            Err(error) => match error {
-                mpsc::TryRecvError::Empty => {}
-                mpsc::TryRecvError::Disconnected => {
-                    println!("Remote debugger disconnected");
+        // Stops from before the command that's waiting came in don't count, so this goes first.
+        if let Some(stop) = debuggable.take_stop() {
+            if self.waiting_for_stop {
+                self.waiting_for_stop = false;
+                self.sender
+                    .send(RemoteDebugMessageResponse::Stopped(stop))
+                    .unwrap();
+            }
+        }
+
+        loop {
+            let msg = self.receiver.try_recv();
+            match msg {
+                Ok(msg) => self.handle(msg, debuggable),
+                Err(error) => {
+                    if error == mpsc::TryRecvError::Disconnected {
+                        println!("Remote debugger disconnected");
+                    }
+                    return;
                 }
-            },
+            }
+        }
+    }
+
+    fn handle(&mut self, msg: RemoteDebugMessage, debuggable: &mut impl Debuggable) {
+        match msg {
+            RemoteDebugMessage::Step
+            | RemoteDebugMessage::RunTo(_)
+            | RemoteDebugMessage::StepOver
+            | RemoteDebugMessage::StepOut
+            | RemoteDebugMessage::RunToLine(_)
+            | RemoteDebugMessage::RunInstructions(_)
+            | RemoteDebugMessage::RunCycles(_)
+            | RemoteDebugMessage::Continue => self.waiting_for_stop = true,
+            RemoteDebugMessage::Resume => self.waiting_for_stop = false,
+            _ => {}
+        }
+
+        match msg {
+            RemoteDebugMessage::Step => {
+                debuggable.step();
+            }
+            RemoteDebugMessage::Resume | RemoteDebugMessage::Continue => {
+                debuggable.resume();
+            }
+            RemoteDebugMessage::Pause => {
+                debuggable.pause();
+            }
+            RemoteDebugMessage::RunTo(addr) => {
+                debuggable.run_to(addr);
+            }
+            RemoteDebugMessage::StepOver => {
+                debuggable.step_over();
+            }
+            RemoteDebugMessage::StepOut => {
+                debuggable.step_out();
+            }
+            RemoteDebugMessage::RunToLine(line) => {
+                debuggable.run_to_line(line);
+            }
+            RemoteDebugMessage::RunInstructions(count) => {
+                debuggable.run_instructions(count);
+            }
+            RemoteDebugMessage::RunCycles(count) => {
+                debuggable.run_cycles(count);
+            }
+            RemoteDebugMessage::Quit => {
+                debuggable.quit();
+            }
+            RemoteDebugMessage::RequestRunning => {
+                let running = debuggable.request_running().unwrap();
+                self.sender
+                    .send(RemoteDebugMessageResponse::RequestRunning(running))
+                    .unwrap();
+            }
+            RemoteDebugMessage::RequestMemory(addr, len) => {
+                let bytes = debuggable.request_memory(addr, len).unwrap();
+                self.sender
+                    .send(RemoteDebugMessageResponse::RequestMemory(addr, bytes))
+                    .unwrap();
+            }
+            RemoteDebugMessage::RequestRegisters => {
+                let regs = debuggable.request_registers().unwrap();
+                self.sender
+                    .send(RemoteDebugMessageResponse::RequestRegisters(regs))
+                    .unwrap();
+            }
+            RemoteDebugMessage::RequestCallStack => {
+                let (pc, bank, frames) = debuggable.request_call_stack().unwrap();
+                self.sender
+                    .send(RemoteDebugMessageResponse::RequestCallStack(
+                        pc, bank, frames,
+                    ))
+                    .unwrap();
+            }
+            RemoteDebugMessage::SetRegisters(registers) => {
+                debuggable.set_registers(registers);
+            }
+            RemoteDebugMessage::WriteMemory(addr, bytes) => {
+                debuggable.write_memory(addr, bytes);
+            }
+            RemoteDebugMessage::RequestNextInstr => {
+                let bytes = debuggable.request_next_instruction().unwrap();
+                self.sender
+                    .send(RemoteDebugMessageResponse::RequestNextInstr(bytes))
+                    .unwrap();
+            }
+            RemoteDebugMessage::RequestDisassembly(addr, count) => {
+                let (pc, instructions) = debuggable.request_disassembly(addr, count).unwrap();
+                self.sender
+                    .send(RemoteDebugMessageResponse::RequestDisassembly(
+                        pc,
+                        instructions,
+                    ))
+                    .unwrap();
+            }
+            RemoteDebugMessage::SetBreakpointOnInstr(instr) => {
+                debuggable.set_breakpoint_on_instr(instr);
+            }
+            RemoteDebugMessage::AddBreakpoint(breakpoint) => {
+                debuggable.add_breakpoint(breakpoint);
+            }
+            RemoteDebugMessage::RemoveBreakpoint(id) => {
+                debuggable.remove_breakpoint(id);
+            }
+            RemoteDebugMessage::SetBreakpointCondition(id, condition) => {
+                debuggable.set_breakpoint_condition(id, condition);
+            }
+            RemoteDebugMessage::SetBreakpointIgnoreCount(id, count) => {
+                debuggable.set_breakpoint_ignore_count(id, count);
+            }
+            RemoteDebugMessage::RequestBreakpoints => {
+                let breakpoints = debuggable.request_breakpoints().unwrap();
+                self.sender
+                    .send(RemoteDebugMessageResponse::RequestBreakpoints(breakpoints))
+                    .unwrap();
+            }
+            RemoteDebugMessage::AddWatchpoint(watchpoint) => {
+                debuggable.add_watchpoint(watchpoint);
+            }
+            RemoteDebugMessage::RequestWatchpoints => {
+                let watchpoints = debuggable.request_watchpoints().unwrap();
+                self.sender
+                    .send(RemoteDebugMessageResponse::RequestWatchpoints(watchpoints))
+                    .unwrap();
+            }
+            RemoteDebugMessage::DumpMemory(addr, bank, len) => {
+                let bytes = debuggable.dump_memory(addr, bank, len).unwrap();
+                self.sender
+                    .send(RemoteDebugMessageResponse::DumpMemory(addr, bank, bytes))
+                    .unwrap();
+            }
+            RemoteDebugMessage::SetRegister(name, value) => {
+                debuggable.set_register(name, value);
+            }
+            RemoteDebugMessage::FillMemory(start, end, pattern) => {
+                debuggable.fill_memory(start, end, pattern);
+            }
+            RemoteDebugMessage::SearchMemory(start, end, pattern) => {
+                let found = debuggable.search_memory(start, end, pattern).unwrap();
+                self.sender
+                    .send(RemoteDebugMessageResponse::SearchMemory(found))
+                    .unwrap();
+            }
+            RemoteDebugMessage::SaveMemory(start, end, path) => {
+                debuggable.save_memory(start, end, path);
+            }
+            RemoteDebugMessage::LoadMemory(addr, path) => {
+                debuggable.load_memory(addr, path);
+            }
         }
     }
 }
@@ -0,0 +1,615 @@
+use std::io;
+use std::sync::mpsc;
+
+use termion::event::Key;
+use termion::input::Keys;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+use termion::raw::RawTerminal;
+use termion::screen::AlternateScreen;
+use termion::AsyncReader;
+use tui::backend::TermionBackend;
+use tui::layout::Constraint;
+use tui::layout::Direction;
+use tui::layout::Layout;
+use tui::layout::Rect;
+use tui::style::Color;
+use tui::style::Modifier;
+use tui::style::Style;
+use tui::text::Span;
+use tui::text::Spans;
+use tui::widgets::Block;
+use tui::widgets::Borders;
+use tui::widgets::List;
+use tui::widgets::ListItem;
+use tui::widgets::ListState;
+use tui::widgets::Paragraph;
+use tui::Frame;
+use tui::Terminal;
+
+use crate::breakpoint;
+use crate::breakpoint::Breakpoint;
+use crate::debug::RemoteDebugMessage;
+use crate::debug::RemoteDebugMessageResponse;
+use crate::disasm::Instruction;
+use crate::registers::Registers;
+use crate::symbols::Symbols;
+
+type Backend = TermionBackend<AlternateScreen<RawTerminal<io::Stdout>>>;
+
+const DISASSEMBLY_LINES: usize = 48;
+const MEMORY_ROWS: usize = 16;
+const STACK_WORDS: usize = 16;
+
+const HELP: &str =
+    "s step  n over  o out  c continue  r run to cursor  p pause  b breakpoint  g goto  . PC  q quit";
+
+// What's shown, as of the last refresh.
+struct State {
+    running: bool,
+    registers: Registers,
+    pc: u16,
+    instructions: std::vec::Vec<Instruction>,
+    // Where the disassembly starts, around PC when not set.
+    disassembly_addr: Option<u16>,
+    // Index in |instructions|
+    cursor: usize,
+    memory_addr: u16,
+    memory: std::vec::Vec<u8>,
+    stack: std::vec::Vec<u8>,
+    // All of FF00-FFFF
+    io: std::vec::Vec<u8>,
+    breakpoints: std::vec::Vec<Breakpoint>,
+    // Text typed after g, until Enter or Esc.
+    goto: Option<String>,
+    message: String,
+}
+
+// A full-screen debugger in the terminal, talking to the console over the same channels as
+// DebuggerRemote. Everything is requested again on every update, so the panes keep changing
+// while the game runs.
+pub struct DebuggerUi {
+    sender: mpsc::Sender<RemoteDebugMessage>,
+    receiver: mpsc::Receiver<RemoteDebugMessageResponse>,
+    symbols: Symbols,
+    terminal: Terminal<Backend>,
+    keys: Keys<AsyncReader>,
+    state: State,
+}
+
+impl DebuggerUi {
+    pub fn new(
+        sender: mpsc::Sender<RemoteDebugMessage>,
+        receiver: mpsc::Receiver<RemoteDebugMessageResponse>,
+        symbols: Symbols,
+    ) -> Result<DebuggerUi, String> {
+        let stdout = io::stdout()
+            .into_raw_mode()
+            .map_err(|e| format!("Can't use the terminal: {}", e))?;
+        let mut terminal = Terminal::new(TermionBackend::new(AlternateScreen::from(stdout)))
+            .map_err(|e| format!("Can't use the terminal: {}", e))?;
+        let _ = terminal.hide_cursor();
+
+        return Ok(DebuggerUi {
+            sender: sender,
+            receiver: receiver,
+            symbols: symbols,
+            terminal: terminal,
+            keys: termion::async_stdin().keys(),
+            state: State {
+                running: false,
+                registers: Registers::default(),
+                pc: 0,
+                instructions: vec![],
+                disassembly_addr: None,
+                cursor: 0,
+                memory_addr: 0xC000,
+                memory: vec![],
+                stack: vec![],
+                io: vec![0; 0x100],
+                breakpoints: vec![],
+                goto: None,
+                message: String::new(),
+            },
+        });
+    }
+
+    // Handles keys and redraws. False once the debugger is done, either because it was quit or
+    // the console went away.
+    pub fn update(&mut self) -> bool {
+        let keys: std::vec::Vec<Key> = self.keys.by_ref().filter_map(|key| key.ok()).collect();
+        let mut clear = false;
+        for key in keys {
+            if !self.handle_key(key) {
+                return false;
+            }
+            clear = true;
+        }
+
+        let was_running = self.state.running;
+        if let Err(e) = self.refresh() {
+            self.state.message = e;
+            return false;
+        }
+        if was_running && !self.state.running {
+            self.follow_pc();
+            clear = true;
+        }
+
+        // The console prints what it's doing, e.g. breakpoints getting hit. Wiping the screen
+        // gets rid of it, so that only happens when something was done.
+        if clear {
+            let _ = self.terminal.clear();
+        }
+        let state = &self.state;
+        let symbols = &self.symbols;
+        return self.terminal.draw(|f| draw(f, state, symbols)).is_ok();
+    }
+
+    fn send(&self, message: RemoteDebugMessage) -> Result<(), String> {
+        return self
+            .sender
+            .send(message)
+            .map_err(|_| "Console is gone".to_string());
+    }
+
+    // Stops can come in between the answers to requests, those end up in the status line.
+    fn response(&mut self) -> Result<RemoteDebugMessageResponse, String> {
+        loop {
+            match self.receiver.recv() {
+                Ok(RemoteDebugMessageResponse::Stopped(stop)) => {
+                    self.state.message = stop.describe(&self.symbols);
+                }
+                Ok(response) => return Ok(response),
+                Err(_) => return Err("Console is gone".to_string()),
+            }
+        }
+    }
+
+    fn memory_response(&mut self) -> Result<std::vec::Vec<u8>, String> {
+        return match self.response()? {
+            RemoteDebugMessageResponse::RequestMemory(_, bytes) => Ok(bytes),
+            _ => Err("Expected memory from the console".to_string()),
+        };
+    }
+
+    // The stack can only be asked for once SP is known, so this takes two round trips.
+    fn refresh(&mut self) -> Result<(), String> {
+        self.send(RemoteDebugMessage::RequestRunning)?;
+        self.send(RemoteDebugMessage::RequestRegisters)?;
+        if let RemoteDebugMessageResponse::RequestRunning(running) = self.response()? {
+            self.state.running = running;
+        }
+        if let RemoteDebugMessageResponse::RequestRegisters(registers) = self.response()? {
+            self.state.registers = registers;
+        }
+
+        self.send(RemoteDebugMessage::RequestDisassembly(
+            self.state.disassembly_addr,
+            DISASSEMBLY_LINES,
+        ))?;
+        self.send(RemoteDebugMessage::RequestMemory(
+            self.state.memory_addr,
+            MEMORY_ROWS * 16,
+        ))?;
+        self.send(RemoteDebugMessage::RequestMemory(
+            self.state.registers.sp,
+            STACK_WORDS * 2,
+        ))?;
+        self.send(RemoteDebugMessage::RequestMemory(0xFF00, 0x100))?;
+        self.send(RemoteDebugMessage::RequestBreakpoints)?;
+
+        if let RemoteDebugMessageResponse::RequestDisassembly(pc, instructions) = self.response()? {
+            self.state.pc = pc;
+            self.state.instructions = instructions;
+        }
+        self.state.memory = self.memory_response()?;
+        self.state.stack = self.memory_response()?;
+        self.state.io = self.memory_response()?;
+        if let RemoteDebugMessageResponse::RequestBreakpoints(breakpoints) = self.response()? {
+            self.state.breakpoints = breakpoints;
+        }
+
+        self.state.cursor = self
+            .state
+            .cursor
+            .min(self.state.instructions.len().saturating_sub(1));
+        return Ok(());
+    }
+
+    // Back to the disassembly around PC, with the cursor on it.
+    fn follow_pc(&mut self) {
+        self.state.disassembly_addr = None;
+        if self.refresh().is_ok() {
+            let pc = self.state.pc;
+            self.state.cursor = self
+                .state
+                .instructions
+                .iter()
+                .position(|i| i.addr == pc)
+                .unwrap_or(0);
+        }
+    }
+
+    fn cursor_addr(&self) -> Option<u16> {
+        return self
+            .state
+            .instructions
+            .get(self.state.cursor)
+            .map(|i| i.addr);
+    }
+
+    fn handle_key(&mut self, key: Key) -> bool {
+        if let Some(text) = self.state.goto.as_mut() {
+            match key {
+                Key::Char('\n') => {
+                    let text = self.state.goto.take().unwrap();
+                    // Labels first, some of them look like hex
+                    let addr = match self.symbols.resolve(&text) {
+                        Some((addr, _)) => Ok(addr),
+                        None => breakpoint::parse_address(&text),
+                    };
+                    match addr {
+                        Ok(addr) => self.state.memory_addr = addr & 0xFFF0,
+                        Err(e) => self.state.message = e,
+                    }
+                }
+                Key::Esc => self.state.goto = None,
+                Key::Backspace => {
+                    text.pop();
+                }
+                Key::Char(c) => text.push(c),
+                _ => {}
+            }
+            return true;
+        }
+
+        self.state.message.clear();
+        let result = match key {
+            Key::Char('q') => {
+                let _ = self.send(RemoteDebugMessage::Quit);
+                return false;
+            }
+            Key::Char('s') => self.send(RemoteDebugMessage::Step),
+            Key::Char('n') => self.send(RemoteDebugMessage::StepOver),
+            Key::Char('o') => self.send(RemoteDebugMessage::StepOut),
+            Key::Char('c') => self.send(RemoteDebugMessage::Resume),
+            Key::Char('p') => self.send(RemoteDebugMessage::Pause),
+            Key::Char('r') => match self.cursor_addr() {
+                Some(addr) => self.send(RemoteDebugMessage::RunTo(addr)),
+                None => Ok(()),
+            },
+            Key::Char('b') => self.toggle_breakpoint(),
+            Key::Char('g') => {
+                self.state.goto = Some(String::new());
+                Ok(())
+            }
+            Key::Char('.') => {
+                self.follow_pc();
+                Ok(())
+            }
+            Key::Up => {
+                self.state.cursor = self.state.cursor.saturating_sub(1);
+                Ok(())
+            }
+            Key::Down => {
+                // Scrolls once the cursor is at the bottom
+                if self.state.cursor + 1 < self.state.instructions.len() {
+                    self.state.cursor += 1;
+                } else if let Some(next) = self.state.instructions.get(1) {
+                    self.state.disassembly_addr = Some(next.addr);
+                }
+                Ok(())
+            }
+            Key::PageUp => {
+                self.state.memory_addr = self.state.memory_addr.wrapping_sub(0x100);
+                Ok(())
+            }
+            Key::PageDown => {
+                self.state.memory_addr = self.state.memory_addr.wrapping_add(0x100);
+                Ok(())
+            }
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            self.state.message = e;
+            return false;
+        }
+        return true;
+    }
+
+    fn toggle_breakpoint(&mut self) -> Result<(), String> {
+        let addr = match self.cursor_addr() {
+            Some(addr) => addr,
+            None => return Ok(()),
+        };
+        let existing = self.state.breakpoints.iter().find(|b| b.addr == addr);
+        return match existing {
+            Some(breakpoint) => self.send(RemoteDebugMessage::RemoveBreakpoint(breakpoint.id)),
+            None => self.send(RemoteDebugMessage::AddBreakpoint(Breakpoint::new(
+                addr, None, None,
+            ))),
+        };
+    }
+}
+
+fn titled(title: &str) -> Block<'_> {
+    return Block::default().borders(Borders::ALL).title(title);
+}
+
+fn draw(f: &mut Frame<Backend>, state: &State, symbols: &Symbols) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Min(12),
+            Constraint::Length(MEMORY_ROWS as u16 + 2),
+            Constraint::Length(1),
+        ])
+        .split(f.size());
+    let top = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Length(22),
+            Constraint::Min(40),
+            Constraint::Length(36),
+        ])
+        .split(rows[0]);
+    let right = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+        .split(top[2]);
+    let bottom = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Length(77), Constraint::Min(30)])
+        .split(rows[1]);
+
+    draw_registers(f, top[0], state);
+    draw_disassembly(f, top[1], state);
+    draw_stack(f, right[0], state, symbols);
+
+    let breakpoints: std::vec::Vec<ListItem> = state
+        .breakpoints
+        .iter()
+        .map(|b| ListItem::new(b.describe()))
+        .collect();
+    f.render_widget(
+        List::new(breakpoints).block(titled("Breakpoints")),
+        right[1],
+    );
+
+    draw_memory(f, bottom[0], state);
+
+    let io: std::vec::Vec<Spans> = describe_io(&state.io)
+        .into_iter()
+        .map(Spans::from)
+        .collect();
+    f.render_widget(Paragraph::new(io).block(titled("IO")), bottom[1]);
+
+    let status = match &state.goto {
+        Some(text) => format!("Goto: {}", text),
+        None if !state.message.is_empty() => state.message.clone(),
+        None => HELP.to_string(),
+    };
+    f.render_widget(Paragraph::new(status), rows[2]);
+}
+
+fn draw_registers(f: &mut Frame<Backend>, area: Rect, state: &State) {
+    let registers = &state.registers;
+    let flag = |name: &'static str, set: bool| {
+        let style = if set {
+            Style::default().add_modifier(Modifier::BOLD)
+        } else {
+            Style::default().fg(Color::DarkGray)
+        };
+        return Span::styled(name, style);
+    };
+
+    let mut lines = vec![];
+    for (name, value) in [
+        ("AF", registers.af),
+        ("BC", registers.bc),
+        ("DE", registers.de),
+        ("HL", registers.hl),
+        ("SP", registers.sp),
+        ("PC", registers.pc),
+    ]
+    .iter()
+    {
+        lines.push(Spans::from(format!("{}  {:04X}", name, value)));
+    }
+    lines.push(Spans::from(""));
+    lines.push(Spans::from(vec![
+        flag("Z ", registers.z_set()),
+        flag("N ", registers.n_set()),
+        flag("H ", registers.h_set()),
+        flag("C", registers.c_set()),
+    ]));
+    lines.push(Spans::from(""));
+    lines.push(Spans::from(if state.running {
+        Span::styled("Running", Style::default().fg(Color::Green))
+    } else {
+        Span::styled("Stopped", Style::default().fg(Color::Red))
+    }));
+
+    f.render_widget(Paragraph::new(lines).block(titled("Registers")), area);
+}
+
+fn draw_disassembly(f: &mut Frame<Backend>, area: Rect, state: &State) {
+    let mut items = vec![];
+    let mut selected = None;
+    for (i, instruction) in state.instructions.iter().enumerate() {
+        if let Some(label) = &instruction.label {
+            items.push(ListItem::new(Span::styled(
+                format!("{}:", label),
+                Style::default().fg(Color::Yellow),
+            )));
+        }
+        if i == state.cursor {
+            selected = Some(items.len());
+        }
+
+        let breakpoint = state.breakpoints.iter().any(|b| b.addr == instruction.addr);
+        let text = format!(
+            "{}{} {}",
+            if breakpoint { "*" } else { " " },
+            if instruction.addr == state.pc {
+                "=>"
+            } else {
+                "  "
+            },
+            instruction.describe()
+        );
+        let style = if breakpoint {
+            Style::default().fg(Color::Red)
+        } else {
+            Style::default()
+        };
+        items.push(ListItem::new(Span::styled(text, style)));
+    }
+
+    let mut list_state = ListState::default();
+    list_state.select(selected);
+    let list = List::new(items)
+        .block(titled("Disassembly"))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    f.render_stateful_widget(list, area, &mut list_state);
+}
+
+fn draw_stack(f: &mut Frame<Backend>, area: Rect, state: &State, symbols: &Symbols) {
+    let lines: std::vec::Vec<Spans> = state
+        .stack
+        .chunks(2)
+        .enumerate()
+        .map(|(i, word)| {
+            let addr = state.registers.sp.wrapping_add(i as u16 * 2);
+            let value = (*word.get(1).unwrap_or(&0) as u16) << 8 | word[0] as u16;
+            // Without the bank there's no knowing which label switchable ROM addresses have.
+            let label = match value {
+                0x4000..=0x7FFF => None,
+                _ => symbols.name(value, Some(0)),
+            };
+            return Spans::from(match label {
+                Some(label) => format!("{:04X}  {:04X} {}", addr, value, label),
+                None => format!("{:04X}  {:04X}", addr, value),
+            });
+        })
+        .collect();
+    f.render_widget(Paragraph::new(lines).block(titled("Stack")), area);
+}
+
+fn draw_memory(f: &mut Frame<Backend>, area: Rect, state: &State) {
+    let lines: std::vec::Vec<Spans> = state
+        .memory
+        .chunks(16)
+        .enumerate()
+        .map(|(i, row)| {
+            let hex: std::vec::Vec<String> = row.iter().map(|b| format!("{:02X}", b)).collect();
+            let text: String = row
+                .iter()
+                .map(|&b| {
+                    if (0x20..0x7F).contains(&b) {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            let addr = state.memory_addr.wrapping_add(i as u16 * 16);
+            return Spans::from(format!("{:04X}  {}  {}", addr, hex.join(" "), text));
+        })
+        .collect();
+    f.render_widget(Paragraph::new(lines).block(titled("Memory")), area);
+}
+
+fn interrupts(value: u8) -> String {
+    let names = ["VBlank", "LCD", "Timer", "Serial", "Joypad"];
+    let set: std::vec::Vec<&str> = (0..5)
+        .filter(|i| value & (1 << i) != 0)
+        .map(|i| names[i])
+        .collect();
+    return set.join(" ");
+}
+
+// The IO registers worth looking at while debugging, decoded. |io| is FF00-FFFF.
+fn describe_io(io: &[u8]) -> std::vec::Vec<String> {
+    let reg = |addr: u16| io[(addr - 0xFF00) as usize];
+    let on = |value: u8, bit: u8| if value & (1 << bit) != 0 { "on" } else { "off" };
+
+    let lcdc = reg(0xFF40);
+    let stat = reg(0xFF41);
+    let tac = reg(0xFF07);
+    let modes = ["HBlank", "VBlank", "OAM scan", "Drawing"];
+    let frequencies = ["4096", "262144", "65536", "16384"];
+    return vec![
+        format!(
+            "LCDC {:02X}  LCD {}, BG {}, OBJ {} {}",
+            lcdc,
+            on(lcdc, 7),
+            on(lcdc, 0),
+            on(lcdc, 1),
+            if lcdc & 0x04 != 0 { "8x16" } else { "8x8" }
+        ),
+        format!(
+            "      window {} at {}, BG at {}, tiles at {}",
+            on(lcdc, 5),
+            if lcdc & 0x40 != 0 { "9C00" } else { "9800" },
+            if lcdc & 0x08 != 0 { "9C00" } else { "9800" },
+            if lcdc & 0x10 != 0 { "8000" } else { "8800" }
+        ),
+        format!(
+            "STAT {:02X}  mode {} {}{}",
+            stat,
+            stat & 0x03,
+            modes[(stat & 0x03) as usize],
+            if stat & 0x04 != 0 { ", LY=LYC" } else { "" }
+        ),
+        format!(
+            "      interrupts HBlank {}, VBlank {}, OAM {}, LYC {}",
+            on(stat, 3),
+            on(stat, 4),
+            on(stat, 5),
+            on(stat, 6)
+        ),
+        format!("LY   {:02X}  LYC {:02X}", reg(0xFF44), reg(0xFF45)),
+        format!("IE   {:02X}  {}", reg(0xFFFF), interrupts(reg(0xFFFF))),
+        format!("IF   {:02X}  {}", reg(0xFF0F), interrupts(reg(0xFF0F))),
+        format!(
+            "DIV  {:02X}  TIMA {:02X}  TMA {:02X}",
+            reg(0xFF04),
+            reg(0xFF05),
+            reg(0xFF06)
+        ),
+        format!(
+            "TAC  {:02X}  timer {}, {} Hz",
+            tac,
+            on(tac, 2),
+            frequencies[(tac & 0x03) as usize]
+        ),
+    ];
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::debugger_ui;
+
+    #[test]
+    fn describe_io() {
+        let mut io = vec![0; 0x100];
+        io[0x40] = 0x91;
+        io[0x41] = 0x85;
+        io[0x07] = 0x05;
+        io[0x0F] = 0xE1;
+        io[0xFF] = 0x05;
+
+        let lines = debugger_ui::describe_io(&io);
+        assert_eq!("LCDC 91  LCD on, BG on, OBJ off 8x8", lines[0]);
+        assert_eq!(
+            "      window off at 9800, BG at 9800, tiles at 8000",
+            lines[1]
+        );
+        assert_eq!("STAT 85  mode 1 VBlank, LY=LYC", lines[2]);
+        assert_eq!("IE   05  VBlank Timer", lines[5]);
+        assert_eq!("IF   E1  VBlank", lines[6]);
+        assert_eq!("TAC  05  timer on, 262144 Hz", lines[8]);
+    }
+}
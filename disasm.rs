@@ -0,0 +1,207 @@
+use crate::disasm_table;
+
+// Instructions can be decoded from anywhere, |read| is handed an address and gives back the
+// byte there. Usually that's the debugger's view of memory or a bank of a ROM file. |labels|
+// names addresses the same way, giving back the closest label and the offset from it.
+#[derive(Clone, Debug)]
+pub struct Instruction {
+    pub addr: u16,
+    pub bytes: std::vec::Vec<u8>,
+    pub text: String,
+    // When there's a label right at |addr|
+    pub label: Option<String>,
+}
+
+impl Instruction {
+    pub fn len(&self) -> u16 {
+        return self.bytes.len() as u16;
+    }
+
+    // "0150  20 FC     JR NZ,$014E"
+    pub fn describe(&self) -> String {
+        let bytes: std::vec::Vec<String> =
+            self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        return format!("{:04X}  {:<9} {}", self.addr, bytes.join(" "), self.text);
+    }
+}
+
+// Fills in the placeholders of the generated templates:
+// - {n8} and {n16} are immediates, {a16} an address and {a8} an offset from 0xFF00
+// - {e8} is a signed offset, {e8s} the same with its sign always shown (for SP+e8)
+// - {rel} is a JR offset, shown as the address it jumps to
+// Addresses get replaced with labels when there are any.
+fn fill_template(
+    template: &str,
+    addr: u16,
+    operands: &[u8],
+    labels: &dyn Fn(u16) -> Option<(String, u16)>,
+) -> String {
+    let byte = operands.first().copied().unwrap_or(0);
+    let word = (operands.get(1).copied().unwrap_or(0) as u16) << 8 | byte as u16;
+    let signed = |always_sign: bool| {
+        let offset = byte as i8;
+        if offset < 0 {
+            return format!("-${:02X}", -(offset as i16));
+        }
+        return format!("{}${:02X}", if always_sign { "+" } else { "" }, offset);
+    };
+    let address = |addr: u16, digits: usize| match labels(addr) {
+        Some((name, 0)) => name,
+        Some((name, offset)) => format!("{}+${:X}", name, offset),
+        None => format!("${:01$X}", addr, digits),
+    };
+
+    return template
+        .replace("{n8}", &format!("${:02X}", byte))
+        .replace("{n16}", &format!("${:04X}", word))
+        .replace("{a16}", &address(word, 4))
+        .replace("{a8}", &address(0xFF00 | byte as u16, 4))
+        .replace("{e8s}", &signed(true))
+        .replace("{e8}", &signed(false))
+        .replace(
+            "{rel}",
+            &address(addr.wrapping_add(2).wrapping_add(byte as i8 as u16), 4),
+        );
+}
+
+pub fn decode(
+    addr: u16,
+    read: &dyn Fn(u16) -> u8,
+    labels: &dyn Fn(u16) -> Option<(String, u16)>,
+) -> Instruction {
+    let label = match labels(addr) {
+        Some((name, 0)) => Some(name),
+        _ => None,
+    };
+
+    let opcode = read(addr);
+    if opcode == 0xCB {
+        let cb_opcode = read(addr.wrapping_add(1));
+        return Instruction {
+            addr: addr,
+            bytes: vec![opcode, cb_opcode],
+            text: disasm_table::CB_PREFIXED[cb_opcode as usize].to_string(),
+            label: label,
+        };
+    }
+
+    let (template, len) = disasm_table::UNPREFIXED[opcode as usize];
+    let bytes: std::vec::Vec<u8> = (0..len as u16)
+        .map(|i| read(addr.wrapping_add(i)))
+        .collect();
+    return Instruction {
+        addr: addr,
+        text: fill_template(template, addr, &bytes[1..], labels),
+        bytes: bytes,
+        label: label,
+    };
+}
+
+// |count| instructions one after the other, starting at |addr|.
+pub fn decode_range(
+    addr: u16,
+    count: usize,
+    read: &dyn Fn(u16) -> u8,
+    labels: &dyn Fn(u16) -> Option<(String, u16)>,
+) -> std::vec::Vec<Instruction> {
+    let mut ret = vec![];
+    let mut addr = addr;
+    for _ in 0..count {
+        let instruction = decode(addr, read, labels);
+        addr = addr.wrapping_add(instruction.len());
+        ret.push(instruction);
+    }
+    return ret;
+}
+
+// Instructions around |addr|, up to |before| of them before it. There's no telling where
+// instructions start going backwards, so this looks for the earliest starting point that
+// decodes into something landing right on |addr|. Usually that's right, but data mixed in
+// with the code can throw it off.
+pub fn decode_around(
+    addr: u16,
+    before: usize,
+    after: usize,
+    read: &dyn Fn(u16) -> u8,
+    labels: &dyn Fn(u16) -> Option<(String, u16)>,
+) -> std::vec::Vec<Instruction> {
+    // Instructions are at most 3 bytes long
+    let max_distance = (before * 3) as u16;
+    for distance in (1..=max_distance).rev() {
+        let start = addr.wrapping_sub(distance);
+        let mut instructions = vec![];
+        let mut current = start;
+        while current != addr && current.wrapping_sub(start) < distance {
+            let instruction = decode(current, read, labels);
+            current = current.wrapping_add(instruction.len());
+            instructions.push(instruction);
+        }
+
+        if current == addr && instructions.len() <= before {
+            instructions.extend(decode_range(addr, after, read, labels));
+            return instructions;
+        }
+    }
+
+    return decode_range(addr, after, read, labels);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::disasm;
+
+    fn no_labels(_addr: u16) -> Option<(String, u16)> {
+        return None;
+    }
+
+    fn text(bytes: &[u8]) -> String {
+        let read = |addr: u16| bytes.get(addr as usize - 0x0150).copied().unwrap_or(0);
+        return disasm::decode(0x0150, &read, &no_labels).text;
+    }
+
+    #[test]
+    fn decode() {
+        assert_eq!("LD A,(HL+)", text(&[0x2A]));
+        assert_eq!("JR NZ,$0150", text(&[0x20, 0xFE]));
+        assert_eq!("JR $0162", text(&[0x18, 0x10]));
+        assert_eq!("LD ($C000),SP", text(&[0x08, 0x00, 0xC0]));
+        assert_eq!("LDH ($FF44),A", text(&[0xE0, 0x44]));
+        assert_eq!("LD HL,SP-$02", text(&[0xF8, 0xFE]));
+        assert_eq!("ADD SP,$05", text(&[0xE8, 0x05]));
+        assert_eq!("RST $38", text(&[0xFF]));
+        assert_eq!("BIT 7,(HL)", text(&[0xCB, 0x7E]));
+        assert_eq!("DB $D3", text(&[0xD3]));
+    }
+
+    #[test]
+    fn decode_around() {
+        // NOP, LD A,$05, LD ($C100),A, JR $0000
+        let bytes = [0x00, 0x3E, 0x05, 0xEA, 0x00, 0xC1, 0x18, 0xF8];
+        let read = |addr: u16| bytes.get(addr as usize).copied().unwrap_or(0);
+
+        let instructions = disasm::decode_around(0x0006, 2, 1, &read, &no_labels);
+        let addrs: std::vec::Vec<u16> = instructions.iter().map(|i| i.addr).collect();
+        assert_eq!(vec![0x0001, 0x0003, 0x0006], addrs);
+        assert_eq!("0003  EA 00 C1  LD ($C100),A", instructions[1].describe());
+    }
+
+    #[test]
+    fn labels() {
+        // CALL $0158, LD ($C105),A, LDH A,($FF44)
+        let bytes = [0xCD, 0x58, 0x01, 0xEA, 0x05, 0xC1, 0xF0, 0x44];
+        let read = |addr: u16| bytes.get(addr as usize - 0x0150).copied().unwrap_or(0);
+        let labels = |addr: u16| match addr {
+            0x0150..=0x0157 => Some(("Main".to_string(), addr - 0x0150)),
+            0x0158 => Some(("Main.loop".to_string(), 0)),
+            0xC100..=0xC1FF => Some(("wBuffer".to_string(), addr - 0xC100)),
+            _ => None,
+        };
+
+        let instructions = disasm::decode_range(0x0150, 3, &read, &labels);
+        assert_eq!(Some("Main".to_string()), instructions[0].label);
+        assert_eq!("CALL Main.loop", instructions[0].text);
+        assert_eq!(None, instructions[1].label);
+        assert_eq!("LD (wBuffer+$5),A", instructions[1].text);
+        assert_eq!("LDH A,($FF44)", instructions[2].text);
+    }
+}
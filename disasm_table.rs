@@ -0,0 +1,521 @@
+// Generated by generate_opcodes.py from opcodes.json, don't edit by hand.
+
+// Text and length in bytes of every unprefixed instruction.
+pub const UNPREFIXED: [(&str, u8); 256] = [
+    ("NOP", 1),
+    ("LD BC,{n16}", 3),
+    ("LD (BC),A", 1),
+    ("INC BC", 1),
+    ("INC B", 1),
+    ("DEC B", 1),
+    ("LD B,{n8}", 2),
+    ("RLCA", 1),
+    ("LD ({a16}),SP", 3),
+    ("ADD HL,BC", 1),
+    ("LD A,(BC)", 1),
+    ("DEC BC", 1),
+    ("INC C", 1),
+    ("DEC C", 1),
+    ("LD C,{n8}", 2),
+    ("RRCA", 1),
+    ("STOP {n8}", 2),
+    ("LD DE,{n16}", 3),
+    ("LD (DE),A", 1),
+    ("INC DE", 1),
+    ("INC D", 1),
+    ("DEC D", 1),
+    ("LD D,{n8}", 2),
+    ("RLA", 1),
+    ("JR {rel}", 2),
+    ("ADD HL,DE", 1),
+    ("LD A,(DE)", 1),
+    ("DEC DE", 1),
+    ("INC E", 1),
+    ("DEC E", 1),
+    ("LD E,{n8}", 2),
+    ("RRA", 1),
+    ("JR NZ,{rel}", 2),
+    ("LD HL,{n16}", 3),
+    ("LD (HL+),A", 1),
+    ("INC HL", 1),
+    ("INC H", 1),
+    ("DEC H", 1),
+    ("LD H,{n8}", 2),
+    ("DAA", 1),
+    ("JR Z,{rel}", 2),
+    ("ADD HL,HL", 1),
+    ("LD A,(HL+)", 1),
+    ("DEC HL", 1),
+    ("INC L", 1),
+    ("DEC L", 1),
+    ("LD L,{n8}", 2),
+    ("CPL", 1),
+    ("JR NC,{rel}", 2),
+    ("LD SP,{n16}", 3),
+    ("LD (HL-),A", 1),
+    ("INC SP", 1),
+    ("INC (HL)", 1),
+    ("DEC (HL)", 1),
+    ("LD (HL),{n8}", 2),
+    ("SCF", 1),
+    ("JR C,{rel}", 2),
+    ("ADD HL,SP", 1),
+    ("LD A,(HL-)", 1),
+    ("DEC SP", 1),
+    ("INC A", 1),
+    ("DEC A", 1),
+    ("LD A,{n8}", 2),
+    ("CCF", 1),
+    ("LD B,B", 1),
+    ("LD B,C", 1),
+    ("LD B,D", 1),
+    ("LD B,E", 1),
+    ("LD B,H", 1),
+    ("LD B,L", 1),
+    ("LD B,(HL)", 1),
+    ("LD B,A", 1),
+    ("LD C,B", 1),
+    ("LD C,C", 1),
+    ("LD C,D", 1),
+    ("LD C,E", 1),
+    ("LD C,H", 1),
+    ("LD C,L", 1),
+    ("LD C,(HL)", 1),
+    ("LD C,A", 1),
+    ("LD D,B", 1),
+    ("LD D,C", 1),
+    ("LD D,D", 1),
+    ("LD D,E", 1),
+    ("LD D,H", 1),
+    ("LD D,L", 1),
+    ("LD D,(HL)", 1),
+    ("LD D,A", 1),
+    ("LD E,B", 1),
+    ("LD E,C", 1),
+    ("LD E,D", 1),
+    ("LD E,E", 1),
+    ("LD E,H", 1),
+    ("LD E,L", 1),
+    ("LD E,(HL)", 1),
+    ("LD E,A", 1),
+    ("LD H,B", 1),
+    ("LD H,C", 1),
+    ("LD H,D", 1),
+    ("LD H,E", 1),
+    ("LD H,H", 1),
+    ("LD H,L", 1),
+    ("LD H,(HL)", 1),
+    ("LD H,A", 1),
+    ("LD L,B", 1),
+    ("LD L,C", 1),
+    ("LD L,D", 1),
+    ("LD L,E", 1),
+    ("LD L,H", 1),
+    ("LD L,L", 1),
+    ("LD L,(HL)", 1),
+    ("LD L,A", 1),
+    ("LD (HL),B", 1),
+    ("LD (HL),C", 1),
+    ("LD (HL),D", 1),
+    ("LD (HL),E", 1),
+    ("LD (HL),H", 1),
+    ("LD (HL),L", 1),
+    ("HALT", 1),
+    ("LD (HL),A", 1),
+    ("LD A,B", 1),
+    ("LD A,C", 1),
+    ("LD A,D", 1),
+    ("LD A,E", 1),
+    ("LD A,H", 1),
+    ("LD A,L", 1),
+    ("LD A,(HL)", 1),
+    ("LD A,A", 1),
+    ("ADD A,B", 1),
+    ("ADD A,C", 1),
+    ("ADD A,D", 1),
+    ("ADD A,E", 1),
+    ("ADD A,H", 1),
+    ("ADD A,L", 1),
+    ("ADD A,(HL)", 1),
+    ("ADD A,A", 1),
+    ("ADC A,B", 1),
+    ("ADC A,C", 1),
+    ("ADC A,D", 1),
+    ("ADC A,E", 1),
+    ("ADC A,H", 1),
+    ("ADC A,L", 1),
+    ("ADC A,(HL)", 1),
+    ("ADC A,A", 1),
+    ("SUB A,B", 1),
+    ("SUB A,C", 1),
+    ("SUB A,D", 1),
+    ("SUB A,E", 1),
+    ("SUB A,H", 1),
+    ("SUB A,L", 1),
+    ("SUB A,(HL)", 1),
+    ("SUB A,A", 1),
+    ("SBC A,B", 1),
+    ("SBC A,C", 1),
+    ("SBC A,D", 1),
+    ("SBC A,E", 1),
+    ("SBC A,H", 1),
+    ("SBC A,L", 1),
+    ("SBC A,(HL)", 1),
+    ("SBC A,A", 1),
+    ("AND A,B", 1),
+    ("AND A,C", 1),
+    ("AND A,D", 1),
+    ("AND A,E", 1),
+    ("AND A,H", 1),
+    ("AND A,L", 1),
+    ("AND A,(HL)", 1),
+    ("AND A,A", 1),
+    ("XOR A,B", 1),
+    ("XOR A,C", 1),
+    ("XOR A,D", 1),
+    ("XOR A,E", 1),
+    ("XOR A,H", 1),
+    ("XOR A,L", 1),
+    ("XOR A,(HL)", 1),
+    ("XOR A,A", 1),
+    ("OR A,B", 1),
+    ("OR A,C", 1),
+    ("OR A,D", 1),
+    ("OR A,E", 1),
+    ("OR A,H", 1),
+    ("OR A,L", 1),
+    ("OR A,(HL)", 1),
+    ("OR A,A", 1),
+    ("CP A,B", 1),
+    ("CP A,C", 1),
+    ("CP A,D", 1),
+    ("CP A,E", 1),
+    ("CP A,H", 1),
+    ("CP A,L", 1),
+    ("CP A,(HL)", 1),
+    ("CP A,A", 1),
+    ("RET NZ", 1),
+    ("POP BC", 1),
+    ("JP NZ,{a16}", 3),
+    ("JP {a16}", 3),
+    ("CALL NZ,{a16}", 3),
+    ("PUSH BC", 1),
+    ("ADD A,{n8}", 2),
+    ("RST $00", 1),
+    ("RET Z", 1),
+    ("RET", 1),
+    ("JP Z,{a16}", 3),
+    ("PREFIX CB", 1),
+    ("CALL Z,{a16}", 3),
+    ("CALL {a16}", 3),
+    ("ADC A,{n8}", 2),
+    ("RST $08", 1),
+    ("RET NC", 1),
+    ("POP DE", 1),
+    ("JP NC,{a16}", 3),
+    ("DB $D3", 1),
+    ("CALL NC,{a16}", 3),
+    ("PUSH DE", 1),
+    ("SUB A,{n8}", 2),
+    ("RST $10", 1),
+    ("RET C", 1),
+    ("RETI", 1),
+    ("JP C,{a16}", 3),
+    ("DB $DB", 1),
+    ("CALL C,{a16}", 3),
+    ("DB $DD", 1),
+    ("SBC A,{n8}", 2),
+    ("RST $18", 1),
+    ("LDH ({a8}),A", 2),
+    ("POP HL", 1),
+    ("LD (C),A", 1),
+    ("DB $E3", 1),
+    ("DB $E4", 1),
+    ("PUSH HL", 1),
+    ("AND A,{n8}", 2),
+    ("RST $20", 1),
+    ("ADD SP,{e8}", 2),
+    ("JP HL", 1),
+    ("LD ({a16}),A", 3),
+    ("DB $EB", 1),
+    ("DB $EC", 1),
+    ("DB $ED", 1),
+    ("XOR A,{n8}", 2),
+    ("RST $28", 1),
+    ("LDH A,({a8})", 2),
+    ("POP AF", 1),
+    ("LD A,(C)", 1),
+    ("DI", 1),
+    ("DB $F4", 1),
+    ("PUSH AF", 1),
+    ("OR A,{n8}", 2),
+    ("RST $30", 1),
+    ("LD HL,SP{e8s}", 2),
+    ("LD SP,HL", 1),
+    ("LD A,({a16})", 3),
+    ("EI", 1),
+    ("DB $FC", 1),
+    ("DB $FD", 1),
+    ("CP A,{n8}", 2),
+    ("RST $38", 1),
+];
+
+// Instructions after the 0xCB prefix, all of them 2 bytes long counting the prefix.
+pub const CB_PREFIXED: [&str; 256] = [
+    "RLC B",
+    "RLC C",
+    "RLC D",
+    "RLC E",
+    "RLC H",
+    "RLC L",
+    "RLC (HL)",
+    "RLC A",
+    "RRC B",
+    "RRC C",
+    "RRC D",
+    "RRC E",
+    "RRC H",
+    "RRC L",
+    "RRC (HL)",
+    "RRC A",
+    "RL B",
+    "RL C",
+    "RL D",
+    "RL E",
+    "RL H",
+    "RL L",
+    "RL (HL)",
+    "RL A",
+    "RR B",
+    "RR C",
+    "RR D",
+    "RR E",
+    "RR H",
+    "RR L",
+    "RR (HL)",
+    "RR A",
+    "SLA B",
+    "SLA C",
+    "SLA D",
+    "SLA E",
+    "SLA H",
+    "SLA L",
+    "SLA (HL)",
+    "SLA A",
+    "SRA B",
+    "SRA C",
+    "SRA D",
+    "SRA E",
+    "SRA H",
+    "SRA L",
+    "SRA (HL)",
+    "SRA A",
+    "SWAP B",
+    "SWAP C",
+    "SWAP D",
+    "SWAP E",
+    "SWAP H",
+    "SWAP L",
+    "SWAP (HL)",
+    "SWAP A",
+    "SRL B",
+    "SRL C",
+    "SRL D",
+    "SRL E",
+    "SRL H",
+    "SRL L",
+    "SRL (HL)",
+    "SRL A",
+    "BIT 0,B",
+    "BIT 0,C",
+    "BIT 0,D",
+    "BIT 0,E",
+    "BIT 0,H",
+    "BIT 0,L",
+    "BIT 0,(HL)",
+    "BIT 0,A",
+    "BIT 1,B",
+    "BIT 1,C",
+    "BIT 1,D",
+    "BIT 1,E",
+    "BIT 1,H",
+    "BIT 1,L",
+    "BIT 1,(HL)",
+    "BIT 1,A",
+    "BIT 2,B",
+    "BIT 2,C",
+    "BIT 2,D",
+    "BIT 2,E",
+    "BIT 2,H",
+    "BIT 2,L",
+    "BIT 2,(HL)",
+    "BIT 2,A",
+    "BIT 3,B",
+    "BIT 3,C",
+    "BIT 3,D",
+    "BIT 3,E",
+    "BIT 3,H",
+    "BIT 3,L",
+    "BIT 3,(HL)",
+    "BIT 3,A",
+    "BIT 4,B",
+    "BIT 4,C",
+    "BIT 4,D",
+    "BIT 4,E",
+    "BIT 4,H",
+    "BIT 4,L",
+    "BIT 4,(HL)",
+    "BIT 4,A",
+    "BIT 5,B",
+    "BIT 5,C",
+    "BIT 5,D",
+    "BIT 5,E",
+    "BIT 5,H",
+    "BIT 5,L",
+    "BIT 5,(HL)",
+    "BIT 5,A",
+    "BIT 6,B",
+    "BIT 6,C",
+    "BIT 6,D",
+    "BIT 6,E",
+    "BIT 6,H",
+    "BIT 6,L",
+    "BIT 6,(HL)",
+    "BIT 6,A",
+    "BIT 7,B",
+    "BIT 7,C",
+    "BIT 7,D",
+    "BIT 7,E",
+    "BIT 7,H",
+    "BIT 7,L",
+    "BIT 7,(HL)",
+    "BIT 7,A",
+    "RES 0,B",
+    "RES 0,C",
+    "RES 0,D",
+    "RES 0,E",
+    "RES 0,H",
+    "RES 0,L",
+    "RES 0,(HL)",
+    "RES 0,A",
+    "RES 1,B",
+    "RES 1,C",
+    "RES 1,D",
+    "RES 1,E",
+    "RES 1,H",
+    "RES 1,L",
+    "RES 1,(HL)",
+    "RES 1,A",
+    "RES 2,B",
+    "RES 2,C",
+    "RES 2,D",
+    "RES 2,E",
+    "RES 2,H",
+    "RES 2,L",
+    "RES 2,(HL)",
+    "RES 2,A",
+    "RES 3,B",
+    "RES 3,C",
+    "RES 3,D",
+    "RES 3,E",
+    "RES 3,H",
+    "RES 3,L",
+    "RES 3,(HL)",
+    "RES 3,A",
+    "RES 4,B",
+    "RES 4,C",
+    "RES 4,D",
+    "RES 4,E",
+    "RES 4,H",
+    "RES 4,L",
+    "RES 4,(HL)",
+    "RES 4,A",
+    "RES 5,B",
+    "RES 5,C",
+    "RES 5,D",
+    "RES 5,E",
+    "RES 5,H",
+    "RES 5,L",
+    "RES 5,(HL)",
+    "RES 5,A",
+    "RES 6,B",
+    "RES 6,C",
+    "RES 6,D",
+    "RES 6,E",
+    "RES 6,H",
+    "RES 6,L",
+    "RES 6,(HL)",
+    "RES 6,A",
+    "RES 7,B",
+    "RES 7,C",
+    "RES 7,D",
+    "RES 7,E",
+    "RES 7,H",
+    "RES 7,L",
+    "RES 7,(HL)",
+    "RES 7,A",
+    "SET 0,B",
+    "SET 0,C",
+    "SET 0,D",
+    "SET 0,E",
+    "SET 0,H",
+    "SET 0,L",
+    "SET 0,(HL)",
+    "SET 0,A",
+    "SET 1,B",
+    "SET 1,C",
+    "SET 1,D",
+    "SET 1,E",
+    "SET 1,H",
+    "SET 1,L",
+    "SET 1,(HL)",
+    "SET 1,A",
+    "SET 2,B",
+    "SET 2,C",
+    "SET 2,D",
+    "SET 2,E",
+    "SET 2,H",
+    "SET 2,L",
+    "SET 2,(HL)",
+    "SET 2,A",
+    "SET 3,B",
+    "SET 3,C",
+    "SET 3,D",
+    "SET 3,E",
+    "SET 3,H",
+    "SET 3,L",
+    "SET 3,(HL)",
+    "SET 3,A",
+    "SET 4,B",
+    "SET 4,C",
+    "SET 4,D",
+    "SET 4,E",
+    "SET 4,H",
+    "SET 4,L",
+    "SET 4,(HL)",
+    "SET 4,A",
+    "SET 5,B",
+    "SET 5,C",
+    "SET 5,D",
+    "SET 5,E",
+    "SET 5,H",
+    "SET 5,L",
+    "SET 5,(HL)",
+    "SET 5,A",
+    "SET 6,B",
+    "SET 6,C",
+    "SET 6,D",
+    "SET 6,E",
+    "SET 6,H",
+    "SET 6,L",
+    "SET 6,(HL)",
+    "SET 6,A",
+    "SET 7,B",
+    "SET 7,C",
+    "SET 7,D",
+    "SET 7,E",
+    "SET 7,H",
+    "SET 7,L",
+    "SET 7,(HL)",
+    "SET 7,A",
+];
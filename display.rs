@@ -1,4 +1,7 @@
+use crate::palette;
+
 use sdl2::pixels::Color;
+use sdl2::pixels::PixelFormatEnum;
 
 pub struct Display {
     pub c: sdl2::render::WindowCanvas,
@@ -35,7 +38,28 @@ impl Display {
         return Display { c: canvas };
     }
 
-    pub fn present(&mut self) {
+    pub fn window_id(&self) -> u32 {
+        return self.c.window().id();
+    }
+
+    pub fn set_title(&mut self, title: &str) {
+        self.c.window_mut().set_title(title).unwrap();
+    }
+
+    // Same as draw_rgb for 0xRRGGBB pixels.
+    pub fn draw_frame(&mut self, width: u32, height: u32, pixels: &[u32]) {
+        self.draw_rgb(width, height, &palette::to_rgb24(pixels));
+    }
+
+    // Draws a whole RGB24 picture in one go and presents it, a lot faster than going point by
+    // point for anything big.
+    pub fn draw_rgb(&mut self, width: u32, height: u32, pixels: &[u8]) {
+        let texture_creator = self.c.texture_creator();
+        let mut texture = texture_creator
+            .create_texture_streaming(PixelFormatEnum::RGB24, width, height)
+            .unwrap();
+        texture.update(None, pixels, (width * 3) as usize).unwrap();
+        self.c.copy(&texture, None, None).unwrap();
         self.c.present();
     }
 }
@@ -0,0 +1,667 @@
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use crate::breakpoint::Breakpoint;
+use crate::debug::RemoteDebugMessage;
+use crate::debug::RemoteDebugMessageResponse;
+use crate::debug::Stop;
+use crate::debug::StopReason;
+use crate::registers::Registers;
+use crate::watchpoint::WatchKind;
+use crate::watchpoint::Watchpoint;
+
+// gdb has no SM83 of its own, gbz80 is what binutils calls it. The registers are the 16 bit
+// pairs, in the order of the 'g' packet.
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <architecture>gbz80</architecture>
+  <feature name="org.yagbe.sm83">
+    <reg name="af" bitsize="16" type="uint16"/>
+    <reg name="bc" bitsize="16" type="uint16"/>
+    <reg name="de" bitsize="16" type="uint16"/>
+    <reg name="hl" bitsize="16" type="uint16"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+// What '?' gets before the console has stopped for gdb: SIGTRAP, no reason.
+const STOPPED: &str = "T05";
+
+// The biggest packet gdb may send, and what replies are kept to. Advertised in qSupported.
+const PACKET_SIZE: usize = 0x1000;
+
+// A single instruction can't take this long, unless the CPU is halted or stopped.
+const STEP_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Lets gdb, or anything else that speaks the remote serial protocol, debug the console:
+//   (gdb) target remote localhost:<port>
+// Takes one client at a time, over the same channels as DebuggerRemote.
+pub struct GdbStub {
+    sender: mpsc::Sender<RemoteDebugMessage>,
+    receiver: mpsc::Receiver<RemoteDebugMessageResponse>,
+    listener: TcpListener,
+    client: Option<TcpStream>,
+    // What came in and hasn't made a full packet yet.
+    buffer: std::vec::Vec<u8>,
+    no_ack: bool,
+    // Set after continuing, the stop reply goes out once the console stops.
+    waiting_for_stop: bool,
+    // The reply to '?'.
+    last_stop: String,
+    // Where Z1 put breakpoints, so hitting one can be told apart from a Z0.
+    hardware_breakpoints: std::vec::Vec<u16>,
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    return data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+}
+
+// Takes the next packet out of |buffer|: Ok with its contents, or Err when the checksum is
+// off. Ctrl-C comes in as a lone 0x03 byte and is given back as "\x03".
+fn next_packet(buffer: &mut std::vec::Vec<u8>) -> Option<Result<String, ()>> {
+    // Acks, we never resend anything
+    while buffer.first().is_some_and(|&b| b == b'+' || b == b'-') {
+        buffer.remove(0);
+    }
+    if buffer.first() == Some(&0x03) {
+        buffer.remove(0);
+        return Some(Ok("\x03".to_string()));
+    }
+
+    let start = buffer.iter().position(|&b| b == b'$')?;
+    let end = buffer.iter().skip(start).position(|&b| b == b'#')? + start;
+    if buffer.len() < end + 3 {
+        return None;
+    }
+
+    let data = buffer[start + 1..end].to_vec();
+    let expected = std::str::from_utf8(&buffer[end + 1..end + 3])
+        .ok()
+        .and_then(|cs| u8::from_str_radix(cs, 16).ok());
+    buffer.drain(..end + 3);
+
+    if expected != Some(checksum(&data)) {
+        return Some(Err(()));
+    }
+    return Some(Ok(String::from_utf8_lossy(&data).to_string()));
+}
+
+fn frame(data: &str) -> String {
+    return format!("${}#{:02x}", data, checksum(data.as_bytes()));
+}
+
+fn hex(bytes: &[u8]) -> String {
+    return bytes.iter().map(|b| format!("{:02x}", b)).collect();
+}
+
+fn parse_hex(text: &str) -> Option<std::vec::Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    return (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect();
+}
+
+// "addr,len" and the like, all hex.
+fn parse_numbers(text: &str) -> Option<std::vec::Vec<usize>> {
+    return text
+        .split(',')
+        .map(|n| usize::from_str_radix(n, 16).ok())
+        .collect();
+}
+
+// What an 'm' packet for |len| bytes at |addr| can actually read: nothing past 0xFFFF, and
+// no more than fits in a reply, two hex digits a byte. gdb asks again for whatever's missing.
+fn memory_read_range(addr: usize, len: usize) -> Option<(u16, usize)> {
+    if addr > 0xFFFF {
+        return None;
+    }
+    let len = len.min(PACKET_SIZE / 2).min(0x10000 - addr);
+    return Some((addr as u16, len));
+}
+
+// The T packet for |stop|. It's SIGTRAP other than for a pause, which gdb asked for with
+// Ctrl-C. Watchpoints say which kind they are, from |watch_kind|, and the address that was
+// accessed.
+fn stop_reply(stop: &Stop, watch_kind: Option<WatchKind>, hardware_breakpoints: &[u16]) -> String {
+    return match &stop.reason {
+        StopReason::Paused => "T02".to_string(),
+        StopReason::Breakpoint(_) if hardware_breakpoints.contains(&stop.pc) => {
+            "T05hwbreak:;".to_string()
+        }
+        StopReason::Breakpoint(_) => "T05swbreak:;".to_string(),
+        StopReason::Watchpoint(_, access, _) => {
+            let kind = match watch_kind {
+                Some(WatchKind::Write) => "watch",
+                Some(WatchKind::Read) => "rwatch",
+                _ => "awatch",
+            };
+            format!("T05{}:{:x};", kind, access.addr)
+        }
+        _ => "T05".to_string(),
+    };
+}
+
+fn register_values(registers: &Registers) -> [u16; 6] {
+    return [
+        registers.af,
+        registers.bc,
+        registers.de,
+        registers.hl,
+        registers.sp,
+        registers.pc,
+    ];
+}
+
+fn set_register(registers: &mut Registers, n: usize, value: u16) -> bool {
+    match n {
+        0 => registers.af = value,
+        1 => registers.bc = value,
+        2 => registers.de = value,
+        3 => registers.hl = value,
+        4 => registers.sp = value,
+        5 => registers.pc = value,
+        _ => return false,
+    }
+    return true;
+}
+
+impl GdbStub {
+    pub fn new(
+        sender: mpsc::Sender<RemoteDebugMessage>,
+        receiver: mpsc::Receiver<RemoteDebugMessageResponse>,
+        port: u16,
+    ) -> Result<GdbStub, String> {
+        let listener = TcpListener::bind(("127.0.0.1", port))
+            .map_err(|e| format!("Can't listen on port {}: {}", port, e))?;
+        listener
+            .set_nonblocking(true)
+            .map_err(|e| format!("Can't listen on port {}: {}", port, e))?;
+        println!("Waiting for gdb on port {}", port);
+
+        return Ok(GdbStub {
+            sender: sender,
+            receiver: receiver,
+            listener: listener,
+            client: None,
+            buffer: vec![],
+            no_ack: false,
+            waiting_for_stop: false,
+            last_stop: STOPPED.to_string(),
+            hardware_breakpoints: vec![],
+        });
+    }
+
+    // Answers everything the client sent since the last update.
+    pub fn update(&mut self) {
+        if self.client.is_none() && !self.accept() {
+            return;
+        }
+
+        loop {
+            if self.waiting_for_stop {
+                if let Ok(RemoteDebugMessageResponse::Stopped(stop)) = self.receiver.try_recv() {
+                    self.waiting_for_stop = false;
+                    let reply = self.stop_reply(&stop);
+                    self.send_packet(&reply);
+                }
+            }
+
+            let mut bytes = [0u8; 4096];
+            let read = match self.client.as_mut() {
+                Some(client) => client.read(&mut bytes),
+                None => return,
+            };
+            match read {
+                Ok(0) => {
+                    println!("gdb disconnected");
+                    self.client = None;
+                    return;
+                }
+                Ok(len) => self.buffer.extend_from_slice(&bytes[..len]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => return,
+                Err(e) => {
+                    println!("gdb connection failed: {}", e);
+                    self.client = None;
+                    return;
+                }
+            }
+
+            while let Some(packet) = next_packet(&mut self.buffer) {
+                match packet {
+                    Ok(packet) => {
+                        if packet != "\x03" && !self.no_ack {
+                            self.write(b"+");
+                        }
+                        if let Some(reply) = self.handle(&packet) {
+                            self.send_packet(&reply);
+                        }
+                    }
+                    Err(()) => self.write(b"-"),
+                }
+            }
+        }
+    }
+
+    fn accept(&mut self) -> bool {
+        let (client, addr) = match self.listener.accept() {
+            Ok(connection) => connection,
+            Err(_) => return false,
+        };
+        // Reads wait a little so packets that come one after the other get handled in the
+        // same update.
+        let configured = client
+            .set_nonblocking(false)
+            .and_then(|_| client.set_read_timeout(Some(Duration::from_millis(50))))
+            .and_then(|_| client.set_nodelay(true));
+        if let Err(e) = configured {
+            println!("gdb connection failed: {}", e);
+            return false;
+        }
+
+        // The last client left while the console ran. Its stop reply would otherwise come back
+        // as the answer to one of this client's requests.
+        if self.waiting_for_stop {
+            self.sender.send(RemoteDebugMessage::Pause).unwrap();
+            self.receiver.recv().unwrap();
+        }
+
+        println!("gdb connected from {}", addr);
+        self.client = Some(client);
+        self.buffer.clear();
+        self.no_ack = false;
+        self.waiting_for_stop = false;
+        self.last_stop = STOPPED.to_string();
+        self.hardware_breakpoints.clear();
+        return true;
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        if let Some(client) = self.client.as_mut() {
+            if client.write_all(data).is_err() {
+                self.client = None;
+            }
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) {
+        self.write(frame(data).as_bytes());
+    }
+
+    fn request(&self, message: RemoteDebugMessage) -> RemoteDebugMessageResponse {
+        self.sender.send(message).unwrap();
+        return self.receiver.recv().unwrap();
+    }
+
+    // Also remembers the reply for '?'.
+    fn stop_reply(&mut self, stop: &Stop) -> String {
+        let watch_kind = match stop.reason {
+            StopReason::Watchpoint(id, _, _) => {
+                match self.request(RemoteDebugMessage::RequestWatchpoints) {
+                    RemoteDebugMessageResponse::RequestWatchpoints(watchpoints) => {
+                        watchpoints.iter().find(|w| w.id == id).map(|w| w.kind)
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        self.last_stop = stop_reply(stop, watch_kind, &self.hardware_breakpoints);
+        return self.last_stop.clone();
+    }
+
+    fn registers(&self) -> Registers {
+        return match self.request(RemoteDebugMessage::RequestRegisters) {
+            RemoteDebugMessageResponse::RequestRegisters(registers) => registers,
+            _ => Registers::default(),
+        };
+    }
+
+    fn step(&self) -> Option<Stop> {
+        self.sender.send(RemoteDebugMessage::Step).unwrap();
+        let stopped = match self.receiver.recv_timeout(STEP_TIMEOUT) {
+            Ok(stopped) => stopped,
+            Err(_) => {
+                self.sender.send(RemoteDebugMessage::Pause).unwrap();
+                self.receiver.recv().unwrap()
+            }
+        };
+        return match stopped {
+            RemoteDebugMessageResponse::Stopped(mut stop) => {
+                // Still a step as far as gdb is concerned, even if it had to be paused.
+                if let StopReason::Paused = stop.reason {
+                    stop.reason = StopReason::Step;
+                }
+                Some(stop)
+            }
+            _ => None,
+        };
+    }
+
+    // The reply to |packet|, None when there isn't one (yet).
+    fn handle(&mut self, packet: &str) -> Option<String> {
+        // Not split_at(1), which panics on an empty packet or one that starts with a
+        // multibyte character. Neither is a command this knows.
+        let (command, args) = match packet.get(..1) {
+            Some(command) => (command, &packet[1..]),
+            None => return Some(String::new()),
+        };
+        let reply = match command {
+            "\x03" => {
+                // Already stopped, so pausing won't get a reply.
+                if !self.waiting_for_stop {
+                    return Some(self.last_stop.clone());
+                }
+                self.sender.send(RemoteDebugMessage::Pause).unwrap();
+                return None;
+            }
+            "?" => self.last_stop.clone(),
+            "g" => {
+                let registers = register_values(&self.registers());
+                let bytes: std::vec::Vec<u8> =
+                    registers.iter().flat_map(|r| r.to_le_bytes()).collect();
+                hex(&bytes)
+            }
+            "G" => match parse_hex(args) {
+                Some(bytes) if bytes.len() == 12 => {
+                    let mut registers = self.registers();
+                    for n in 0..6 {
+                        let value = u16::from_le_bytes([bytes[n * 2], bytes[n * 2 + 1]]);
+                        set_register(&mut registers, n, value);
+                    }
+                    self.sender
+                        .send(RemoteDebugMessage::SetRegisters(registers))
+                        .unwrap();
+                    "OK".to_string()
+                }
+                _ => "E01".to_string(),
+            },
+            "p" => {
+                let registers = register_values(&self.registers());
+                match usize::from_str_radix(args, 16)
+                    .ok()
+                    .and_then(|n| registers.get(n))
+                {
+                    Some(value) => hex(&value.to_le_bytes()),
+                    None => "E01".to_string(),
+                }
+            }
+            "P" => {
+                let (n, value) = match args.split_once('=') {
+                    Some(split) => split,
+                    None => return Some("E01".to_string()),
+                };
+                let n = usize::from_str_radix(n, 16).ok();
+                let value = parse_hex(value).filter(|v| v.len() == 2);
+                let mut registers = self.registers();
+                match (n, value) {
+                    (Some(n), Some(value))
+                        if set_register(
+                            &mut registers,
+                            n,
+                            u16::from_le_bytes([value[0], value[1]]),
+                        ) =>
+                    {
+                        self.sender
+                            .send(RemoteDebugMessage::SetRegisters(registers))
+                            .unwrap();
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "m" => match parse_numbers(args).as_deref() {
+                Some(&[addr, len]) => match memory_read_range(addr, len) {
+                    Some((addr, len)) => {
+                        match self.request(RemoteDebugMessage::RequestMemory(addr, len)) {
+                            RemoteDebugMessageResponse::RequestMemory(_, bytes) => hex(&bytes),
+                            _ => "E01".to_string(),
+                        }
+                    }
+                    None => "E01".to_string(),
+                },
+                _ => "E01".to_string(),
+            },
+            "M" => {
+                let (location, data) = match args.split_once(':') {
+                    Some(split) => split,
+                    None => return Some("E01".to_string()),
+                };
+                match (parse_numbers(location).as_deref(), parse_hex(data)) {
+                    (Some(&[addr, len]), Some(bytes)) if bytes.len() == len && addr <= 0xFFFF => {
+                        self.sender
+                            .send(RemoteDebugMessage::WriteMemory(addr as u16, bytes))
+                            .unwrap();
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "Z" | "z" => self.handle_breakpoint(command == "Z", args),
+            "s" => match self.step() {
+                Some(stop) => self.stop_reply(&stop),
+                None => STOPPED.to_string(),
+            },
+            "c" => {
+                self.sender.send(RemoteDebugMessage::Continue).unwrap();
+                self.waiting_for_stop = true;
+                return None;
+            }
+            "k" => {
+                self.sender.send(RemoteDebugMessage::Quit).unwrap();
+                self.client = None;
+                return None;
+            }
+            "D" => {
+                self.sender.send(RemoteDebugMessage::Resume).unwrap();
+                self.send_packet("OK");
+                println!("gdb detached");
+                self.client = None;
+                return None;
+            }
+            // There's only the one thread
+            "H" | "T" => "OK".to_string(),
+            "q" | "Q" => self.handle_query(packet),
+            _ => String::new(),
+        };
+        return Some(reply);
+    }
+
+    fn handle_query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return format!(
+                "PacketSize={:x};qXfer:features:read+;QStartNoAckMode+;swbreak+;hwbreak+",
+                PACKET_SIZE
+            );
+        }
+        if packet == "QStartNoAckMode" {
+            // This one still gets acked, everything after doesn't.
+            self.no_ack = true;
+            return "OK".to_string();
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            return match parse_numbers(range).as_deref() {
+                Some(&[offset, len]) => {
+                    let xml = TARGET_XML.as_bytes();
+                    let start = offset.min(xml.len());
+                    let end = (offset + len).min(xml.len());
+                    let more = if end < xml.len() { "m" } else { "l" };
+                    format!("{}{}", more, String::from_utf8_lossy(&xml[start..end]))
+                }
+                _ => "E01".to_string(),
+            };
+        }
+        return match packet {
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            "qSymbol::" => "OK".to_string(),
+            _ => String::new(),
+        };
+    }
+
+    // Z0/Z1 are software and hardware breakpoints, which are the same thing here. Z2, Z3 and
+    // Z4 are write, read and access watchpoints. z removes them.
+    fn handle_breakpoint(&mut self, insert: bool, args: &str) -> String {
+        let (kind, location) = match args.split_once(',') {
+            Some(split) => split,
+            None => return "E01".to_string(),
+        };
+        // Watched ranges stop at the end of memory instead of wrapping around.
+        let (addr, end) = match parse_numbers(location).as_deref() {
+            Some(&[addr, len]) if addr <= 0xFFFF => {
+                let end = addr.saturating_add(len.max(1) - 1).min(0xFFFF);
+                (addr as u16, end as u16)
+            }
+            _ => return "E01".to_string(),
+        };
+        let watch_kind = match kind {
+            "0" | "1" => None,
+            "2" => Some(WatchKind::Write),
+            "3" => Some(WatchKind::Read),
+            "4" => Some(WatchKind::Access),
+            _ => return String::new(),
+        };
+
+        match (watch_kind, insert) {
+            (None, true) => {
+                if kind == "1" {
+                    self.hardware_breakpoints.push(addr);
+                }
+                let breakpoint = Breakpoint::new(addr, None, None);
+                self.sender
+                    .send(RemoteDebugMessage::AddBreakpoint(breakpoint))
+                    .unwrap();
+            }
+            (Some(kind), true) => {
+                let watchpoint = Watchpoint::new(addr, end, kind, None);
+                self.sender
+                    .send(RemoteDebugMessage::AddWatchpoint(watchpoint))
+                    .unwrap();
+            }
+            (None, false) => {
+                self.hardware_breakpoints.retain(|&a| a != addr);
+                let id = match self.request(RemoteDebugMessage::RequestBreakpoints) {
+                    RemoteDebugMessageResponse::RequestBreakpoints(breakpoints) => breakpoints
+                        .iter()
+                        .find(|b| b.addr == addr && b.bank.is_none() && b.condition.is_none())
+                        .map(|b| b.id),
+                    _ => None,
+                };
+                if let Some(id) = id {
+                    self.sender
+                        .send(RemoteDebugMessage::RemoveBreakpoint(id))
+                        .unwrap();
+                }
+            }
+            (Some(kind), false) => {
+                let id = match self.request(RemoteDebugMessage::RequestWatchpoints) {
+                    RemoteDebugMessageResponse::RequestWatchpoints(watchpoints) => watchpoints
+                        .iter()
+                        .find(|w| w.start == addr && w.end == end && w.kind == kind)
+                        .map(|w| w.id),
+                    _ => None,
+                };
+                if let Some(id) = id {
+                    self.sender
+                        .send(RemoteDebugMessage::RemoveBreakpoint(id))
+                        .unwrap();
+                }
+            }
+        }
+        return "OK".to_string();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::debug::Stop;
+    use crate::debug::StopReason;
+    use crate::gdb;
+    use crate::watchpoint::Access;
+    use crate::watchpoint::WatchKind;
+
+    #[test]
+    fn packets() {
+        let mut buffer = b"+$g#67$m150,3#ff".to_vec();
+        assert_eq!(Some(Ok("g".to_string())), gdb::next_packet(&mut buffer));
+        // Bad checksum
+        assert_eq!(Some(Err(())), gdb::next_packet(&mut buffer));
+        assert_eq!(None, gdb::next_packet(&mut buffer));
+
+        // Split across reads
+        let mut buffer = b"$qSupp".to_vec();
+        assert_eq!(None, gdb::next_packet(&mut buffer));
+        buffer.extend_from_slice(b"orted#37\x03");
+        assert_eq!(
+            Some(Ok("qSupported".to_string())),
+            gdb::next_packet(&mut buffer)
+        );
+        assert_eq!(Some(Ok("\x03".to_string())), gdb::next_packet(&mut buffer));
+
+        assert_eq!("$OK#9a", gdb::frame("OK"));
+        assert_eq!(Some(vec![0x01, 0xB0]), gdb::parse_hex("01b0"));
+    }
+
+    #[test]
+    fn memory_read_range() {
+        assert_eq!(Some((0x0150, 3)), gdb::memory_read_range(0x0150, 3));
+        assert_eq!(
+            Some((0xC000, 0x800)),
+            gdb::memory_read_range(0xC000, 0x4000)
+        );
+        assert_eq!(Some((0xFFF0, 0x10)), gdb::memory_read_range(0xFFF0, 0x100));
+        assert_eq!(None, gdb::memory_read_range(0x10000, 1));
+    }
+
+    #[test]
+    fn stop_reply() {
+        let stop = |reason| Stop {
+            reason: reason,
+            pc: 0x0150,
+            bank: None,
+        };
+        let access = Access {
+            addr: 0xC010,
+            value: 1,
+            old: 0,
+            write: true,
+        };
+
+        assert_eq!("T05", gdb::stop_reply(&stop(StopReason::Step), None, &[]));
+        assert_eq!("T02", gdb::stop_reply(&stop(StopReason::Paused), None, &[]));
+        assert_eq!(
+            "T05swbreak:;",
+            gdb::stop_reply(&stop(StopReason::Breakpoint(1)), None, &[])
+        );
+        assert_eq!(
+            "T05hwbreak:;",
+            gdb::stop_reply(&stop(StopReason::Breakpoint(1)), None, &[0x0150])
+        );
+        let watchpoint = stop(StopReason::Watchpoint(2, access, 0x0150));
+        assert_eq!(
+            "T05watch:c010;",
+            gdb::stop_reply(&watchpoint, Some(WatchKind::Write), &[])
+        );
+        assert_eq!(
+            "T05rwatch:c010;",
+            gdb::stop_reply(&watchpoint, Some(WatchKind::Read), &[])
+        );
+        assert_eq!(
+            "T05awatch:c010;",
+            gdb::stop_reply(&watchpoint, Some(WatchKind::Access), &[])
+        );
+    }
+}
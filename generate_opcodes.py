@@ -131,5 +131,69 @@ def generate():
 
 		f.writelines(s + '\n' for s in lines)
 
+# Each instruction's text, with placeholders for whatever comes from the bytes after the opcode.
+# See disasm.rs for what they turn into.
+def disasm_template(v):
+	mnemonic = v["mnemonic"]
+	if mnemonic[0:7].lower() == "illegal":
+		return "DB $" + mnemonic[8:]
+	if mnemonic[0:6].lower() == "prefix":
+		return "PREFIX CB"
+
+	operands = []
+	for o in v["operands"]:
+		name = o["name"]
+		if name == "n8" or name == "n16" or name == "a16":
+			text = "{" + name + "}"
+		elif name == "a8":
+			text = "{a8}"
+		elif name == "e8" and mnemonic == "JR":
+			text = "{rel}"
+		elif name == "e8" and len(operands) > 0 and operands[-1] == "SP+":
+			# LD HL,SP+e8, glued to the SP operand
+			operands[-1] = "SP{e8s}"
+			continue
+		elif name == "e8":
+			text = "{e8}"
+		elif name[0] == "$":
+			text = "$" + name[1:]
+		else:
+			text = name
+		if "increment" in o and o["increment"]:
+			text = text + "+"
+		if "decrement" in o and o["decrement"]:
+			text = text + "-"
+		if not o["immediate"]:
+			text = "(" + text + ")"
+		operands.append(text)
+
+	if len(operands) == 0:
+		return mnemonic
+	return mnemonic + " " + ",".join(operands)
+
+def generate_disasm_table():
+	with open('opcodes.json', 'r') as f:
+		parsed = json.load(f)
+
+	with open('disasm_table.rs', 'w') as f:
+		lines = [
+			"// Generated by generate_opcodes.py from opcodes.json, don't edit by hand.",
+			"",
+			"// Text and length in bytes of every unprefixed instruction.",
+			"pub const UNPREFIXED: [(&str, u8); 256] = [",
+		]
+		for k, v in parsed["unprefixed"].items():
+			lines.append("	(\"" + disasm_template(v) + "\", " + str(v["bytes"]) + "),")
+		lines.append("];")
+		lines.append("")
+		lines.append("// Instructions after the 0xCB prefix, all of them 2 bytes long counting the prefix.")
+		lines.append("pub const CB_PREFIXED: [&str; 256] = [")
+		for k, v in parsed["cbprefixed"].items():
+			lines.append("	\"" + disasm_template(v) + "\",")
+		lines.append("];")
+
+		f.writelines(s + '\n' for s in lines)
+
 if __name__ == "__main__":
-	generate()
\ No newline at end of file
+	generate()
+	generate_disasm_table()
\ No newline at end of file
@@ -1,9 +1,11 @@
 use crate::memory::Memory;
+use crate::sgb::PacketReader;
 
 use std::vec::Vec;
 
 use sdl2::keyboard::Keycode;
 
+#[derive(Clone, Copy)]
 pub enum Button {
     A = 0,
     B = 1,
@@ -15,9 +17,22 @@ pub enum Button {
     Left = 7,
 }
 
+// The SGB takes up to 4 controllers, each player gets their own keys:
+//   1: arrows, A, S (B), Z (Start), X (Select)
+//   2: I, J, K, L, period (A), comma (B), M (Start), N (Select)
+//   3: T, F, G, H, R (A), E (B), W (Start), Q (Select)
+//   4: keypad 8, 4, 5, 6, 0 (A), period (B), enter (Start), plus (Select)
+const PLAYERS: usize = 4;
+
 pub struct Joypad {
-    buttons: Vec<bool>,
+    // Held buttons for each player, indexed by Button.
+    buttons: Vec<[bool; 8]>,
     last_button_set_select_state: u8,
+    // Only there when the SGB is listening for packets.
+    sgb_packets: Option<PacketReader>,
+    // SGB multiplayer, set through MLT_REQ. P1 shows the buttons of |current_player|.
+    players: u8,
+    current_player: u8,
 }
 
 impl Joypad {
@@ -25,8 +40,8 @@ impl Joypad {
         let button = Joypad::keycode_to_button(keycode);
 
         match button {
-            Some(b) => {
-                self.button_pressed(b, memory);
+            Some((player, b)) => {
+                self.button_pressed(player, b, memory);
             }
             None => {}
         };
@@ -36,24 +51,62 @@ impl Joypad {
         let button = Joypad::keycode_to_button(keycode);
 
         match button {
-            Some(b) => {
-                self.button_released(b, memory);
+            Some((player, b)) => {
+                self.button_released(player, b, memory);
             }
             None => {}
         };
     }
 
-    fn keycode_to_button(keycode: Keycode) -> Option<Button> {
+    // Bit N is set when Button N is held by player 1.
+    pub fn pressed(&self) -> u8 {
+        let mut ret = 0;
+        for (i, pressed) in self.buttons[0].iter().enumerate() {
+            if *pressed {
+                ret |= 1 << i;
+            }
+        }
+        return ret;
+    }
+
+    // The player the key belongs to (0~3) and which of their buttons it is.
+    fn keycode_to_button(keycode: Keycode) -> Option<(usize, Button)> {
         return match keycode {
-            Keycode::A => Some(Button::A),
-            Keycode::S => Some(Button::B),
-            Keycode::Z => Some(Button::Start),
-            Keycode::X => Some(Button::Select),
+            Keycode::A => Some((0, Button::A)),
+            Keycode::S => Some((0, Button::B)),
+            Keycode::Z => Some((0, Button::Start)),
+            Keycode::X => Some((0, Button::Select)),
+            Keycode::Up => Some((0, Button::Up)),
+            Keycode::Right => Some((0, Button::Right)),
+            Keycode::Down => Some((0, Button::Down)),
+            Keycode::Left => Some((0, Button::Left)),
 
-            Keycode::Up => Some(Button::Up),
-            Keycode::Right => Some(Button::Right),
-            Keycode::Down => Some(Button::Down),
-            Keycode::Left => Some(Button::Left),
+            Keycode::Period => Some((1, Button::A)),
+            Keycode::Comma => Some((1, Button::B)),
+            Keycode::M => Some((1, Button::Start)),
+            Keycode::N => Some((1, Button::Select)),
+            Keycode::I => Some((1, Button::Up)),
+            Keycode::L => Some((1, Button::Right)),
+            Keycode::K => Some((1, Button::Down)),
+            Keycode::J => Some((1, Button::Left)),
+
+            Keycode::R => Some((2, Button::A)),
+            Keycode::E => Some((2, Button::B)),
+            Keycode::W => Some((2, Button::Start)),
+            Keycode::Q => Some((2, Button::Select)),
+            Keycode::T => Some((2, Button::Up)),
+            Keycode::H => Some((2, Button::Right)),
+            Keycode::G => Some((2, Button::Down)),
+            Keycode::F => Some((2, Button::Left)),
+
+            Keycode::Kp0 => Some((3, Button::A)),
+            Keycode::KpPeriod => Some((3, Button::B)),
+            Keycode::KpEnter => Some((3, Button::Start)),
+            Keycode::KpPlus => Some((3, Button::Select)),
+            Keycode::Kp8 => Some((3, Button::Up)),
+            Keycode::Kp6 => Some((3, Button::Right)),
+            Keycode::Kp5 => Some((3, Button::Down)),
+            Keycode::Kp4 => Some((3, Button::Left)),
 
             _ => None,
         };
@@ -62,28 +115,36 @@ impl Joypad {
     fn make_p1_low_nibble(&self, memory: &Memory) -> u8 {
         let p1 = memory[0xFF00];
 
+        // With SGB multiplayer on, the current player shows up when no buttons are selected:
+        // 0xF for player 1, 0xE for player 2 and so on.
+        if self.players > 1 && p1 & 0b110000 == 0b110000 {
+            return 0x0F - self.current_player;
+        }
+        // Without multiplayer, current_player stays at 0
+        let buttons = &self.buttons[self.current_player as usize];
+
         // For some (electronics-related, or weird) reason, the p1 register is 0 == set/pressed
         // and 1 == not set/pressed, even for the bits that select the button set to query.
 
         // Directions
         let mut direction_nibble = 0;
         if p1 & 0b10000 == 0 {
-            if self.buttons[Button::Down as usize] {
+            if buttons[Button::Down as usize] {
                 direction_nibble |= 1;
             }
             direction_nibble = direction_nibble << 1;
 
-            if self.buttons[Button::Up as usize] {
+            if buttons[Button::Up as usize] {
                 direction_nibble |= 1;
             }
             direction_nibble = direction_nibble << 1;
 
-            if self.buttons[Button::Left as usize] {
+            if buttons[Button::Left as usize] {
                 direction_nibble |= 1;
             }
             direction_nibble = direction_nibble << 1;
 
-            if self.buttons[Button::Right as usize] {
+            if buttons[Button::Right as usize] {
                 direction_nibble |= 1;
             }
         }
@@ -91,22 +152,22 @@ impl Joypad {
         // Actions
         let mut action_nibble = 0;
         if p1 & 0b100000 == 0 {
-            if self.buttons[Button::Start as usize] {
+            if buttons[Button::Start as usize] {
                 action_nibble |= 1;
             }
             action_nibble = action_nibble << 1;
 
-            if self.buttons[Button::Select as usize] {
+            if buttons[Button::Select as usize] {
                 action_nibble |= 1;
             }
             action_nibble = action_nibble << 1;
 
-            if self.buttons[Button::B as usize] {
+            if buttons[Button::B as usize] {
                 action_nibble |= 1;
             }
             action_nibble = action_nibble << 1;
 
-            if self.buttons[Button::A as usize] {
+            if buttons[Button::A as usize] {
                 action_nibble |= 1;
             }
         }
@@ -114,38 +175,153 @@ impl Joypad {
         return (!(action_nibble | direction_nibble)) & 0x0F;
     }
 
-    fn button_pressed(&mut self, b: Button, memory: &mut Memory) {
+    fn button_pressed(&mut self, player: usize, b: Button, memory: &mut Memory) {
         let p1 = memory[0xFF00];
         let low_nibble = p1 & 0x0F;
-        self.buttons[b as usize] = true;
+        self.buttons[player][b as usize] = true;
         let new_low_nibble = self.make_p1_low_nibble(memory);
         memory.set_joypad_low_nibble(new_low_nibble);
 
         if low_nibble != new_low_nibble {
-            memory.set(0xFF0F, memory[0xFF0F] | 0b10000);
+            memory.set_internal(0xFF0F, memory[0xFF0F] | 0b10000);
         }
     }
 
-    fn button_released(&mut self, b: Button, memory: &mut Memory) {
-        self.buttons[b as usize] = false;
+    fn button_released(&mut self, player: usize, b: Button, memory: &mut Memory) {
+        self.buttons[player][b as usize] = false;
         let new_low_nibble = self.make_p1_low_nibble(memory);
         memory.set_joypad_low_nibble(new_low_nibble);
     }
 
-    pub fn tick(&mut self, memory: &mut Memory) {
+    // Returns SGB commands once they've been fully received.
+    pub fn tick(&mut self, memory: &mut Memory) -> Option<Vec<u8>> {
         let p1 = memory[0xFF00];
         let select_state = p1 & 0b110000;
-        if select_state != self.last_button_set_select_state {
-            let new_low_nibble = self.make_p1_low_nibble(memory);
-            memory.set_joypad_low_nibble(new_low_nibble);
-            self.last_button_set_select_state = select_state;
+        if select_state == self.last_button_set_select_state {
+            return None;
+        }
+
+        // Moving on to the next player happens when P15 goes back high.
+        let last_state = self.last_button_set_select_state;
+        if self.players > 1 && last_state & 0b100000 == 0 && select_state & 0b100000 != 0 {
+            self.current_player = (self.current_player + 1) % self.players;
+        }
+
+        self.last_button_set_select_state = select_state;
+        let command = match self.sgb_packets.as_mut() {
+            Some(reader) => reader.update(select_state),
+            None => None,
+        };
+
+        // MLT_REQ
+        if let Some(command) = &command {
+            if command[0] >> 3 == 0x11 {
+                self.players = match command[1] & 0b11 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                };
+                self.current_player = 0;
+            }
         }
+
+        let new_low_nibble = self.make_p1_low_nibble(memory);
+        memory.set_joypad_low_nibble(new_low_nibble);
+
+        return command;
     }
 
-    pub fn new() -> Joypad {
+    pub fn new(sgb: bool) -> Joypad {
         return Joypad {
-            buttons: vec![false; 8],
+            buttons: vec![[false; 8]; PLAYERS],
             last_button_set_select_state: 0xFF,
+            sgb_packets: if sgb { Some(PacketReader::new()) } else { None },
+            players: 1,
+            current_player: 0,
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::joypad::Joypad;
+    use crate::memory::Memory;
+
+    use sdl2::keyboard::Keycode;
+
+    fn write_p1(joypad: &mut Joypad, memory: &mut Memory, val: u8) -> Option<Vec<u8>> {
+        memory.set(0xFF00, val);
+        return joypad.tick(memory);
+    }
+
+    // MLT_REQ asking for |players|: 1, 2 or 4.
+    fn request_players(joypad: &mut Joypad, memory: &mut Memory, players: u8) {
+        let mut packet = [0u8; 16];
+        packet[0] = (0x11 << 3) | 1;
+        packet[1] = match players {
+            2 => 1,
+            4 => 3,
+            _ => 0,
+        };
+        write_p1(joypad, memory, 0x00);
+        write_p1(joypad, memory, 0x30);
+        for i in 0..128 {
+            let bit = (packet[i / 8] >> (i % 8)) & 1;
+            write_p1(joypad, memory, if bit == 1 { 0x10 } else { 0x20 });
+            write_p1(joypad, memory, 0x30);
+        }
+        let command = write_p1(joypad, memory, 0x20);
+        assert_eq!(Some(packet.to_vec()), command);
+        write_p1(joypad, memory, 0x30);
+    }
+
+    #[test]
+    fn sgb_multiplayer() {
+        let mut memory = Memory::empty();
+        let mut joypad = Joypad::new(true);
+
+        request_players(&mut joypad, &mut memory, 2);
+        assert_eq!(0x0F, memory.read(0xFF00) & 0x0F);
+
+        write_p1(&mut joypad, &mut memory, 0x10);
+        write_p1(&mut joypad, &mut memory, 0x30);
+        assert_eq!(0x0E, memory.read(0xFF00) & 0x0F);
+        write_p1(&mut joypad, &mut memory, 0x10);
+        write_p1(&mut joypad, &mut memory, 0x30);
+        assert_eq!(0x0F, memory.read(0xFF00) & 0x0F);
+    }
+
+    #[test]
+    fn sgb_four_players() {
+        let mut memory = Memory::empty();
+        let mut joypad = Joypad::new(true);
+        request_players(&mut joypad, &mut memory, 4);
+
+        // Each player holds a different direction
+        joypad.handle_key_down(Keycode::Right, &mut memory);
+        joypad.handle_key_down(Keycode::J, &mut memory);
+        joypad.handle_key_down(Keycode::T, &mut memory);
+        joypad.handle_key_down(Keycode::Kp5, &mut memory);
+        let expected = [0b1110, 0b1101, 0b1011, 0b0111];
+
+        for round in 0..2 {
+            for (player, directions) in expected.iter().enumerate() {
+                assert_eq!(0x0F - player as u8, memory.read(0xFF00) & 0x0F);
+                write_p1(&mut joypad, &mut memory, 0x20);
+                assert_eq!(*directions, memory.read(0xFF00) & 0x0F, "round {}", round);
+                // Buttons aren't shared
+                write_p1(&mut joypad, &mut memory, 0x10);
+                assert_eq!(0x0F, memory.read(0xFF00) & 0x0F);
+                write_p1(&mut joypad, &mut memory, 0x30);
+            }
+        }
+
+        // Back to one player, P1 goes back to player 1's buttons
+        joypad.handle_key_down(Keycode::M, &mut memory);
+        request_players(&mut joypad, &mut memory, 1);
+        write_p1(&mut joypad, &mut memory, 0x10);
+        assert_eq!(0x0F, memory.read(0xFF00) & 0x0F);
+        write_p1(&mut joypad, &mut memory, 0x20);
+        assert_eq!(0b1110, memory.read(0xFF00) & 0x0F);
+    }
+}
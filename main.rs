@@ -1,40 +1,307 @@
+mod boot;
+mod breakpoint;
+mod call_stack;
 mod cartridge;
+mod compat_palette;
+mod config;
 mod console;
 mod cpu;
 mod debug;
+mod debugger_ui;
+mod disasm;
+mod disasm_table;
 mod display;
+mod gdb;
 mod joypad;
 mod memory;
 mod memory_utils;
+mod model;
 mod opcodes;
+mod palette;
+mod png;
 mod ppu;
+mod recorder;
 mod registers;
+mod sgb;
+mod symbols;
+mod trace;
 mod utils;
+mod viewer;
+mod watchpoint;
 
+use std::fs;
 use std::path::Path;
+use std::path::PathBuf;
 use std::sync::mpsc;
 use std::thread;
 use std::time::Duration;
 
 use std::env;
 
+fn palette_arg(args: &[String], i: usize, flag: &str) -> Result<[u32; 4], String> {
+    let spec = args.get(i).ok_or(format!("{} needs a value", flag))?;
+    return palette::parse_colors(spec);
+}
+
+fn parse_options(mut args: Vec<String>) -> Result<console::ConsoleOptions, String> {
+    let mut options = console::ConsoleOptions::new();
+    let mut record_video: Option<PathBuf> = None;
+    let mut record_input: Option<PathBuf> = None;
+    let mut trace: Option<PathBuf> = None;
+    // Config files already spliced in, so ones that include each other don't go on forever.
+    let mut configs: Vec<PathBuf> = vec![];
+    let mut trace_range = None;
+    let mut trace_frames = None;
+    let mut trace_last = None;
+
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--debug" => {
+                options.debugged = true;
+            }
+            "--gdb" => {
+                // Waits for gdb on this port instead of showing the prompt.
+                i += 1;
+                let port = args.get(i).ok_or("--gdb needs a port")?;
+                let port = port
+                    .parse::<u16>()
+                    .map_err(|_| format!("Invalid port {}", port))?;
+                options.debugged = true;
+                options.gdb_port = Some(port);
+            }
+            "--tui" => {
+                options.debugged = true;
+                options.tui = true;
+            }
+            "--no-access-restrictions" => {
+                // Lets homebrew that pokes VRAM/OAM at the wrong time keep running while debugging it.
+                options.ppu_access_restrictions = false;
+            }
+            "--boot-rom" => {
+                i += 1;
+                let path = args.get(i).ok_or("--boot-rom needs a path")?;
+                let data = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+                options.boot_rom = Some(data);
+            }
+            "--boot-rom-dir" => {
+                // Picks dmg_boot.bin, cgb_boot.bin etc. depending on the model
+                i += 1;
+                let path = args.get(i).ok_or("--boot-rom-dir needs a path")?;
+                options.boot_rom_dir = Some(PathBuf::from(path));
+            }
+            "--model" => {
+                i += 1;
+                let name = args.get(i).ok_or("--model needs a value")?;
+                let model = model::Model::from_name(name).ok_or(format!(
+                    "Unknown model {}, expected one of dmg0, dmg, mgb, sgb, sgb2, cgb or agb",
+                    name
+                ))?;
+                options.model = Some(model);
+            }
+            "--config" => {
+                // The file's options apply as if they were given right here.
+                i += 1;
+                let path = args.get(i).ok_or("--config needs a path")?;
+                let canonical = fs::canonicalize(path).map_err(|e| format!("{}: {}", path, e))?;
+                if configs.contains(&canonical) {
+                    return Err(format!("{} is included more than once", path));
+                }
+                let file_args = config::load(&canonical)?;
+                configs.push(canonical);
+                args.splice(i + 1..i + 1, file_args);
+            }
+            "--palette" => {
+                i += 1;
+                options.palette = palette::Palette::uniform(palette_arg(&args, i, "--palette")?);
+            }
+            "--bg-palette" => {
+                i += 1;
+                options.palette.bg = palette_arg(&args, i, "--bg-palette")?;
+            }
+            "--obp0-palette" => {
+                i += 1;
+                options.palette.obj0 = palette_arg(&args, i, "--obp0-palette")?;
+            }
+            "--obp1-palette" => {
+                i += 1;
+                options.palette.obj1 = palette_arg(&args, i, "--obp1-palette")?;
+            }
+            "--raw-screenshots" => {
+                options.raw_screenshots = true;
+            }
+            "--headless" => {
+                options.headless = true;
+            }
+            "--frames" => {
+                i += 1;
+                let frames = args.get(i).ok_or("--frames needs a value")?;
+                let frames = frames
+                    .parse::<u64>()
+                    .map_err(|_| format!("Invalid frame count {}", frames))?;
+                options.frames = Some(frames);
+            }
+            "--record-video" => {
+                // .y4m files get Y4M, anything else raw RGB24 frames.
+                i += 1;
+                let path = args.get(i).ok_or("--record-video needs a path")?;
+                record_video = Some(PathBuf::from(path));
+            }
+            "--record-audio" => {
+                // A file full of silence would only mislead whoever gets it with a bug report.
+                return Err(
+                    "--record-audio isn't supported yet, there's no sound emulation".to_string(),
+                );
+            }
+            "--record-input" => {
+                i += 1;
+                let path = args.get(i).ok_or("--record-input needs a path")?;
+                record_input = Some(PathBuf::from(path));
+            }
+            "--trace" => {
+                // Gameboy Doctor's format, to diff against its reference logs.
+                i += 1;
+                let path = args.get(i).ok_or("--trace needs a path")?;
+                trace = Some(PathBuf::from(path));
+            }
+            "--trace-range" => {
+                // Only logs instructions in start-end
+                i += 1;
+                let range = args.get(i).ok_or("--trace-range needs a range")?;
+                trace_range = Some(watchpoint::parse_range(range)?);
+            }
+            "--trace-frames" => {
+                // Only logs the first this many frames
+                i += 1;
+                let frames = args.get(i).ok_or("--trace-frames needs a value")?;
+                let frames = frames
+                    .parse::<u64>()
+                    .map_err(|_| format!("Invalid frame count {}", frames))?;
+                trace_frames = Some(frames);
+            }
+            "--trace-last" => {
+                // Only writes the last this many instructions, on exit or when it panics.
+                i += 1;
+                let count = args.get(i).ok_or("--trace-last needs a value")?;
+                let count = count
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|count| *count > 0)
+                    .ok_or(format!("Invalid instruction count {}", count))?;
+                trace_last = Some(count);
+            }
+            "--symbols" => {
+                // Otherwise the ROM's path with .sym instead of its extension is tried.
+                i += 1;
+                let path = args.get(i).ok_or("--symbols needs a path")?;
+                options.symbols = symbols::Symbols::load(Path::new(path))?;
+            }
+            "--compat-palette" => {
+                // Same as holding these buttons during the CGB boot logo, e.g. "left+b".
+                i += 1;
+                let combo = args.get(i).ok_or("--compat-palette needs a value")?;
+                let palette = compat_palette::from_button_combo(combo).ok_or(format!(
+                    "Unknown palette {}, expected one of {}",
+                    combo,
+                    compat_palette::button_combo_names().join(", ")
+                ))?;
+                options.compat_palette = Some(palette);
+            }
+            other => {
+                return Err(format!("Unknown argument: {}", other));
+            }
+        }
+        i += 1;
+    }
+
+    if record_video.is_some() || record_input.is_some() {
+        options.recorder = Some(recorder::Recorder::new(
+            record_video.as_deref(),
+            record_input.as_deref(),
+        )?);
+    }
+
+    if let Some(path) = trace {
+        options.trace = Some(trace::Trace::new(
+            &path,
+            trace_range,
+            trace_frames,
+            trace_last,
+        )?);
+    } else if trace_range.is_some() || trace_frames.is_some() || trace_last.is_some() {
+        return Err("The --trace-* options need --trace".to_string());
+    }
+
+    return Ok(options);
+}
+
 fn main() -> Result<(), String> {
     let args: Vec<String> = env::args().collect();
 
     assert!(args.len() > 1);
 
-    let debug = args.len() > 2 && args[2] == "--debug";
-    let rom_path = args[1].clone();
+    let mut option_args = vec![];
+    if let Some(path) = config::default_path() {
+        if path.exists() {
+            option_args = config::load(&path)?;
+        }
+    }
+    option_args.extend_from_slice(&args[2..]);
+
+    let mut options = parse_options(option_args)?;
+    let debug = options.debugged;
+    let cart = cartridge::Cartridge::load(Path::new(&args[1]));
+
+    // Like RGBDS names them, next to the ROM.
+    let sym_path = Path::new(&args[1]).with_extension("sym");
+    if options.symbols.is_empty() && sym_path.exists() {
+        options.symbols = symbols::Symbols::load(&sym_path)?;
+    }
+
+    let model = options
+        .model
+        .unwrap_or_else(|| model::Model::from_cartridge(&cart));
+    if options.boot_rom.is_none() {
+        if let Some(dir) = &options.boot_rom_dir {
+            let path = dir.join(model.boot_rom_name());
+            if path.exists() {
+                options.boot_rom =
+                    Some(fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))?);
+            } else {
+                println!("No {:?} boot ROM in {}, skipping it", model, dir.display());
+            }
+        }
+    }
+    if let Some(boot_rom) = &options.boot_rom {
+        boot::validate_boot_rom(boot_rom, model)?;
+    }
 
     let (stx, srx) = mpsc::channel();
 
     // TODO: don't set up the debugger if not debugging
     let (rth_send, rth_recv) = mpsc::channel();
     let (htr_send, htr_recv) = mpsc::channel();
-    let mut debugger_remote = debug::DebuggerRemote::new(rth_send, htr_recv);
+    let mut debugger_remote = None;
+    let mut debugger_ui = None;
+    let mut gdb_stub = None;
+    if let Some(port) = options.gdb_port {
+        gdb_stub = Some(gdb::GdbStub::new(rth_send, htr_recv, port)?);
+    } else if options.tui {
+        debugger_ui = Some(debugger_ui::DebuggerUi::new(
+            rth_send,
+            htr_recv,
+            options.symbols.clone(),
+        )?);
+    } else {
+        debugger_remote = Some(debug::DebuggerRemote::new(
+            rth_send,
+            htr_recv,
+            options.symbols.clone(),
+        ));
+    }
 
-    thread::spawn(move || {
-        let mut console = console::Console::new(Path::new(&rom_path), stx, debug);
+    let console_thread = thread::spawn(move || {
+        let mut console = console::Console::new(cart, model, stx, options);
         let mut debugger_host = debug::DebuggerHost::new(rth_recv, htr_send);
 
         'running: loop {
@@ -62,11 +329,27 @@ fn main() -> Result<(), String> {
         }
 
         if debug {
-            debugger_remote.update();
+            if let Some(debugger_ui) = debugger_ui.as_mut() {
+                if !debugger_ui.update() {
+                    break 'looping;
+                }
+            }
+            if let Some(debugger_remote) = debugger_remote.as_mut() {
+                debugger_remote.update();
+            }
+            if let Some(gdb_stub) = gdb_stub.as_mut() {
+                gdb_stub.update();
+            }
         }
 
         thread::sleep(Duration::from_millis(100));
     }
 
+    // Puts the terminal back the way it was.
+    drop(debugger_ui);
+
+    // Lets the console finish writing any recording before exiting.
+    console_thread.join().expect("console thread panicked");
+
     return Ok(());
 }
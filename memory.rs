@@ -1,12 +1,155 @@
+use crate::model::Model;
+use crate::watchpoint::Access;
+use crate::watchpoint::WatchKind;
+
+use std::cell::Cell;
+use std::cell::RefCell;
+
+// How the CPU sees an IO register. Bits outside of |readable| always read back as 1 and bits
+// outside of |writable| can't be changed by CPU writes.
+struct IoRegister {
+    readable: u8,
+    writable: u8,
+}
+
+// CGB registers are only there in CGB mode, a DMG game running on a CGB doesn't see them.
+fn io_register(addr: u16, cgb_mode: bool) -> IoRegister {
+    let (readable, writable) = match addr {
+        0xFF00 => (0x3F, 0x30),                            // P1
+        0xFF01 => (0xFF, 0xFF),                            // SB
+        0xFF02 => (0x81, 0x81),                            // SC
+        0xFF04..=0xFF06 => (0xFF, 0xFF),                   // DIV, TIMA, TMA
+        0xFF07 => (0x07, 0x07),                            // TAC
+        0xFF0F => (0x1F, 0x1F),                            // IF
+        0xFF10 => (0x7F, 0x7F),                            // NR10
+        0xFF11 | 0xFF16 => (0xC0, 0xFF),                   // NR11, NR21
+        0xFF12 | 0xFF17 | 0xFF21 => (0xFF, 0xFF),          // NR12, NR22, NR42
+        0xFF13 | 0xFF18 | 0xFF1B | 0xFF1D => (0x00, 0xFF), // NR13, NR23, NR31, NR33
+        0xFF14 | 0xFF19 | 0xFF1E => (0x40, 0xC7),          // NR14, NR24, NR34
+        0xFF1A => (0x80, 0x80),                            // NR30
+        0xFF1C => (0x60, 0x60),                            // NR32
+        0xFF20 => (0x00, 0x3F),                            // NR41
+        0xFF22 | 0xFF24 | 0xFF25 => (0xFF, 0xFF),          // NR43, NR50, NR51
+        0xFF23 => (0x40, 0xC0),                            // NR44
+        0xFF26 => (0x8F, 0x80),                            // NR52
+        0xFF30..=0xFF3F => (0xFF, 0xFF),                   // Wave RAM
+        0xFF40 => (0xFF, 0xFF),                            // LCDC
+        0xFF41 => (0x7F, 0x78),                            // STAT
+        0xFF42 | 0xFF43 => (0xFF, 0xFF),                   // SCY, SCX
+        0xFF44 => (0xFF, 0x00),                            // LY
+        0xFF45..=0xFF4B => (0xFF, 0xFF),                   // LYC, DMA, BGP, OBP0, OBP1, WY, WX
+        0xFF4D if cgb_mode => (0x81, 0x01),                // KEY1
+        0xFF4F if cgb_mode => (0x01, 0x01),                // VBK
+        0xFF51..=0xFF54 if cgb_mode => (0x00, 0xFF),       // HDMA1~4
+        0xFF55 if cgb_mode => (0xFF, 0xFF),                // HDMA5
+        0xFF68 | 0xFF6A if cgb_mode => (0xBF, 0xBF),       // BCPS, OCPS
+        0xFF69 | 0xFF6B if cgb_mode => (0xFF, 0xFF),       // BCPD, OCPD
+        0xFF6C if cgb_mode => (0x01, 0x01),                // OPRI
+        0xFF70 if cgb_mode => (0x07, 0x07),                // SVBK
+        0xFFFF => (0xFF, 0xFF),                            // IE
+        _ => (0x00, 0x00),
+    };
+
+    return IoRegister {
+        readable: readable,
+        writable: writable,
+    };
+}
+
+// Where an address ends up once banking has been taken into account.
+#[derive(Copy, Clone)]
+enum Area {
+    BootRom,
+    Rom(usize),
+    Vram,
+    Wram,
+    BgPalette,
+    ObjPalette,
+    // Everything else lives in one flat block covering 0x8000~0xFFFF
+    Flat,
+}
+
+// Everything below OAM that isn't VRAM is on the external bus.
+// How the CPU set off the OAM bug, each kind mangles OAM its own way.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum OamBug {
+    Read,
+    Write,
+}
+
+fn on_vram_bus(addr: u16) -> bool {
+    return (0x8000..=0x9FFF).contains(&addr);
+}
+
+// OAM DMA copies 160 bytes from |source| to 0xFE00, one byte per M-cycle.
+#[derive(Copy, Clone)]
+struct OamDma {
+    source: u16,
+    index: u16,
+    // M-cycles left before the first byte is copied.
+    delay: u8,
+}
+
+// CGB VRAM DMA copies 16 byte blocks from |source| to |dest| in VRAM. General purpose transfers
+// copy everything in one go, HBlank ones one block per HBlank.
+#[derive(Copy, Clone)]
+struct VramDma {
+    source: u16,
+    dest: u16,
+    // Blocks left, including the one being copied.
+    blocks: u8,
+    hblank: bool,
+    // Bytes of the current block already copied.
+    copied: u8,
+    copying: bool,
+}
+
 pub struct Memory {
     rom_banks: std::vec::Vec<std::vec::Vec<u8>>,
     m: std::vec::Vec<u8>,
     current_bank: usize,
-    dma_in_progress_addr: Option<u16>,
+    // 2 banks of 0x2000 bytes, only bank 0 is reachable outside of CGB mode.
+    vram: std::vec::Vec<u8>,
+    vram_bank: usize,
+    // 8 banks of 0x1000 bytes. Bank 0 is always at 0xC000, 0xD000 can be switched to 1~7.
+    wram: std::vec::Vec<u8>,
+    wram_bank: usize,
+    // 8 palettes of 4 RGB555 colors each, for BG and objects.
+    bg_palette_ram: std::vec::Vec<u8>,
+    obj_palette_ram: std::vec::Vec<u8>,
+    cgb_mode: bool,
+    // CGB double speed, where everything but the PPU and APU runs twice as fast.
+    double_speed: bool,
+    dma: Option<OamDma>,
+    // A DMA started while another one is running only takes over once its startup delay is over.
+    pending_dma: Option<OamDma>,
+    // The byte on the bus during the current DMA M-cycle, which is what the CPU reads if it
+    // tries to access anything but HRAM while the transfer is running.
+    dma_bus_byte: u8,
+    dot_in_m_cycle: u8,
+    vram_dma: Option<VramDma>,
+    // PPU mode seen on the last tick, to find the start of HBlank.
+    last_ppu_mode: u8,
+    // Dots since the PPU started its OAM scan, it goes through one 8 byte row every M-cycle.
+    oam_scan_dots: u8,
+    // A read set off the OAM bug, it gets applied on the next tick since reads only borrow
+    // |self|. Holds the row the PPU was on.
+    pending_oam_bug: Cell<Option<usize>>,
+    // Set when the CPU writes to DIV, the timer picks it up to reset its internal counter.
+    div_reset: bool,
+    model: Model,
+    // Mapped over the cartridge until the CPU writes to 0xFF50.
+    boot_rom: Option<std::vec::Vec<u8>>,
+    // When set, CPU accesses to VRAM and OAM are blocked while the PPU is using them.
+    ppu_access_restrictions: bool,
+    // Debugger watchpoints. CPU reads and writes in these ranges get recorded until the
+    // debugger takes them, reads need a RefCell since they only borrow |self|.
+    watched_ranges: std::vec::Vec<(u16, u16, WatchKind)>,
+    accesses: RefCell<std::vec::Vec<Access>>,
 }
 
 impl Memory {
-    pub fn new(cartridge_data: &std::vec::Vec<u8>) -> Memory {
+    pub fn new(cartridge_data: &std::vec::Vec<u8>, model: Model) -> Memory {
         let num_banks = cartridge_data.len() / 0x4000;
         let mut banks: std::vec::Vec<std::vec::Vec<u8>> = vec![];
 
@@ -21,74 +164,600 @@ impl Memory {
             rom_banks: banks,
             m: vec![0; 0xFFFF - 0x8000 + 1],
             current_bank: 1,
-            dma_in_progress_addr: None,
+            vram: vec![0; 0x4000],
+            vram_bank: 0,
+            wram: vec![0; 0x8000],
+            wram_bank: 1,
+            bg_palette_ram: vec![0; 64],
+            obj_palette_ram: vec![0; 64],
+            cgb_mode: model.is_cgb() && cartridge_data[0x0143] & 0x80 != 0,
+            double_speed: false,
+            dma: None,
+            pending_dma: None,
+            dma_bus_byte: 0xFF,
+            dot_in_m_cycle: 0,
+            vram_dma: None,
+            last_ppu_mode: 0,
+            oam_scan_dots: 0,
+            pending_oam_bug: Cell::new(None),
+            div_reset: false,
+            model: model,
+            boot_rom: None,
+            ppu_access_restrictions: true,
+            watched_ranges: vec![],
+            accesses: RefCell::new(vec![]),
         };
     }
 
     #[cfg(test)]
     pub fn empty() -> Memory {
-        let num_banks = 2;
-        let mut banks: std::vec::Vec<std::vec::Vec<u8>> = vec![];
-
-        for i in 0..num_banks {
-            banks.push(vec![0; 0x4000]);
-        }
+        return Memory::new(&vec![0; 0x8000], Model::Dmg);
+    }
 
-        return Memory {
-            rom_banks: banks,
-            m: vec![0; 0xFFFF - 0x8000 + 1],
-            current_bank: 1,
-            dma_in_progress_addr: None,
-        };
+    // Same thing with a cartridge that asks for CGB mode.
+    #[cfg(test)]
+    pub fn empty_cgb() -> Memory {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0143] = 0x80;
+        return Memory::new(&rom, Model::Cgb);
     }
 
+    // Called once per CPU clock, which is twice per dot in double speed. DMAs only move
+    // forward once per M-cycle.
     pub fn tick(&mut self) {
-        if self.dma_in_progress_addr.is_none() {
+        // HBlank DMA copies a block whenever the PPU enters HBlank
+        let ppu_mode = self.m[(0xFF41 - 0x8000) as usize] & 0b11;
+        if ppu_mode == 0 && self.last_ppu_mode == 3 {
+            if let Some(vram_dma) = self.vram_dma.as_mut() {
+                vram_dma.copying = true;
+            }
+        }
+        if ppu_mode == 2 && self.last_ppu_mode == 2 {
+            self.oam_scan_dots = self.oam_scan_dots.saturating_add(1);
+        } else {
+            self.oam_scan_dots = 0;
+        }
+        self.last_ppu_mode = ppu_mode;
+        if let Some(row) = self.pending_oam_bug.take() {
+            self.corrupt_oam(OamBug::Read, row);
+        }
+
+        self.dot_in_m_cycle = (self.dot_in_m_cycle + 1) % 4;
+        if self.dot_in_m_cycle != 0 {
             return;
         }
 
-        let addr = match self.dma_in_progress_addr {
-            Some(a) => a,
+        self.oam_dma_tick();
+        self.vram_dma_tick();
+    }
+
+    fn oam_dma_tick(&mut self) {
+        if let Some(mut pending) = self.pending_dma {
+            if pending.delay == 0 {
+                self.dma = Some(pending);
+                self.pending_dma = None;
+            } else {
+                pending.delay -= 1;
+                self.pending_dma = Some(pending);
+            }
+        }
+
+        let mut dma = match self.dma {
+            Some(dma) => dma,
+            None => {
+                return;
+            }
+        };
+
+        let byte = self[dma.source + dma.index];
+        self.m[(0xFE00 + dma.index - 0x8000) as usize] = byte;
+        self.dma_bus_byte = byte;
+
+        dma.index += 1;
+        self.dma = if dma.index == 0xA0 { None } else { Some(dma) };
+    }
+
+    // VRAM DMA moves 16 bytes every 8us, so 2 bytes per M-cycle in normal speed and 1 in double
+    // speed where M-cycles are twice as short.
+    fn vram_dma_tick(&mut self) {
+        let mut dma = match self.vram_dma {
+            Some(dma) if dma.copying => dma,
             _ => {
-                panic!("DMA");
+                return;
+            }
+        };
+
+        let bytes_per_cycle = if self.double_speed { 1 } else { 2 };
+        for _ in 0..bytes_per_cycle {
+            let byte = self[dma.source];
+            *self.byte_mut(dma.dest) = byte;
+            dma.source = dma.source.wrapping_add(1);
+            dma.dest += 1;
+            dma.copied += 1;
+        }
+
+        let hdma5_idx = (0xFF55 - 0x8000) as usize;
+        if dma.copied == 16 {
+            dma.copied = 0;
+            dma.blocks -= 1;
+            // General purpose DMA goes straight on to the next block, HBlank DMA waits for
+            // the next HBlank.
+            dma.copying = !dma.hblank;
+
+            // Running past the end of VRAM ends the transfer as well
+            if dma.blocks == 0 || dma.dest >= 0xA000 {
+                self.vram_dma = None;
+                self.m[hdma5_idx] = 0xFF;
+                return;
+            }
+            self.m[hdma5_idx] = dma.blocks - 1;
+        }
+
+        self.vram_dma = Some(dma);
+    }
+
+    // Writing HDMA5 starts a VRAM DMA, or cancels a running HBlank one when bit 7 is clear.
+    fn start_vram_dma(&mut self, val: u8) {
+        let hdma5_idx = (0xFF55 - 0x8000) as usize;
+
+        if let Some(dma) = self.vram_dma {
+            if dma.hblank && val & 0x80 == 0 {
+                self.vram_dma = None;
+                self.m[hdma5_idx] = 0x80 | (dma.blocks - 1);
+                return;
             }
+        }
+
+        let source = ((self.m[(0xFF51 - 0x8000) as usize] as u16) << 8)
+            | (self.m[(0xFF52 - 0x8000) as usize] as u16);
+        let dest = ((self.m[(0xFF53 - 0x8000) as usize] as u16) << 8)
+            | (self.m[(0xFF54 - 0x8000) as usize] as u16);
+        let hblank = val & 0x80 != 0;
+        let lcd_on = self.m[(0xFF40 - 0x8000) as usize] & 0x80 != 0;
+
+        self.vram_dma = Some(VramDma {
+            source: source & 0xFFF0,
+            dest: 0x8000 | (dest & 0x1FF0),
+            blocks: (val & 0x7F) + 1,
+            hblank: hblank,
+            copied: 0,
+            // With the LCD off there's no HBlank to wait for, the first block goes right away
+            copying: !hblank || !lcd_on,
+        });
+        self.m[hdma5_idx] = val & 0x7F;
+    }
+
+    // The CPU doesn't run while VRAM DMA is copying, whether it's a general purpose transfer
+    // or a block during HBlank.
+    pub fn vram_dma_stalls_cpu(&self) -> bool {
+        return match self.vram_dma {
+            Some(dma) => dma.copying,
+            None => false,
+        };
+    }
+
+    fn start_dma(&mut self, val: u8) {
+        // Sources above 0xDFFF don't reach OAM or IO, they read from echo RAM instead.
+        let mut source = (val as u16) << 8;
+        if source >= 0xE000 {
+            source -= 0x2000;
+        }
+
+        self.pending_dma = Some(OamDma {
+            source: source,
+            index: 0,
+            delay: 1,
+        });
+    }
+
+    // While OAM DMA runs, the CPU can't use OAM, nor anything on the bus the DMA is reading
+    // from, where it gets whatever DMA is moving instead. VRAM has a bus of its own, so a DMA
+    // from ROM or WRAM leaves it alone and the other way around. HRAM and the IO registers are
+    // always fine.
+    fn dma_conflict(&self, addr: u16) -> Option<u8> {
+        let dma = self.dma?;
+
+        return match addr {
+            0xFE00..=0xFEFF => Some(0xFF),
+            0xFF00..=0xFFFF => None,
+            _ if on_vram_bus(addr) == on_vram_bus(dma.source) => Some(self.dma_bus_byte),
+            _ => None,
+        };
+    }
+
+    pub fn load_boot_rom(&mut self, data: std::vec::Vec<u8>) {
+        // The CGB boot ROM always starts in CGB mode, then picks the mode for the cartridge
+        // through KEY0 before unmapping itself.
+        self.cgb_mode = self.model.is_cgb();
+        self.boot_rom = Some(data);
+    }
+
+    // The DMG boot ROM covers 0x0000~0x00FF. The CGB one also covers 0x0200~0x08FF, leaving
+    // the cartridge header visible in between.
+    fn boot_rom_mapped(&self, addr: u16) -> bool {
+        let boot_rom = match &self.boot_rom {
+            Some(b) => b,
+            None => {
+                return false;
+            }
+        };
+
+        return match addr {
+            0x0000..=0x00FF => true,
+            0x0200..=0x08FF => boot_rom.len() > addr as usize,
+            _ => false,
+        };
+    }
+
+    fn locate(&self, addr: u16) -> (Area, usize) {
+        if self.boot_rom_mapped(addr) {
+            return (Area::BootRom, addr as usize);
+        }
+
+        let addr = addr as usize;
+        return match addr {
+            // This is the first, static ROM bank
+            0..=0x3FFF => (Area::Rom(0), addr),
+            // This is the switchable bank
+            0x4000..=0x7FFF => (Area::Rom(self.current_bank), addr - 0x4000),
+            0x8000..=0x9FFF => (Area::Vram, self.vram_bank * 0x2000 + addr - 0x8000),
+            0xC000..=0xCFFF => (Area::Wram, addr - 0xC000),
+            0xD000..=0xDFFF => (Area::Wram, self.wram_bank * 0x1000 + addr - 0xD000),
+            // 0xC000~0xDDFF is mirrored at 0xE000~0xFDFF
+            0xE000..=0xFDFF => self.locate((addr - 0x2000) as u16),
+            // Palette data registers are windows into palette RAM
+            0xFF69 => (Area::BgPalette, (self.m[0xFF68 - 0x8000] & 0x3F) as usize),
+            0xFF6B => (Area::ObjPalette, (self.m[0xFF6A - 0x8000] & 0x3F) as usize),
+            _ => (Area::Flat, addr - 0x8000),
+        };
+    }
+
+    fn byte_mut(&mut self, addr: u16) -> &mut u8 {
+        let (area, offset) = self.locate(addr);
+        return match area {
+            Area::BootRom => &mut self.boot_rom.as_mut().unwrap()[offset],
+            Area::Rom(bank) => &mut self.rom_banks[bank][offset],
+            Area::Vram => &mut self.vram[offset],
+            Area::Wram => &mut self.wram[offset],
+            Area::BgPalette => &mut self.bg_palette_ram[offset],
+            Area::ObjPalette => &mut self.obj_palette_ram[offset],
+            Area::Flat => &mut self.m[offset],
+        };
+    }
+
+    // ROM bank mapped at |addr|, None when it's not in ROM or the boot ROM is covering it.
+    pub fn rom_bank(&self, addr: u16) -> Option<usize> {
+        return match self.locate(addr).0 {
+            Area::Rom(bank) => Some(bank),
+            _ => None,
         };
+    }
 
-        let byte = self[addr];
-        let dest = 0xFE00 | (addr & 0xFF);
-        self.m[(dest - 0x8000) as usize] = byte;
+    // Byte at |addr| in |bank| whether or not that bank is mapped, for the debugger. None when
+    // there's no such bank, or nothing banked at |addr|.
+    pub fn peek_bank(&self, addr: u16, bank: usize) -> Option<u8> {
+        let offset = addr as usize;
+        return match addr {
+            0x0000..=0x3FFF if bank == 0 => self.rom_banks.first().map(|rom| rom[offset]),
+            0x4000..=0x7FFF => self.rom_banks.get(bank).map(|rom| rom[offset - 0x4000]),
+            0x8000..=0x9FFF if bank < 2 => Some(self.vram[bank * 0x2000 + offset - 0x8000]),
+            // There's no MBC yet, so cartridge RAM is the one bank
+            0xA000..=0xBFFF if bank == 0 => Some(self[addr]),
+            0xD000..=0xDFFF if bank < 8 => Some(self.wram[bank * 0x1000 + offset - 0xD000]),
+            _ => None,
+        };
+    }
 
-        let next = addr + 1;
+    // Where the banked region |addr| is in ends, e.g. 0x8000 for ROM banks. Past that, a bank
+    // number means something else or nothing at all.
+    pub fn bank_end(addr: u16) -> usize {
+        return match addr {
+            0x0000..=0x3FFF => 0x4000,
+            0x4000..=0x7FFF => 0x8000,
+            0x8000..=0x9FFF => 0xA000,
+            0xA000..=0xBFFF => 0xC000,
+            0xD000..=0xDFFF => 0xE000,
+            _ => addr as usize + 1,
+        };
+    }
 
-        if next & 0xFF > 0x9F {
-            self.dma_in_progress_addr = None;
+    pub fn cgb_mode(&self) -> bool {
+        return self.cgb_mode;
+    }
+
+    // A DMG game running on a CGB. It's colored through the CGB palettes, picked by the boot ROM.
+    pub fn cgb_compat_mode(&self) -> bool {
+        return self.model.is_cgb() && !self.cgb_mode;
+    }
+
+    pub fn double_speed(&self) -> bool {
+        return self.double_speed;
+    }
+
+    // Called by STOP. Switches speed if KEY1 asked for it, returning whether it did.
+    pub fn switch_speed(&mut self) -> bool {
+        let key1_idx = (0xFF4D - 0x8000) as usize;
+        if !self.cgb_mode || self.m[key1_idx] & 1 == 0 {
+            return false;
+        }
+
+        self.double_speed = !self.double_speed;
+        self.m[key1_idx] = if self.double_speed { 0x80 } else { 0x00 };
+        return true;
+    }
+
+    // Lets the PPU fetch from either VRAM bank whichever one the CPU has selected.
+    pub fn read_vram(&self, bank: usize, addr: u16) -> u8 {
+        return self.vram[bank * 0x2000 + (addr - 0x8000) as usize];
+    }
+
+    // Colors are little endian RGB555.
+    pub fn bg_palette_color(&self, palette: u8, color: u8) -> u16 {
+        let idx = (palette as usize) * 8 + (color as usize) * 2;
+        return (self.bg_palette_ram[idx] as u16) | ((self.bg_palette_ram[idx + 1] as u16) << 8);
+    }
+
+    pub fn obj_palette_color(&self, palette: u8, color: u8) -> u16 {
+        let idx = (palette as usize) * 8 + (color as usize) * 2;
+        return (self.obj_palette_ram[idx] as u16) | ((self.obj_palette_ram[idx + 1] as u16) << 8);
+    }
+
+    pub fn initialize_palette(&mut self, obj: bool, palette: u8, colors: [u16; 4]) {
+        let ram = if obj {
+            &mut self.obj_palette_ram
         } else {
-            self.dma_in_progress_addr = Some(addr + 1);
+            &mut self.bg_palette_ram
+        };
+
+        for (i, color) in colors.iter().enumerate() {
+            let idx = (palette as usize) * 8 + i * 2;
+            ram[idx] = (color & 0xFF) as u8;
+            ram[idx + 1] = (color >> 8) as u8;
+        }
+    }
+
+    pub fn set_ppu_access_restrictions(&mut self, enabled: bool) {
+        self.ppu_access_restrictions = enabled;
+    }
+
+    // VRAM is inaccessible to the CPU during mode 3 and OAM during modes 2 and 3.
+    // When the LCD is off, the PPU doesn't touch either so the CPU always has access.
+    fn cpu_can_access(&self, addr: u16) -> bool {
+        if !self.ppu_access_restrictions || self.m[(0xFF40 - 0x8000) as usize] & 0x80 == 0 {
+            return true;
+        }
+
+        let mode = self.m[(0xFF41 - 0x8000) as usize] & 0b11;
+        return match addr {
+            0x8000..=0x9FFF => mode != 3,
+            0xFE00..=0xFEFF => mode != 2 && mode != 3,
+            0xFF69 | 0xFF6B => !self.cgb_mode || mode != 3,
+            _ => true,
+        };
+    }
+
+    // The OAM row the PPU is reading if |addr| showing up on the bus right now sets off the OAM
+    // bug. Only models before the CGB have it, and only while the PPU is scanning OAM. Those
+    // never run in double speed, so there's always one tick per dot.
+    fn oam_bug_row(&self, addr: u16) -> Option<usize> {
+        if self.model.is_cgb() || !(0xFE00..=0xFEFF).contains(&addr) || self.cpu_can_access(addr) {
+            return None;
+        }
+        if self.m[(0xFF41 - 0x8000) as usize] & 0b11 != 2 {
+            return None;
         }
+        return Some((self.oam_scan_dots / 4) as usize);
+    }
+
+    // OAM is 20 rows of 8 bytes. The row the PPU is on gets mixed with the one before it, the
+    // first row never gets hit. See "OAM Corruption Bug" in Pan Docs.
+    fn corrupt_oam(&mut self, bug: OamBug, row: usize) {
+        if row == 0 || row >= 20 {
+            return;
+        }
+        let current = (0xFE00 - 0x8000) + row * 8;
+        let previous = current - 8;
+        // The first word is mixed bit by bit, which works the same one byte at a time.
+        for i in 0..2 {
+            let a = self.m[current + i];
+            let b = self.m[previous + i];
+            let c = self.m[previous + 4 + i];
+            self.m[current + i] = match bug {
+                OamBug::Read => b | (a & c),
+                OamBug::Write => ((a ^ c) & (b ^ c)) ^ c,
+            };
+        }
+        // The other three words come straight from the previous row
+        for i in 2..8 {
+            self.m[current + i] = self.m[previous + i];
+        }
+    }
+
+    // INC rr and DEC rr put the register pair on the address bus, which is enough for the OAM
+    // bug even though nothing gets read or written.
+    pub fn idu_access(&mut self, addr: u16) {
+        if let Some(row) = self.oam_bug_row(addr) {
+            self.corrupt_oam(OamBug::Write, row);
+        }
+    }
+
+    pub fn set_watched_ranges(&mut self, ranges: std::vec::Vec<(u16, u16, WatchKind)>) {
+        self.watched_ranges = ranges;
+    }
+
+    // Watched accesses since the last call.
+    pub fn take_accesses(&mut self) -> std::vec::Vec<Access> {
+        return self.accesses.replace(vec![]);
+    }
+
+    fn record_access(&self, addr: u16, value: u8, old: u8, write: bool) {
+        let watched = self
+            .watched_ranges
+            .iter()
+            .any(|&(start, end, kind)| addr >= start && addr <= end && kind.matches(write));
+        if watched {
+            self.accesses.borrow_mut().push(Access {
+                addr: addr,
+                value: value,
+                old: old,
+                write: write,
+            });
+        }
+    }
+
+    // Reads a byte the way the CPU sees it on the bus. Components that own the memory they
+    // read (PPU, DMA, debugger) index into |Memory| directly instead, which also keeps them
+    // from setting off read watchpoints.
+    pub fn read(&self, addr: u16) -> u8 {
+        let val = self.bus_read(addr);
+        if !self.watched_ranges.is_empty() {
+            self.record_access(addr, val, val, false);
+        }
+        return val;
+    }
+
+    // Same as read, for opcodes and their immediate operands. Those aren't data reads as far as
+    // watchpoints are concerned.
+    pub fn fetch(&self, addr: u16) -> u8 {
+        return self.bus_read(addr);
+    }
+
+    fn bus_read(&self, addr: u16) -> u8 {
+        if let Some(byte) = self.dma_conflict(addr) {
+            return byte;
+        }
+
+        if !self.cpu_can_access(addr) {
+            if let Some(row) = self.oam_bug_row(addr) {
+                self.pending_oam_bug.set(Some(row));
+            }
+            return 0xFF;
+        }
+
+        return match addr {
+            // Unusable region after OAM
+            0xFEA0..=0xFEFF => 0x00,
+            0xFF00..=0xFF7F | 0xFFFF => {
+                let reg = io_register(addr, self.cgb_mode);
+                (self[addr] & reg.readable) | !reg.readable
+            }
+            _ => self[addr],
+        };
     }
 
     pub fn initialize(&mut self, addr: u16, val: u8) {
         self.m[(addr - 0x8000) as usize] = val;
     }
 
+    // Used by the hardware itself (PPU, timer, joypad) to update registers it owns, bypassing
+    // the masks and side effects that apply to CPU writes.
+    pub fn set_internal(&mut self, addr: u16, val: u8) {
+        self.m[(addr - 0x8000) as usize] = val;
+    }
+
+    // Writes from the debugger go straight to whatever is mapped at |addr|, ROM included,
+    // without any of the side effects a CPU write would have.
+    pub fn poke(&mut self, addr: u16, val: u8) {
+        *self.byte_mut(addr) = val;
+    }
+
+    pub fn reset_div(&mut self) {
+        self.m[(0xFF04 - 0x8000) as usize] = 0;
+        self.div_reset = true;
+    }
+
+    pub fn take_div_reset(&mut self) -> bool {
+        let reset = self.div_reset;
+        self.div_reset = false;
+        return reset;
+    }
+
     pub fn set_joypad_low_nibble(&mut self, val: u8) {
         self.m[(0xFF00 - 0x8000) as usize] =
             (self.m[(0xFF00 - 0x8000) as usize] & 0b11110000) | (val & 0b00001111);
     }
 
+    // Palette data writes can move on to the next color on their own, which is set in bit 7 of
+    // the matching palette index register.
+    fn palette_data_set(&mut self, index_addr: u16, val: u8) {
+        let index_idx = (index_addr - 0x8000) as usize;
+        let index = self.m[index_idx];
+        let color_idx = (index & 0x3F) as usize;
+
+        if index_addr == 0xFF68 {
+            self.bg_palette_ram[color_idx] = val;
+        } else {
+            self.obj_palette_ram[color_idx] = val;
+        }
+
+        if index & 0x80 != 0 {
+            self.m[index_idx] = (index & 0x80) | ((index + 1) & 0x3F);
+        }
+    }
+
+    fn io_set(&mut self, addr: u16, val: u8) {
+        let reg = io_register(addr, self.cgb_mode);
+        let idx = (addr - 0x8000) as usize;
+
+        // Palette data is backed by palette RAM rather than the register itself.
+        if self.cgb_mode && (addr == 0xFF69 || addr == 0xFF6B) {
+            self.palette_data_set(addr - 1, val);
+            return;
+        }
+
+        self.m[idx] = (self.m[idx] & !reg.writable) | (val & reg.writable);
+
+        match addr {
+            0xFF04 => {
+                self.reset_div();
+            }
+            0xFF41 => {
+                // On everything before the CGB, writing to STAT briefly enables every STAT
+                // interrupt source. Doing it in HBlank, VBlank or when LY=LYC raises one.
+                let stat = self.m[idx];
+                let lcd_on = self.m[(0xFF40 - 0x8000) as usize] & 0x80 != 0;
+                if !self.model.is_cgb() && lcd_on && (stat & 0b11 < 2 || stat & 0b100 != 0) {
+                    self.m[(0xFF0F - 0x8000) as usize] |= 0b10;
+                }
+            }
+            0xFF46 => {
+                self.start_dma(val);
+            }
+            // KEY0 is how the CGB boot ROM drops into DMG compatibility mode. It's locked once
+            // the boot ROM is gone.
+            0xFF4C if self.model.is_cgb() && self.boot_rom.is_some() => {
+                self.cgb_mode = val & 0b100 == 0;
+            }
+            0xFF55 if self.cgb_mode => {
+                self.start_vram_dma(val);
+            }
+            0xFF4F if self.cgb_mode => {
+                self.vram_bank = (val & 1) as usize;
+            }
+            // Selecting bank 0 gives bank 1
+            0xFF70 if self.cgb_mode => {
+                self.wram_bank = std::cmp::max(1, (val & 0b111) as usize);
+            }
+            // Unmapping the boot ROM is one-way, it can't be mapped back in.
+            0xFF50 if val & 1 != 0 => {
+                self.boot_rom = None;
+            }
+            _ => {}
+        }
+    }
+
     fn special_set(&mut self, addr: u16, val: u8) -> bool {
         match addr {
             0..=0x7FFF => {
                 // TODO: Some of this range triggers things, but it's ROM so writing to it can't go through.
                 return true;
             }
-            0xFF00 => {
-                self.m[(addr - 0x8000) as usize] =
-                    (self.m[(addr - 0x8000) as usize] & 0b00001111) | (val & 0b11110000);
+            0xFEA0..=0xFEFF => {
+                // Unusable, writes are ignored
                 return true;
             }
-            0xFF46 => {
-                self.dma_in_progress_addr = Some((val as u16) << 8);
+            0xFF00..=0xFF7F | 0xFFFF => {
+                self.io_set(addr, val);
                 return true;
             }
             _ => {
@@ -98,21 +767,26 @@ impl Memory {
     }
 
     pub fn set(&mut self, addr: u16, val: u8) {
+        // Writes that get dropped never happened as far as watchpoints are concerned.
+        if self.dma_conflict(addr).is_some() {
+            return;
+        }
+        if !self.cpu_can_access(addr) {
+            if let Some(row) = self.oam_bug_row(addr) {
+                self.corrupt_oam(OamBug::Write, row);
+            }
+            return;
+        }
+
+        if !self.watched_ranges.is_empty() {
+            self.record_access(addr, val, self[addr], true);
+        }
+
         if self.special_set(addr, val) {
             return;
         }
 
-        let byte: &mut u8 = match addr {
-            // This is the first, static ROM bank
-            0..=0x3FFF => &mut self.rom_banks[0][addr as usize],
-            // This is the switchable bank
-            0x4000..=0x7FFF => &mut self.rom_banks[self.current_bank][(addr - 0x4000) as usize],
-            // 0xC000~0xDDFF is mirrored at 0xE000~0xFDFF
-            0xE000..=0xFDFF => &mut self.m[(addr - 0x2000 - 0x8000) as usize],
-            _ => &mut self.m[(addr - 0x8000) as usize],
-        };
-        let _before: u8 = *byte;
-        *byte = val;
+        *self.byte_mut(addr) = val;
     }
 }
 
@@ -120,14 +794,484 @@ impl std::ops::Index<u16> for Memory {
     type Output = u8;
 
     fn index(&self, i: u16) -> &Self::Output {
-        match i {
-            // This is the first, static ROM bank
-            0..=0x3FFF => &self.rom_banks[0][i as usize],
-            // This is the switchable bank
-            0x4000..=0x7FFF => &self.rom_banks[self.current_bank][(i - 0x4000) as usize],
-            // 0xC000~0xDDFF is mirrored at 0xE000~0xFDFF
-            0xE000..=0xFDFF => &self.m[(i - 0x2000 - 0x8000) as usize],
-            _ => &self.m[(i - 0x8000) as usize],
+        let (area, offset) = self.locate(i);
+        return match area {
+            Area::BootRom => &self.boot_rom.as_ref().unwrap()[offset],
+            Area::Rom(bank) => &self.rom_banks[bank][offset],
+            Area::Vram => &self.vram[offset],
+            Area::Wram => &self.wram[offset],
+            Area::BgPalette => &self.bg_palette_ram[offset],
+            Area::ObjPalette => &self.obj_palette_ram[offset],
+            Area::Flat => &self.m[offset],
+        };
+    }
+}
+
+// Tests poke straight into ROM and registers, bypassing what the bus would allow.
+#[cfg(test)]
+impl std::ops::IndexMut<u16> for Memory {
+    fn index_mut(&mut self, i: u16) -> &mut Self::Output {
+        return self.byte_mut(i);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::Memory;
+    use crate::model::Model;
+
+    #[test]
+    fn vram_oam_blocked_by_ppu_mode() {
+        let mut memory = Memory::empty();
+        memory[0x8000] = 0x12;
+        memory[0xFE00] = 0x34;
+        memory[0xFF40] = 0x80;
+
+        // Mode 2: OAM blocked, VRAM accessible
+        memory[0xFF41] = 2;
+        assert_eq!(0x12, memory.read(0x8000));
+        assert_eq!(0xFF, memory.read(0xFE00));
+        memory.set(0xFE00, 0x56);
+        assert_eq!(0x34, memory[0xFE00]);
+
+        // Mode 3: both blocked
+        memory[0xFF41] = 3;
+        assert_eq!(0xFF, memory.read(0x8000));
+        memory.set(0x8000, 0x56);
+        assert_eq!(0x12, memory[0x8000]);
+
+        // LCD off: nothing is blocked
+        memory[0xFF40] = 0x00;
+        assert_eq!(0x12, memory.read(0x8000));
+        assert_eq!(0x34, memory.read(0xFE00));
+
+        memory[0xFF40] = 0x80;
+        memory.set_ppu_access_restrictions(false);
+        assert_eq!(0x12, memory.read(0x8000));
+        memory.set(0x8000, 0x56);
+        assert_eq!(0x56, memory[0x8000]);
+    }
+
+    fn tick_m_cycles(memory: &mut Memory, n: usize) {
+        for _ in 0..(n * 4) {
+            memory.tick();
         }
     }
+
+    #[test]
+    fn oam_dma_timing() {
+        let mut memory = Memory::empty();
+        for i in 0..0xA0u16 {
+            memory[0xC000 + i] = i as u8 + 1;
+        }
+
+        memory.set(0xFF46, 0xC0);
+        assert_eq!(0xC0, memory[0xFF46]);
+
+        // Startup delay: nothing copied and no bus conflict yet
+        tick_m_cycles(&mut memory, 1);
+        assert_eq!(0x00, memory[0xFE00]);
+        assert_eq!(0x01, memory.read(0xC000));
+
+        tick_m_cycles(&mut memory, 1);
+        assert_eq!(0x01, memory[0xFE00]);
+        assert_eq!(0x00, memory[0xFE01]);
+
+        // The CPU sees the byte being transferred everywhere but HRAM
+        memory[0xFF80] = 0x42;
+        assert_eq!(0x01, memory.read(0x0000));
+        assert_eq!(0x01, memory.read(0xC050));
+        assert_eq!(0xFF, memory.read(0xFE00));
+        assert_eq!(0x42, memory.read(0xFF80));
+        memory.set(0xC050, 0x00);
+        assert_eq!(0x51, memory[0xC050]);
+
+        tick_m_cycles(&mut memory, 159);
+        assert_eq!(0xA0, memory[0xFE9F]);
+        assert_eq!(0xA0, memory.read(0xFE9F));
+        assert_eq!(0x51, memory.read(0xC050));
+    }
+
+    #[test]
+    fn oam_dma_separate_buses() {
+        let mut memory = Memory::empty();
+        memory[0x8000] = 0x12;
+        memory[0x8010] = 0x34;
+        memory[0xC000] = 0x56;
+
+        // From WRAM: VRAM is on its own bus and still usable
+        memory.set(0xFF46, 0xC0);
+        tick_m_cycles(&mut memory, 2);
+        assert_eq!(0x12, memory.read(0x8000));
+        assert_eq!(0x56, memory.read(0x0000));
+
+        // From VRAM: the external bus is free, VRAM isn't
+        tick_m_cycles(&mut memory, 160);
+        memory.set(0xFF46, 0x80);
+        tick_m_cycles(&mut memory, 2);
+        assert_eq!(0x12, memory.read(0x8010));
+        assert_eq!(0x56, memory.read(0xC000));
+        memory.set(0xC001, 0x78);
+        assert_eq!(0x78, memory[0xC001]);
+    }
+
+    #[test]
+    fn oam_dma_echo_source() {
+        let mut memory = Memory::empty();
+        memory[0xC000] = 0x12;
+        memory[0xDF00] = 0x34;
+
+        memory.set(0xFF46, 0xE0);
+        tick_m_cycles(&mut memory, 2);
+        assert_eq!(0x12, memory[0xFE00]);
+
+        tick_m_cycles(&mut memory, 160);
+        memory.set(0xFF46, 0xFF);
+        tick_m_cycles(&mut memory, 2);
+        assert_eq!(0x34, memory[0xFE00]);
+    }
+
+    #[test]
+    fn oam_dma_restart() {
+        let mut memory = Memory::empty();
+        for i in 0..0xA0u16 {
+            memory[0xC000 + i] = 0x11;
+            memory[0xD000 + i] = 0x22;
+        }
+
+        memory.set(0xFF46, 0xC0);
+        tick_m_cycles(&mut memory, 11);
+        memory.set(0xFF46, 0xD0);
+
+        // The first transfer keeps going during the second one's startup delay
+        tick_m_cycles(&mut memory, 1);
+        assert_eq!(0x11, memory[0xFE0A]);
+        assert_eq!(0xFF, memory.read(0xFE00));
+
+        tick_m_cycles(&mut memory, 1);
+        assert_eq!(0x22, memory[0xFE00]);
+        assert_eq!(0x00, memory[0xFE0B]);
+
+        tick_m_cycles(&mut memory, 159);
+        assert_eq!(0x22, memory[0xFE9F]);
+    }
+
+    #[test]
+    fn io_register_masks() {
+        let mut memory = Memory::empty();
+
+        // Unused bits read back as 1 whatever was written
+        for &(addr, unused) in [
+            (0xFF00, 0xC0),
+            (0xFF07, 0xF8),
+            (0xFF0F, 0xE0),
+            (0xFF41, 0x80),
+        ]
+        .iter()
+        {
+            memory.set(addr, 0x00);
+            assert_eq!(unused, memory.read(addr) & unused);
+        }
+
+        // Unmapped registers always read 0xFF
+        memory.set(0xFF03, 0x00);
+        assert_eq!(0xFF, memory.read(0xFF03));
+        memory.set(0xFF4C, 0x00);
+        assert_eq!(0xFF, memory.read(0xFF4C));
+
+        // Only the interrupt bits of IF can be written, all of IE can be
+        memory.set(0xFF0F, 0xFF);
+        assert_eq!(0x1F, memory[0xFF0F]);
+        memory.set(0xFFFF, 0xFF);
+        assert_eq!(0xFF, memory.read(0xFFFF));
+
+        // STAT mode and LYC flags are read only
+        memory[0xFF41] = 0b101;
+        memory.set(0xFF41, 0xFF);
+        assert_eq!(0xFD, memory.read(0xFF41));
+    }
+
+    #[test]
+    fn io_register_side_effects() {
+        let mut memory = Memory::empty();
+
+        memory[0xFF44] = 0x42;
+        memory.set(0xFF44, 0x00);
+        assert_eq!(0x42, memory.read(0xFF44));
+
+        memory[0xFF04] = 0x42;
+        memory.set(0xFF04, 0x12);
+        assert_eq!(0x00, memory.read(0xFF04));
+        assert!(memory.take_div_reset());
+        assert!(!memory.take_div_reset());
+    }
+
+    #[test]
+    fn unusable_region() {
+        let mut memory = Memory::empty();
+
+        memory.set(0xFEA0, 0x12);
+        assert_eq!(0x00, memory.read(0xFEA0));
+        assert_eq!(0x00, memory.read(0xFEFF));
+
+        memory[0xFF40] = 0x80;
+        memory[0xFF41] = 2;
+        assert_eq!(0xFF, memory.read(0xFEA0));
+    }
+
+    #[test]
+    fn boot_rom_unmap() {
+        let mut memory = Memory::empty();
+        memory[0x0000] = 0x12;
+        memory[0x0100] = 0x34;
+        memory.load_boot_rom(vec![0xAB; 0x100]);
+
+        assert_eq!(0xAB, memory.read(0x0000));
+        assert_eq!(0x34, memory.read(0x0100));
+
+        memory.set(0xFF50, 0x00);
+        assert_eq!(0xAB, memory.read(0x0000));
+        memory.set(0xFF50, 0x01);
+        assert_eq!(0x12, memory.read(0x0000));
+        assert_eq!(0xFF, memory.read(0xFF50));
+    }
+
+    #[test]
+    fn dmg_stat_write_quirk() {
+        let mut memory = Memory::empty();
+        memory[0xFF40] = 0x80;
+
+        memory[0xFF41] = 2;
+        memory.set(0xFF41, 0x00);
+        assert_eq!(0x00, memory[0xFF0F]);
+
+        memory[0xFF41] = 0;
+        memory.set(0xFF41, 0x00);
+        assert_eq!(0b10, memory[0xFF0F]);
+    }
+
+    #[test]
+    fn oam_bug() {
+        let fill_oam = |memory: &mut Memory| {
+            for i in 0..0xA0 {
+                memory[0xFE00 + i] = i as u8;
+            }
+        };
+        // Third M-cycle of the OAM scan, the PPU is on row 2
+        let start_oam_scan = |memory: &mut Memory| {
+            memory[0xFF40] = 0x80;
+            memory[0xFF41] = 0;
+            memory.tick();
+            memory[0xFF41] = 2;
+            for _ in 0..9 {
+                memory.tick();
+            }
+        };
+
+        let mut memory = Memory::empty();
+        fill_oam(&mut memory);
+        start_oam_scan(&mut memory);
+        memory.set(0xFE40, 0xFF);
+        // a = 0x10, b = 0x08, c = 0x0C: ((a ^ c) & (b ^ c)) ^ c
+        assert_eq!(0x08, memory[0xFE10]);
+        assert_eq!(0x09, memory[0xFE11]);
+        assert_eq!(0x0A, memory[0xFE12]);
+        assert_eq!(0x0F, memory[0xFE17]);
+        assert_eq!(0x18, memory[0xFE18]);
+
+        fill_oam(&mut memory);
+        memory.idu_access(0xFE00);
+        assert_eq!(0x08, memory[0xFE10]);
+
+        // Reads land on the next tick, b | (a & c)
+        fill_oam(&mut memory);
+        assert_eq!(0xFF, memory.read(0xFE00));
+        assert_eq!(0x10, memory[0xFE10]);
+        memory.tick();
+        assert_eq!(0x08, memory[0xFE10]);
+        assert_eq!(0x0A, memory[0xFE12]);
+
+        // Not outside the OAM scan, nor on the row the PPU is done with first
+        fill_oam(&mut memory);
+        memory[0xFF41] = 3;
+        memory.set(0xFE40, 0xFF);
+        memory[0xFF41] = 0;
+        memory.tick();
+        memory[0xFF41] = 2;
+        memory.tick();
+        memory.set(0xFE40, 0xFF);
+        memory.idu_access(0xFE00);
+        for i in 0..0xA0 {
+            assert_eq!(i as u8, memory[0xFE00 + i]);
+        }
+
+        let mut rom = vec![0; 0x8000];
+        rom[0x0143] = 0x80;
+        let mut memory = Memory::new(&rom, Model::Cgb);
+        fill_oam(&mut memory);
+        start_oam_scan(&mut memory);
+        memory.set(0xFE40, 0xFF);
+        memory.idu_access(0xFE00);
+        memory.read(0xFE00);
+        memory.tick();
+        for i in 0..0xA0 {
+            assert_eq!(i as u8, memory[0xFE00 + i]);
+        }
+    }
+
+    #[test]
+    fn cgb_banking() {
+        let mut memory = Memory::empty_cgb();
+
+        memory.set(0x8000, 0x11);
+        memory.set(0xD000, 0x22);
+        memory.set(0xFF4F, 0x01);
+        memory.set(0xFF70, 0x02);
+        assert_eq!(0x00, memory.read(0x8000));
+        assert_eq!(0x00, memory.read(0xD000));
+        memory.set(0x8000, 0x33);
+        memory.set(0xD000, 0x44);
+        assert_eq!(0xFF, memory.read(0xFF4F));
+        assert_eq!(0xFA, memory.read(0xFF70));
+
+        memory.set(0xFF4F, 0x00);
+        memory.set(0xFF70, 0x00);
+        assert_eq!(0x11, memory.read(0x8000));
+        assert_eq!(0x22, memory.read(0xD000));
+        assert_eq!(0x33, memory.read_vram(1, 0x8000));
+    }
+
+    #[test]
+    fn cgb_palette_auto_increment() {
+        let mut memory = Memory::empty_cgb();
+
+        memory.set(0xFF68, 0x80 | 0x3E);
+        memory.set(0xFF69, 0xFF);
+        memory.set(0xFF69, 0x7F);
+        memory.set(0xFF69, 0x1F);
+        assert_eq!(0x7FFF, memory.bg_palette_color(7, 3));
+        assert_eq!(0x001F, memory.bg_palette_color(0, 0) & 0x00FF);
+        assert_eq!(0xC1, memory.read(0xFF68));
+
+        memory.set(0xFF6A, 0x02);
+        memory.set(0xFF6B, 0x12);
+        memory.set(0xFF6B, 0x34);
+        assert_eq!(0x34, memory.obj_palette_color(0, 1) & 0xFF);
+        assert_eq!(0x02 | 0x40, memory.read(0xFF6A));
+    }
+
+    #[test]
+    fn dmg_has_no_cgb_registers() {
+        let mut memory = Memory::empty();
+        memory.set(0xD000, 0x22);
+        memory.set(0xFF70, 0x02);
+        assert_eq!(0xFF, memory.read(0xFF70));
+        assert_eq!(0x22, memory.read(0xD000));
+    }
+
+    #[test]
+    fn cgb_speed_switch() {
+        let mut memory = Memory::empty_cgb();
+
+        assert!(!memory.switch_speed());
+        memory.set(0xFF4D, 0xFF);
+        assert_eq!(0x7F, memory.read(0xFF4D));
+
+        assert!(memory.switch_speed());
+        assert!(memory.double_speed());
+        assert_eq!(0xFE, memory.read(0xFF4D));
+
+        memory.set(0xFF4D, 0x01);
+        assert!(memory.switch_speed());
+        assert!(!memory.double_speed());
+        assert_eq!(0x7E, memory.read(0xFF4D));
+    }
+
+    #[test]
+    fn general_purpose_vram_dma() {
+        let mut memory = Memory::empty_cgb();
+        for i in 0..0x20 {
+            memory.set(0xC000 + i, i as u8 + 1);
+        }
+        memory.set(0xFF51, 0xC0);
+        memory.set(0xFF52, 0x00);
+        memory.set(0xFF53, 0x01);
+        memory.set(0xFF54, 0x00);
+        memory.set(0xFF55, 0x01);
+
+        assert!(memory.vram_dma_stalls_cpu());
+        assert_eq!(0x01, memory.read(0xFF55));
+        tick_m_cycles(&mut memory, 8);
+        assert_eq!(0x00, memory.read(0xFF55));
+        tick_m_cycles(&mut memory, 8);
+        assert!(!memory.vram_dma_stalls_cpu());
+        assert_eq!(0xFF, memory.read(0xFF55));
+        assert_eq!(0x01, memory.read(0x8100));
+        assert_eq!(0x20, memory.read(0x811F));
+    }
+
+    #[test]
+    fn vram_dma_double_speed() {
+        let mut memory = Memory::empty_cgb();
+        memory.set(0xFF4D, 0x01);
+        memory.switch_speed();
+        memory.set(0xFF55, 0x00);
+
+        tick_m_cycles(&mut memory, 15);
+        assert!(memory.vram_dma_stalls_cpu());
+        tick_m_cycles(&mut memory, 1);
+        assert!(!memory.vram_dma_stalls_cpu());
+    }
+
+    #[test]
+    fn hblank_vram_dma() {
+        let mut memory = Memory::empty_cgb();
+        memory[0xFF40] = 0x80;
+        memory[0xFF41] = 3;
+        memory.set(0xC010, 0xAB);
+        memory.set(0xFF51, 0xC0);
+        memory.set(0xFF52, 0x00);
+        memory.set(0xFF55, 0x82);
+
+        tick_m_cycles(&mut memory, 10);
+        assert!(!memory.vram_dma_stalls_cpu());
+        assert_eq!(0x02, memory.read(0xFF55));
+
+        memory[0xFF41] = 0;
+        tick_m_cycles(&mut memory, 8);
+        assert!(!memory.vram_dma_stalls_cpu());
+        assert_eq!(0x01, memory.read(0xFF55));
+        memory[0xFF41] = 3;
+        tick_m_cycles(&mut memory, 1);
+        memory[0xFF41] = 0;
+        tick_m_cycles(&mut memory, 8);
+        assert_eq!(0xAB, memory.read(0x8010));
+        assert_eq!(0x00, memory.read(0xFF55));
+
+        memory.set(0xFF55, 0x00);
+        assert_eq!(0x80, memory.read(0xFF55));
+        assert!(!memory.vram_dma_stalls_cpu());
+    }
+
+    #[test]
+    fn peek_bank() {
+        let mut rom = vec![0; 0xC000];
+        rom[0x0143] = 0x80;
+        rom[0x8000] = 0x12;
+        let mut memory = Memory::new(&rom, Model::Cgb);
+        memory.set(0xFF70, 0x02);
+        memory.set(0xD000, 0x34);
+        memory.set(0xFF70, 0x01);
+
+        assert_eq!(Some(0x12), memory.peek_bank(0x4000, 2));
+        assert_eq!(Some(0x00), memory.peek_bank(0x4000, 1));
+        assert_eq!(None, memory.peek_bank(0x4000, 3));
+        assert_eq!(None, memory.peek_bank(0x0000, 1));
+        assert_eq!(Some(0x34), memory.peek_bank(0xD000, 2));
+        assert_eq!(0x00, memory[0xD000]);
+        assert_eq!(None, memory.peek_bank(0xC000, 0));
+
+        assert_eq!(0x8000, Memory::bank_end(0x7FF0));
+        assert_eq!(0xE000, Memory::bank_end(0xD000));
+    }
 }
@@ -68,7 +68,7 @@ impl Location {
         } else if self.is_register {
             return registers.read_byte(self.register);
         } else {
-            return memory[self.address].to_be_bytes()[0];
+            return memory.read(self.address);
         }
     }
 
@@ -79,7 +79,8 @@ impl Location {
         } else if self.is_register {
             return registers.read_word(self.register);
         } else {
-            return ((memory[self.address + 1] as u16) << 8) | (memory[self.address] as u16);
+            return ((memory.read(self.address.wrapping_add(1)) as u16) << 8)
+                | (memory.read(self.address) as u16);
         }
     }
 
@@ -0,0 +1,65 @@
+use crate::cartridge::Cartridge;
+
+// The hardware revision being emulated. Games can tell them apart from the registers the boot
+// ROM leaves behind, and some of them behave slightly differently: models before the CGB have
+// the STAT write bug and the OAM corruption bug, see |Memory|. The timer works the same on all
+// of them, only DIV's phase when the boot ROM hands over differs.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Model {
+    Dmg0,
+    Dmg,
+    Mgb,
+    Sgb,
+    Sgb2,
+    Cgb,
+    Agb,
+}
+
+impl Model {
+    pub fn from_name(name: &str) -> Option<Model> {
+        return match name.to_lowercase().as_str() {
+            "dmg0" => Some(Model::Dmg0),
+            "dmg" => Some(Model::Dmg),
+            "mgb" => Some(Model::Mgb),
+            "sgb" => Some(Model::Sgb),
+            "sgb2" => Some(Model::Sgb2),
+            "cgb" => Some(Model::Cgb),
+            "agb" => Some(Model::Agb),
+            _ => None,
+        };
+    }
+
+    // Color games get a CGB, everything else a DMG.
+    pub fn from_cartridge(cart: &Cartridge) -> Model {
+        if cart.supports_cgb() {
+            return Model::Cgb;
+        }
+
+        return Model::Dmg;
+    }
+
+    pub fn is_cgb(&self) -> bool {
+        return *self == Model::Cgb || *self == Model::Agb;
+    }
+
+    pub fn is_sgb(&self) -> bool {
+        return *self == Model::Sgb || *self == Model::Sgb2;
+    }
+
+    pub fn boot_rom_size(&self) -> usize {
+        return if self.is_cgb() { 0x900 } else { 0x100 };
+    }
+
+    // What the model's boot ROM is usually called, for picking it out of --boot-rom-dir.
+    pub fn boot_rom_name(&self) -> &'static str {
+        return match self {
+            Model::Dmg0 => "dmg0_boot.bin",
+            Model::Dmg => "dmg_boot.bin",
+            Model::Mgb => "mgb_boot.bin",
+            Model::Sgb => "sgb_boot.bin",
+            Model::Sgb2 => "sgb2_boot.bin",
+            Model::Cgb => "cgb_boot.bin",
+            Model::Agb => "agb_boot.bin",
+        };
+    }
+}
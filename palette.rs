@@ -0,0 +1,107 @@
+// Colors used for each of the 4 DMG shades, from lightest to darkest, as 0xRRGGBB. BG/window,
+// and objects using OBP0 or OBP1 each get their own so sprites can stand out.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Palette {
+    pub bg: [u32; 4],
+    pub obj0: [u32; 4],
+    pub obj1: [u32; 4],
+}
+
+pub const PRESETS: [(&str, [u32; 4]); 4] = [
+    ("grey", [0xFFFFFF, 0xAAAAAA, 0x555555, 0x000000]),
+    ("pocket", [0xE0DBCD, 0xA89F94, 0x706B66, 0x2B2B26]),
+    ("pea-green", [0x9BBC0F, 0x8BAC0F, 0x306230, 0x0F380F]),
+    ("high-contrast", [0xFFFFFF, 0xFFD800, 0x0050FF, 0x000000]),
+];
+
+impl Palette {
+    // Same colors for every layer, like on the actual hardware.
+    pub fn uniform(colors: [u32; 4]) -> Palette {
+        return Palette {
+            bg: colors,
+            obj0: colors,
+            obj1: colors,
+        };
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        return Palette::uniform(PRESETS[0].1);
+    }
+}
+
+const fn stretch(v: u32) -> u32 {
+    return (v << 3) | (v >> 2);
+}
+
+// CGB and SGB colors are RGB555, stretched here to the full 0~255 range.
+pub const fn rgb555_to_rgb(c: u16) -> u32 {
+    let r = (c & 0x1F) as u32;
+    let g = ((c >> 5) & 0x1F) as u32;
+    let b = ((c >> 10) & 0x1F) as u32;
+    return (stretch(r) << 16) | (stretch(g) << 8) | stretch(b);
+}
+
+// 0xRRGGBB pixels to the 3 bytes per pixel most everything else wants.
+pub fn to_rgb24(pixels: &[u32]) -> std::vec::Vec<u8> {
+    let mut rgb = vec![0; pixels.len() * 3];
+    for (i, p) in pixels.iter().enumerate() {
+        rgb[i * 3] = (p >> 16) as u8;
+        rgb[i * 3 + 1] = (p >> 8) as u8;
+        rgb[i * 3 + 2] = *p as u8;
+    }
+    return rgb;
+}
+
+pub fn preset_names() -> std::vec::Vec<&'static str> {
+    return PRESETS.iter().map(|(name, _)| *name).collect();
+}
+
+// Either the name of a preset, or 4 comma separated hex colors like "#E0F8D0,88C070,346856,081820".
+pub fn parse_colors(spec: &str) -> Result<[u32; 4], String> {
+    if let Some((_, colors)) = PRESETS.iter().find(|(name, _)| *name == spec) {
+        return Ok(*colors);
+    }
+
+    let parts: std::vec::Vec<&str> = spec.split(',').map(|p| p.trim()).collect();
+    if parts.len() != 4 {
+        return Err(format!(
+            "Invalid palette {}, expected one of {} or 4 comma separated RGB hex colors",
+            spec,
+            preset_names().join(", ")
+        ));
+    }
+
+    let mut colors = [0; 4];
+    for (i, part) in parts.iter().enumerate() {
+        let hex = part.trim_start_matches('#');
+        if hex.len() != 6 {
+            return Err(format!("Invalid color {}, expected RRGGBB", part));
+        }
+        colors[i] = u32::from_str_radix(hex, 16)
+            .map_err(|_| format!("Invalid color {}, expected RRGGBB", part))?;
+    }
+
+    return Ok(colors);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::palette;
+
+    #[test]
+    fn parse_colors() {
+        assert_eq!(
+            Ok([0x9BBC0F, 0x8BAC0F, 0x306230, 0x0F380F]),
+            palette::parse_colors("pea-green")
+        );
+        assert_eq!(
+            Ok([0xE0F8D0, 0x88C070, 0x346856, 0x081820]),
+            palette::parse_colors("#E0F8D0, 88c070,#346856,081820")
+        );
+        assert!(palette::parse_colors("E0F8D0,88C070,346856").is_err());
+        assert!(palette::parse_colors("E0F8D0,88C070,346856,08182G").is_err());
+        assert!(palette::parse_colors("purple").is_err());
+    }
+}
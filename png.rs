@@ -0,0 +1,151 @@
+// Just enough of a PNG encoder for screenshots. Pixel data goes in zlib "stored" blocks, so
+// nothing gets compressed, but a 160x144 picture is small enough that nobody should care.
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+// Color types from the IHDR chunk
+const GRAYSCALE: u8 = 0;
+const RGB: u8 = 2;
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    return !crc;
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let mut a = 1u32;
+    let mut b = 0u32;
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    return (b << 16) | a;
+}
+
+fn write_chunk(out: &mut std::vec::Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> std::vec::Vec<u8> {
+    // No compression, smallest window
+    let mut out = vec![0x08, 0x1D];
+
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(if last { 1 } else { 0 });
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    return out;
+}
+
+// |rows| is the already packed pixel data, one slice per row.
+fn encode(
+    width: u32,
+    height: u32,
+    bit_depth: u8,
+    color_type: u8,
+    rows: &[&[u8]],
+) -> std::vec::Vec<u8> {
+    let mut header = vec![];
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // No interlacing, default compression and filtering
+    header.extend_from_slice(&[bit_depth, color_type, 0, 0, 0]);
+
+    // Every row starts with its filter type, 0 being none
+    let mut raw = vec![];
+    for row in rows {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    let mut out = SIGNATURE.to_vec();
+    write_chunk(&mut out, b"IHDR", &header);
+    write_chunk(&mut out, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut out, b"IEND", &[]);
+    return out;
+}
+
+// |pixels| are 0xRRGGBB, row by row.
+pub fn encode_rgb(width: u32, height: u32, pixels: &[u32]) -> std::vec::Vec<u8> {
+    let rows: std::vec::Vec<std::vec::Vec<u8>> = pixels
+        .chunks(width as usize)
+        .map(|row| {
+            row.iter()
+                .flat_map(|p| vec![(p >> 16) as u8, (p >> 8) as u8, *p as u8])
+                .collect()
+        })
+        .collect();
+    let rows: std::vec::Vec<&[u8]> = rows.iter().map(|r| r.as_slice()).collect();
+    return encode(width, height, 8, RGB, &rows);
+}
+
+// 2 bit grayscale, |indices| going from 0 to 3. These are written as is, so 0 comes out black
+// even though it's the lightest shade on the Game Boy.
+pub fn encode_gray2(width: u32, height: u32, indices: &[u8]) -> std::vec::Vec<u8> {
+    let rows: std::vec::Vec<std::vec::Vec<u8>> = indices
+        .chunks(width as usize)
+        .map(|row| {
+            let mut packed = vec![0; row.len().div_ceil(4)];
+            for (i, idx) in row.iter().enumerate() {
+                packed[i / 4] |= (idx & 0b11) << (6 - (i % 4) * 2);
+            }
+            packed
+        })
+        .collect();
+    let rows: std::vec::Vec<&[u8]> = rows.iter().map(|r| r.as_slice()).collect();
+    return encode(width, height, 2, GRAYSCALE, &rows);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::png;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(0xCBF43926, png::crc32(b"123456789"));
+        assert_eq!(0xAE426082, png::crc32(b"IEND"));
+        assert_eq!(0x11E60398, png::adler32(b"Wikipedia"));
+    }
+
+    #[test]
+    fn encode_gray2() {
+        let data = png::encode_gray2(5, 1, &[0, 1, 2, 3, 3]);
+        assert_eq!(&png::SIGNATURE, &data[0..8]);
+
+        // IHDR: 5x1, 2 bit grayscale
+        assert_eq!(b"IHDR", &data[12..16]);
+        assert_eq!(&[0, 0, 0, 5, 0, 0, 0, 1, 2, 0, 0, 0, 0], &data[16..29]);
+
+        // IDAT: zlib header, a single final stored block, then the filter byte and the packed row
+        assert_eq!(b"IDAT", &data[37..41]);
+        assert_eq!(&[0x08, 0x1D, 1, 3, 0, 0xFC, 0xFF], &data[41..48]);
+        assert_eq!(&[0, 0b00011011, 0b11000000], &data[48..51]);
+
+        assert_eq!(b"IEND", &data[data.len() - 8..data.len() - 4]);
+    }
+}
@@ -1,7 +1,7 @@
-use crate::display::Display;
 use crate::memory::Memory;
-
-use sdl2::pixels::Color;
+use crate::palette;
+use crate::palette::Palette;
+use crate::sgb::Sgb;
 
 struct ObjectAttribute {
     y: u8,
@@ -15,20 +15,213 @@ pub struct Ppu {
     window_line: u16,
     drew_window_on_line: bool,
     curr_line_objects: Vec<ObjectAttribute>,
+    // Colors for the DMG shades. CGB and CGB compatibility mode use palette RAM instead.
+    pub palette: Palette,
+    // The last frame as 0xRRGGBB, and the color indices that made it. Those are DMG shades
+    // after going through the palette registers, or CGB color numbers in CGB mode.
+    frame: std::vec::Vec<u32>,
+    frame_indices: std::vec::Vec<u8>,
 }
 
 impl Ppu {
-    pub fn new() -> Ppu {
+    pub fn new(palette: Palette) -> Ppu {
         return Ppu {
             lx: 0,
             window_line: 0,
             drew_window_on_line: false,
             curr_line_objects: vec![],
+            palette: palette,
+            frame: vec![0xFFFFFF; 160 * 144],
+            frame_indices: vec![0; 160 * 144],
+        };
+    }
+
+    pub fn frame(&self) -> &[u32] {
+        return &self.frame;
+    }
+
+    pub fn frame_indices(&self) -> &[u8] {
+        return &self.frame_indices;
+    }
+
+    fn put_pixel(&mut self, x: u8, y: u8, rgb: u32, index: u8) {
+        let idx = y as usize * 160 + x as usize;
+        self.frame[idx] = rgb;
+        self.frame_indices[idx] = index;
+    }
+
+    // Picks the first 10 objects in OAM that are on the current line. Object Y is the screen
+    // line + 16, so objects can be partly above the top of the screen.
+    fn oam_search(&mut self, memory: &Memory) {
+        self.curr_line_objects.clear();
+        if memory[0xFF40] & 0b10 == 0 {
+            return;
+        }
+
+        let obj_size_16 = (memory[0xFF40] & 0b100) != 0;
+        let height = if obj_size_16 { 16 } else { 8 };
+        let line = memory[0xFF44] as u16 + 16;
+
+        for i in (0xFE00..0xFE9F).step_by(4) {
+            let y_min = memory[i] as u16;
+            if line >= y_min && line < y_min + height {
+                self.curr_line_objects.push(ObjectAttribute {
+                    y: memory[i],
+                    x: memory[i + 1],
+                    // In 8x16 mode the lowest bit of the tile index is ignored
+                    tile_idx: memory[i + 2] & if obj_size_16 { 0xFE } else { 0xFF },
+                    attributes: memory[i + 3],
+                });
+
+                if self.curr_line_objects.len() == 10 {
+                    break;
+                }
+            }
+        }
+    }
+
+    // Color index (0~3) of |obj| at the given screen coordinates, which the caller has already
+    // checked are covered by the object.
+    fn obj_color_idx(
+        &self,
+        memory: &Memory,
+        obj: &ObjectAttribute,
+        screen_x: u8,
+        screen_y: u8,
+    ) -> u8 {
+        let obj_size_16 = (memory[0xFF40] & 0b100) != 0;
+        let height = if obj_size_16 { 16 } else { 8 };
+        let h_flip = obj.attributes & 0b100000 != 0;
+        let v_flip = obj.attributes & 0b1000000 != 0;
+
+        let mut row = screen_y as u16 + 16 - obj.y as u16;
+        if v_flip {
+            row = height - 1 - row;
+        }
+        let mut col = screen_x + 8 - obj.x;
+        if h_flip {
+            col = 7 - col;
+        }
+
+        // In 8x16 mode the top tile always has an even index and the bottom one follows it
+        let tile_id = obj.tile_idx as u16 + row / 8;
+        let tile_addr = 0x8000 + tile_id * 16 + (row % 8) * 2;
+        let bank = if memory.cgb_mode() {
+            ((obj.attributes >> 3) & 1) as usize
+        } else {
+            0
         };
+
+        let lsb = memory.read_vram(bank, tile_addr);
+        let msb = memory.read_vram(bank, tile_addr + 1);
+        return (((msb >> (7 - col)) & 1) << 1) | ((lsb >> (7 - col)) & 1);
+    }
+
+    // The object whose pixel shows up at the given coordinates, along with its color index.
+    // Transparent pixels let the next object through. With |x_priority| the object with the
+    // lowest X wins, otherwise (and on ties) the one that comes first in OAM does.
+    fn select_object(
+        &self,
+        memory: &Memory,
+        screen_x: u8,
+        screen_y: u8,
+        x_priority: bool,
+    ) -> Option<(&ObjectAttribute, u8)> {
+        let mut best: Option<(u16, &ObjectAttribute, u8)> = None;
+
+        // Objects were gathered in OAM order so their index in the vector works as well.
+        for (i, obj) in self.curr_line_objects.iter().enumerate() {
+            if !(screen_x as u16 + 8 >= obj.x as u16 && (screen_x as u16) < obj.x as u16) {
+                continue;
+            }
+
+            let color_idx = self.obj_color_idx(memory, obj, screen_x, screen_y);
+            if color_idx == 0 {
+                continue;
+            }
+
+            let key = if x_priority {
+                ((obj.x as u16) << 8) | i as u16
+            } else {
+                i as u16
+            };
+            if best.is_none_or(|(best_key, _, _)| key < best_key) {
+                best = Some((key, obj, color_idx));
+            }
+        }
+
+        return best.map(|(_, obj, color_idx)| (obj, color_idx));
+    }
+
+    // CGB mode pixel: BG map attributes live in VRAM bank 1 and colors come from palette RAM.
+    fn cgb_pixel(
+        &self,
+        memory: &Memory,
+        screen_x: u8,
+        screen_y: u8,
+        is_window: bool,
+        map_addr: u16,
+    ) -> (u32, u8) {
+        let lcdc = memory[0xFF40];
+
+        let (map_x, map_y) = if is_window {
+            ((7 + screen_x - memory[0xFF4B]) as u16, self.window_line)
+        } else {
+            (
+                screen_x.wrapping_add(memory[0xFF43]) as u16,
+                screen_y.wrapping_add(memory[0xFF42]) as u16,
+            )
+        };
+
+        let map_entry = map_addr + (map_y / 8) * 32 + map_x / 8;
+        let tile_id = memory.read_vram(0, map_entry);
+        let attributes = memory.read_vram(1, map_entry);
+
+        let tile_addr = if lcdc & 0b10000 == 0 {
+            0x8800 + tile_id.wrapping_add(128) as u16 * 16
+        } else {
+            0x8000 + tile_id as u16 * 16
+        };
+
+        let mut y_offset = map_y % 8;
+        if attributes & 0b1000000 != 0 {
+            y_offset = 7 - y_offset;
+        }
+        let mut x_offset = (map_x % 8) as u8;
+        if attributes & 0b100000 != 0 {
+            x_offset = 7 - x_offset;
+        }
+
+        let bank = ((attributes >> 3) & 1) as usize;
+        let lsb = memory.read_vram(bank, tile_addr + 2 * y_offset);
+        let msb = memory.read_vram(bank, tile_addr + 2 * y_offset + 1);
+        let bg_color_idx = (((msb >> (7 - x_offset)) & 1) << 1) | ((lsb >> (7 - x_offset)) & 1);
+
+        let mut color = memory.bg_palette_color(attributes & 0b111, bg_color_idx);
+        let mut final_color_idx = bg_color_idx;
+
+        if lcdc & 0b10 != 0 {
+            // Objects are prioritized by OAM order, unless OPRI asks for DMG-style X ordering.
+            let x_priority = memory[0xFF6C] & 1 != 0;
+            let best = self.select_object(memory, screen_x, screen_y, x_priority);
+            if let Some((obj, color_idx)) = best {
+                // With LCDC bit 0 cleared, objects always win over BG and window
+                let bg_wins = lcdc & 1 != 0
+                    && bg_color_idx != 0
+                    && (attributes & 0x80 != 0 || obj.attributes & 0x80 != 0);
+                if !bg_wins {
+                    color = memory.obj_palette_color(obj.attributes & 0b111, color_idx);
+                    final_color_idx = color_idx;
+                }
+            }
+        }
+
+        return (palette::rgb555_to_rgb(color), final_color_idx);
     }
 
     // each tick is one dot, so 1 TCycle
-    pub fn tick(&mut self, memory: &mut Memory, display: &mut Display) -> bool {
+    // |sgb| is there when running on an SGB, which colors the screen.
+    pub fn tick(&mut self, memory: &mut Memory, sgb: Option<&Sgb>) -> bool {
         let mut has_frame = false;
 
         let mode = memory[0xFF41] & 0b11;
@@ -45,33 +238,7 @@ impl Ppu {
                     // Perform all of OAM search on the last dot if obj is enabled/
                     // TODO: This is incorrect, but since some things write to registers during OAM search,
                     //       it's probably more useful to do the search at the end than at the start of the interval.
-                    self.curr_line_objects.clear();
-                    if memory[0xFF40] & 0b10 != 0 {
-                        for i in (0xFE00..0xFE9F).step_by(4) {
-                            let obj_size_16 = (memory[0xFF40] & 0b100) != 0;
-
-                            if memory[i] >= 160 {
-                                continue;
-                            }
-
-                            let y_min = memory[i];
-                            let y_max = y_min + if obj_size_16 { 16 } else { 8 };
-
-                            if memory[0xFF44] + 16 >= y_min && memory[0xFF44] + 16 < y_max {
-                                // Object is on line
-                                self.curr_line_objects.push(ObjectAttribute {
-                                    y: memory[i],
-                                    x: memory[i + 1],
-                                    tile_idx: memory[i + 2] & if obj_size_16 { 0xFE } else { 0xFF },
-                                    attributes: memory[i + 3],
-                                });
-
-                                if self.curr_line_objects.len() == 10 {
-                                    break;
-                                }
-                            }
-                        }
-                    }
+                    self.oam_search(memory);
                 }
             }
             3 => {
@@ -93,7 +260,7 @@ impl Ppu {
                         0x9C00
                     };
 
-                    let debug_pixel_color: Option<Color> = None;
+                    let debug_pixel_color: Option<u32> = None;
 
                     // Approximate whatever shittery the PPU and Pixel FIFOs do
                     // by generating a full line right now.
@@ -107,10 +274,24 @@ impl Ppu {
                             self.drew_window_on_line = true;
                         }
 
+                        if memory.cgb_mode() {
+                            let map_addr = if is_window {
+                                window_tile_map_addr
+                            } else {
+                                bg_tile_map_addr
+                            };
+                            let (rgb, color_idx) =
+                                self.cgb_pixel(memory, screen_x, screen_y, is_window, map_addr);
+                            self.put_pixel(screen_x, screen_y, rgb, color_idx);
+                            continue;
+                        }
+
                         let scy = if is_window { 0 } else { memory[0xFF42] };
                         let scx = if is_window { 0 } else { memory[0xFF43] };
 
                         let mut color = 0;
+                        // Before going through BGP, objects behind the BG only show over color 0
+                        let mut bg_color_idx = 0;
 
                         // Draw BG/Window
                         if memory[0xFF40] & 1 != 0 {
@@ -152,109 +333,56 @@ impl Ppu {
                                 | ((msb & mask) >> (7 - x_offset) << 1);
 
                             color = (memory[0xFF47] >> (color_idx * 2)) & 0b11;
+                            bg_color_idx = color_idx;
                         }
 
-                        let bg_window_color = color;
-
-                        // Objects
-                        // TODO: obj to bg priority
-                        let mut best_obj_prio = 0xFF;
-                        for obj in self.curr_line_objects.iter() {
-                            if screen_x + 8 >= obj.x && screen_x < obj.x && best_obj_prio > obj.x {
-                                if obj.attributes & 0x80 != 0 && bg_window_color != 0 {
-                                    continue;
-                                }
-
-                                best_obj_prio = obj.x;
-
-                                let mut y_offset = screen_y + 16 - obj.y;
-                                let x_offset = screen_x + 8 - obj.x;
-
-                                let h_flip = obj.attributes & 0b100000 != 0;
-                                let v_flip = obj.attributes & 0b1000000 != 0;
-
-                                let obj_size_16 = (memory[0xFF40] & 0b100) != 0;
-                                let corrected_tile_id = obj.tile_idx as u16
-                                    + if (!v_flip && y_offset > 7)
-                                        || (obj_size_16 && v_flip && y_offset < 8)
-                                    {
-                                        1
-                                    } else {
-                                        0
-                                    };
-
-                                // No need to check for the y coord to be inside the object, it wouldn't be in the vector if it wasn't
-                                let tile_addr = 0x8000 + corrected_tile_id * 16;
-                                y_offset = y_offset % 8;
-
-                                let lsb = if v_flip {
-                                    memory[tile_addr + 15 - (2 * y_offset as u16) - 1]
-                                } else {
-                                    memory[tile_addr + (2 * y_offset as u16)]
-                                };
-
-                                let msb = if v_flip {
-                                    memory[tile_addr + 15 - (2 * y_offset as u16)]
-                                } else {
-                                    memory[tile_addr + (2 * y_offset as u16) + 1]
-                                };
-
-                                let palette_addr = if obj.attributes & 0b10000 == 0 {
-                                    0xFF48
+                        // Where the color comes from, each has its own palette: 0 for BG/window,
+                        // 1 for OBP0 and 2 for OBP1.
+                        let mut layer = 0;
+
+                        // Objects. Only the one picked by priority matters, if it's behind the
+                        // BG it doesn't let other objects through.
+                        if let Some((obj, color_idx)) =
+                            self.select_object(memory, screen_x, screen_y, true)
+                        {
+                            if obj.attributes & 0x80 == 0 || bg_color_idx == 0 {
+                                let (palette_addr, obj_layer) = if obj.attributes & 0b10000 == 0 {
+                                    (0xFF48, 1)
                                 } else {
-                                    0xFF49
+                                    (0xFF49, 2)
                                 };
-
-                                if h_flip {
-                                    let mask = 1 << x_offset;
-                                    let color_idx = ((lsb & mask) >> x_offset)
-                                        | ((msb & mask) >> x_offset << 1);
-
-                                    if color_idx != 0 {
-                                        color = (memory[palette_addr] >> (color_idx * 2)) & 0b11;
-                                    }
-                                } else {
-                                    let mask = 0b10000000 >> x_offset;
-                                    let color_idx = ((lsb & mask) >> (7 - x_offset))
-                                        | ((msb & mask) >> (7 - x_offset) << 1);
-                                    if color_idx != 0 {
-                                        color = (memory[palette_addr] >> (color_idx * 2)) & 0b11;
-                                    }
-                                }
+                                color = (memory[palette_addr] >> (color_idx * 2)) & 0b11;
+                                layer = obj_layer;
                             }
                         }
 
-                        match debug_pixel_color {
-                            Some(c) => {
-                                let p: sdl2::rect::Point =
-                                    sdl2::rect::Point::new(screen_x as i32, screen_y as i32);
-                                display.c.set_draw_color(c);
-                                display.c.draw_point(p).unwrap();
+                        let rgb = match debug_pixel_color {
+                            Some(c) => c,
+                            None if memory.cgb_compat_mode() => {
+                                let c = match layer {
+                                    0 => memory.bg_palette_color(0, color),
+                                    1 => memory.obj_palette_color(0, color),
+                                    _ => memory.obj_palette_color(1, color),
+                                };
+                                palette::rgb555_to_rgb(c)
+                            }
+                            None if sgb.is_some() => {
+                                match sgb.unwrap().pixel_color(screen_x, screen_y, color) {
+                                    Some(c) => c,
+                                    // Frozen, keep the last frame
+                                    None => continue,
+                                }
                             }
                             None => {
-                                let p: sdl2::rect::Point =
-                                    sdl2::rect::Point::new(screen_x as i32, screen_y as i32);
-                                match color {
-                                    0b00 => {
-                                        display.c.set_draw_color(Color::RGB(0xFF, 0xFF, 0xFF));
-                                        display.c.draw_point(p).unwrap();
-                                    }
-                                    0b01 => {
-                                        display.c.set_draw_color(Color::RGB(0xAA, 0xAA, 0xAA));
-                                        display.c.draw_point(p).unwrap();
-                                    }
-                                    0b10 => {
-                                        display.c.set_draw_color(Color::RGB(0x55, 0x55, 0x55));
-                                        display.c.draw_point(p).unwrap();
-                                    }
-                                    0b11 => {
-                                        display.c.set_draw_color(Color::RGB(0x00, 0x00, 0x00));
-                                        display.c.draw_point(p).unwrap();
-                                    }
-                                    _ => panic!("Impossible color"),
+                                let colors = match layer {
+                                    0 => self.palette.bg,
+                                    1 => self.palette.obj0,
+                                    _ => self.palette.obj1,
                                 };
+                                colors[color as usize]
                             }
-                        }
+                        };
+                        self.put_pixel(screen_x, screen_y, rgb, color);
                     }
                 }
             }
@@ -265,7 +393,7 @@ impl Ppu {
 
         self.lx = (self.lx + 1) % 456;
         if self.lx == 0 {
-            memory.set(0xFF44, (memory[0xFF44] + 1) % 154);
+            memory.set_internal(0xFF44, (memory[0xFF44] + 1) % 154);
             if self.drew_window_on_line {
                 self.window_line += 1;
             }
@@ -277,15 +405,15 @@ impl Ppu {
 
         // Set STAT LYC=LY flag if LY == LYC
         if memory[0xFF44] == memory[0xFF45] {
-            memory.set(0xFF41, memory[0xFF41] | 0b100);
+            memory.set_internal(0xFF41, memory[0xFF41] | 0b100);
             if self.lx == 0 {
                 // Also send the STAT interrupt if the source is enabled
                 if memory[0xFF41] & 0b1000000 != 0 {
-                    memory.set(0xFF0F, memory[0xFF0F] | 0b10);
+                    memory.set_internal(0xFF0F, memory[0xFF0F] | 0b10);
                 }
             }
         } else {
-            memory.set(0xFF41, memory[0xFF41] & !0b100);
+            memory.set_internal(0xFF41, memory[0xFF41] & !0b100);
         }
 
         if self.lx == 0 {
@@ -293,41 +421,129 @@ impl Ppu {
             // See pandocs for how the circuitry causes this behavior.
             if memory[0xFF44] == 144 {
                 // VBLANK request interrupt
-                memory.set(0xFF0F, memory[0xFF0F] | 0x01);
+                memory.set_internal(0xFF0F, memory[0xFF0F] | 0x01);
                 // Set STAT mode flag to VBLANK
-                memory.set(0xFF41, (memory[0xFF41] & !0b11) | 1);
+                memory.set_internal(0xFF41, (memory[0xFF41] & !0b11) | 1);
 
                 // Also send the STAT interrupt if it's enabled
                 if memory[0xFF41] & 0b10000 != 0 {
-                    memory.set(0xFF0F, memory[0xFF0F] | 0b10);
+                    memory.set_internal(0xFF0F, memory[0xFF0F] | 0b10);
                 }
 
                 has_frame = true;
             } else {
                 // OAM search for 80 dots
                 // Set STAT mode flag to OAM search
-                memory.set(0xFF41, (memory[0xFF41] & !0b11) | 2);
+                memory.set_internal(0xFF41, (memory[0xFF41] & !0b11) | 2);
 
                 // Send STAT if enabled
                 if memory[0xFF41] & 0b100000 != 0 {
-                    memory.set(0xFF0F, memory[0xFF0F] | 0b10);
+                    memory.set_internal(0xFF0F, memory[0xFF0F] | 0b10);
                 }
             }
         } else if self.lx == 80 && memory[0xFF44] < 144 {
             // Mode 3
             // Set STAT mode flag to mode 3
-            memory.set(0xFF41, (memory[0xFF41] & !0b11) | 3);
+            memory.set_internal(0xFF41, (memory[0xFF41] & !0b11) | 3);
         } else if self.lx == 369 && memory[0xFF44] < 144 {
             // HBLANK, TODO: this isn't correct when mode 3 is lengthened
             // Set STAT mode flag to HBLANK
-            memory.set(0xFF41, memory[0xFF41] & !0b11);
+            memory.set_internal(0xFF41, memory[0xFF41] & !0b11);
 
             // Send STAT if enabled
             if memory[0xFF41] & 0b1000 != 0 {
-                memory.set(0xFF0F, memory[0xFF0F] | 0b10);
+                memory.set_internal(0xFF0F, memory[0xFF0F] | 0b10);
             }
         }
 
         return has_frame;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::Memory;
+    use crate::palette::Palette;
+    use crate::ppu::Ppu;
+
+    fn set_object(memory: &mut Memory, idx: u16, y: u8, x: u8, tile: u8, attributes: u8) {
+        let addr = 0xFE00 + idx * 4;
+        memory[addr] = y;
+        memory[addr + 1] = x;
+        memory[addr + 2] = tile;
+        memory[addr + 3] = attributes;
+    }
+
+    // Tile 1 is solid color 1, tile 2 solid color 3, tile 3 has only its first row set to 2.
+    fn setup_tiles(memory: &mut Memory) {
+        for row in 0..8 {
+            memory[0x8010 + row * 2] = 0xFF;
+            memory[0x8020 + row * 2] = 0xFF;
+            memory[0x8020 + row * 2 + 1] = 0xFF;
+        }
+        memory[0x8030 + 1] = 0xFF;
+    }
+
+    #[test]
+    fn object_priority() {
+        let mut memory = Memory::empty();
+        let mut ppu = Ppu::new(Palette::default());
+        setup_tiles(&mut memory);
+        memory[0xFF40] = 0x82;
+        memory[0xFF44] = 0;
+
+        // Same X, the lower OAM index wins
+        set_object(&mut memory, 0, 16, 8, 2, 0);
+        set_object(&mut memory, 1, 16, 8, 1, 0);
+        // Lower X wins even if it comes later in OAM
+        set_object(&mut memory, 2, 16, 20, 2, 0);
+        set_object(&mut memory, 3, 16, 19, 1, 0);
+        // Transparent pixels of the winner let the next object through
+        set_object(&mut memory, 4, 16, 40, 0, 0);
+        set_object(&mut memory, 5, 16, 41, 1, 0);
+        ppu.oam_search(&memory);
+
+        assert_eq!(Some(3), ppu.select_object(&memory, 0, 0, true).map(|o| o.1));
+        assert_eq!(
+            Some(1),
+            ppu.select_object(&memory, 12, 0, true).map(|o| o.1)
+        );
+        assert_eq!(
+            Some(3),
+            ppu.select_object(&memory, 12, 0, false).map(|o| o.1)
+        );
+        assert_eq!(
+            Some(1),
+            ppu.select_object(&memory, 33, 0, true).map(|o| o.1)
+        );
+        assert_eq!(None, ppu.select_object(&memory, 50, 0, true).map(|o| o.1));
+    }
+
+    #[test]
+    fn objects_8x16() {
+        let mut memory = Memory::empty();
+        let mut ppu = Ppu::new(Palette::default());
+        setup_tiles(&mut memory);
+        memory[0xFF40] = 0x86;
+
+        // Tile 3 is ignored in favor of 2 on top and 3 at the bottom. A vertical flip flips
+        // the whole object, the top row of tile 2 ends up on its last line.
+        set_object(&mut memory, 0, 2, 8, 3, 0x40);
+        memory[0xFF44] = 1;
+        ppu.oam_search(&memory);
+        assert_eq!(Some(3), ppu.select_object(&memory, 0, 1, true).map(|o| o.1));
+
+        // Only the bottom 2 lines of the object are on screen
+        memory[0xFF44] = 0;
+        ppu.oam_search(&memory);
+        assert_eq!(1, ppu.curr_line_objects.len());
+        memory[0xFF44] = 2;
+        ppu.oam_search(&memory);
+        assert_eq!(0, ppu.curr_line_objects.len());
+
+        set_object(&mut memory, 0, 16, 8, 3, 0x40);
+        memory[0xFF44] = 7;
+        ppu.oam_search(&memory);
+        assert_eq!(Some(2), ppu.select_object(&memory, 0, 7, true).map(|o| o.1));
+    }
+}
@@ -0,0 +1,182 @@
+use crate::palette;
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+
+// One frame is 70224 dots at 4194304 Hz, about 59.73 frames per second.
+const DOTS_PER_FRAME: u64 = 70224;
+const DOTS_PER_SECOND: u64 = 4194304;
+
+const BUTTON_NAMES: [&str; 8] = ["A", "B", "Start", "Select", "Up", "Right", "Down", "Left"];
+
+enum VideoFormat {
+    // YUV 4:4:4, which players understand but isn't quite lossless since converting from RGB
+    // rounds a little.
+    Y4m,
+    // Plain RGB24 frames back to back, no header. Lossless, but the size and frame rate have to
+    // be given to whatever reads it, e.g. ffmpeg -f rawvideo -pixel_format rgb24.
+    Rgb,
+}
+
+struct Video {
+    out: BufWriter<File>,
+    format: VideoFormat,
+    started: bool,
+}
+
+// Writes what the emulator outputs to disk, one frame at a time. Every part is optional.
+// There's no sound to record until there's an APU.
+pub struct Recorder {
+    video: Option<Video>,
+    input: Option<BufWriter<File>>,
+    frames: u64,
+    last_buttons: u8,
+}
+
+fn create(path: &Path) -> Result<BufWriter<File>, String> {
+    let file = File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    return Ok(BufWriter::new(file));
+}
+
+fn rgb_to_yuv(rgb: u32) -> (u8, u8, u8) {
+    let r = ((rgb >> 16) & 0xFF) as i32;
+    let g = ((rgb >> 8) & 0xFF) as i32;
+    let b = (rgb & 0xFF) as i32;
+
+    // BT.601, limited range, which is what players assume for Y4M
+    let y = ((66 * r + 129 * g + 25 * b + 128) >> 8) + 16;
+    let u = ((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128;
+    let v = ((112 * r - 94 * g - 18 * b + 128) >> 8) + 128;
+    return (y as u8, u as u8, v as u8);
+}
+
+impl Recorder {
+    // Video files ending in .y4m are Y4M, anything else gets raw RGB24.
+    pub fn new(video_path: Option<&Path>, input_path: Option<&Path>) -> Result<Recorder, String> {
+        let mut video = None;
+        if let Some(path) = video_path {
+            let format = match path.extension() {
+                Some(ext) if ext == "y4m" => VideoFormat::Y4m,
+                _ => VideoFormat::Rgb,
+            };
+            video = Some(Video {
+                out: create(path)?,
+                format: format,
+                started: false,
+            });
+        }
+
+        let mut input = None;
+        if let Some(path) = input_path {
+            let mut out = create(path)?;
+            writeln!(out, "# frame buttons").map_err(|e| format!("{}: {}", path.display(), e))?;
+            input = Some(out);
+        }
+
+        return Ok(Recorder {
+            video: video,
+            input: input,
+            frames: 0,
+            last_buttons: 0,
+        });
+    }
+
+    // Called at the end of every frame with the picture that was shown and the buttons that
+    // were held, bit N being joypad::Button N.
+    pub fn frame(
+        &mut self,
+        width: u32,
+        height: u32,
+        pixels: &[u32],
+        buttons: u8,
+    ) -> std::io::Result<()> {
+        if let Some(video) = self.video.as_mut() {
+            write_video_frame(video, width, height, pixels)?;
+        }
+
+        if let Some(input) = self.input.as_mut() {
+            if buttons != self.last_buttons {
+                let names: std::vec::Vec<&str> = (0..8)
+                    .filter(|i| buttons & (1 << i) != 0)
+                    .map(|i| BUTTON_NAMES[i])
+                    .collect();
+                writeln!(input, "{} {}", self.frames, names.join("+"))?;
+                self.last_buttons = buttons;
+            }
+        }
+
+        self.frames += 1;
+        return Ok(());
+    }
+
+    // Flushes everything. Also done when dropped.
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        if let Some(video) = self.video.as_mut() {
+            video.out.flush()?;
+        }
+        if let Some(input) = self.input.as_mut() {
+            input.flush()?;
+        }
+        return Ok(());
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            println!("Finishing recording failed: {}", e);
+        }
+    }
+}
+
+fn write_video_frame(
+    video: &mut Video,
+    width: u32,
+    height: u32,
+    pixels: &[u32],
+) -> std::io::Result<()> {
+    match video.format {
+        VideoFormat::Y4m => {
+            if !video.started {
+                let fps = DOTS_PER_SECOND / 16;
+                let frame_dots = DOTS_PER_FRAME / 16;
+                writeln!(
+                    video.out,
+                    "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444",
+                    width, height, fps, frame_dots
+                )?;
+            }
+            writeln!(video.out, "FRAME")?;
+
+            // Planar, all of Y then all of U then all of V
+            let yuv: std::vec::Vec<(u8, u8, u8)> = pixels.iter().map(|p| rgb_to_yuv(*p)).collect();
+            let y: std::vec::Vec<u8> = yuv.iter().map(|p| p.0).collect();
+            let u: std::vec::Vec<u8> = yuv.iter().map(|p| p.1).collect();
+            let v: std::vec::Vec<u8> = yuv.iter().map(|p| p.2).collect();
+            video.out.write_all(&y)?;
+            video.out.write_all(&u)?;
+            video.out.write_all(&v)?;
+        }
+        VideoFormat::Rgb => {
+            if !video.started {
+                println!("Recording {}x{} RGB24 frames", width, height);
+            }
+            video.out.write_all(&palette::to_rgb24(pixels))?;
+        }
+    }
+    video.started = true;
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::recorder;
+
+    #[test]
+    fn rgb_to_yuv() {
+        assert_eq!((16, 128, 128), recorder::rgb_to_yuv(0x000000));
+        assert_eq!((235, 128, 128), recorder::rgb_to_yuv(0xFFFFFF));
+    }
+}
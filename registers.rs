@@ -15,7 +15,7 @@ pub enum RegisterName {
     Invalid,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Default)]
 pub struct Registers {
     pub af: u16,
     pub bc: u16,
@@ -42,6 +42,37 @@ impl Registers {
         *r = (*r & 0xFF00) | (v as u16);
     }
 
+    // For the debugger, with the names breakpoint conditions use.
+    pub fn write_named(&mut self, name: &str, v: u16) -> Result<(), String> {
+        // Which half it is for 8 bit registers, true being the high one
+        let (r, high) = match name {
+            "a" => (&mut self.af, Some(true)),
+            "f" => (&mut self.af, Some(false)),
+            "b" => (&mut self.bc, Some(true)),
+            "c" => (&mut self.bc, Some(false)),
+            "d" => (&mut self.de, Some(true)),
+            "e" => (&mut self.de, Some(false)),
+            "h" => (&mut self.hl, Some(true)),
+            "l" => (&mut self.hl, Some(false)),
+            "af" => (&mut self.af, None),
+            "bc" => (&mut self.bc, None),
+            "de" => (&mut self.de, None),
+            "hl" => (&mut self.hl, None),
+            "sp" => (&mut self.sp, None),
+            "pc" => (&mut self.pc, None),
+            _ => return Err(format!("Unknown register {}", name)),
+        };
+        match high {
+            None => *r = v,
+            Some(_) if v > 0xFF => return Err(format!("{:X} doesn't fit in {}", v, name)),
+            Some(true) => Registers::write_first_byte(r, v as u8),
+            Some(false) => Registers::write_second_byte(r, v as u8),
+        }
+        // The low bits of F are always 0
+        self.af &= 0xFFF0;
+        return Ok(());
+    }
+
     pub fn z_set(&self) -> bool {
         return (self.af & 0b00000000_10000000) > 0;
     }
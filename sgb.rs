@@ -0,0 +1,565 @@
+use crate::memory::Memory;
+use crate::palette::rgb555_to_rgb;
+
+// The SGB draws the Game Boy screen in the middle of a 256x224 picture, the rest is the border.
+pub const SCREEN_WIDTH: u32 = 256;
+pub const SCREEN_HEIGHT: u32 = 224;
+pub const GB_SCREEN_X: u8 = 48;
+pub const GB_SCREEN_Y: u8 = 40;
+
+// What MASK_EN does to the Game Boy screen.
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Mask {
+    None,
+    // Keep showing the last frame
+    Freeze,
+    Black,
+    // Everything in color 0 of palette 0
+    Color0,
+}
+
+// Decodes the packets games send by pulsing P14/P15 in P1. A transfer starts with both lines
+// low, then every bit is one of them going low (P14 for 0, P15 for 1) with both going back high
+// in between. Packets are 16 bytes, LSB first, followed by a 0 stop bit.
+pub struct PacketReader {
+    last_select: u8,
+    receiving: bool,
+    bit_idx: usize,
+    packet: [u8; 16],
+    // Packets of the command received so far, the first one says how many there are.
+    command: std::vec::Vec<u8>,
+}
+
+impl PacketReader {
+    pub fn new() -> PacketReader {
+        return PacketReader {
+            last_select: 0x30,
+            receiving: false,
+            bit_idx: 0,
+            packet: [0; 16],
+            command: vec![],
+        };
+    }
+
+    // |select| is P1 & 0x30. Returns whole commands once their last packet is in.
+    pub fn update(&mut self, select: u8) -> Option<std::vec::Vec<u8>> {
+        let last_select = self.last_select;
+        self.last_select = select;
+
+        if select == 0x00 {
+            // Reset pulse, starts a packet
+            self.receiving = true;
+            self.bit_idx = 0;
+            self.packet = [0; 16];
+            return None;
+        }
+
+        if !self.receiving || last_select != 0x30 || select == 0x30 {
+            return None;
+        }
+
+        let bit = if select == 0x10 { 1 } else { 0 };
+        if self.bit_idx < 128 {
+            self.packet[self.bit_idx / 8] |= bit << (self.bit_idx % 8);
+            self.bit_idx += 1;
+            return None;
+        }
+
+        // Stop bit. A 1 here means the packet got garbled somehow, drop it.
+        self.receiving = false;
+        if bit != 0 {
+            self.command.clear();
+            return None;
+        }
+
+        self.command.extend_from_slice(&self.packet);
+        let packet_count = std::cmp::max(1, (self.command[0] & 0b111) as usize);
+        if self.command.len() < packet_count * 16 {
+            return None;
+        }
+
+        return Some(std::mem::take(&mut self.command));
+    }
+}
+
+pub struct Sgb {
+    // The 4 palettes the Game Boy screen is colored with, RGB555. Color 0 is shared.
+    palettes: [[u16; 4]; 4],
+    // Palettes that can be loaded in one go with PAL_SET, sent with PAL_TRN.
+    system_palettes: std::vec::Vec<u16>,
+    // Palette used by each 8x8 cell of the Game Boy screen, 20x18.
+    attributes: [u8; 20 * 18],
+    mask: Mask,
+    // 256 4bpp tiles in SNES format, sent by CHR_TRN in two halves.
+    border_tiles: std::vec::Vec<u8>,
+    // 32x32 tilemap and the 4 border palettes, sent by PCT_TRN.
+    border_map: std::vec::Vec<u16>,
+    border_palettes: [[u16; 16]; 4],
+}
+
+impl Sgb {
+    pub fn new() -> Sgb {
+        return Sgb {
+            // What the SGB shows before a game picks anything
+            palettes: [[0x67BF, 0x265B, 0x10B5, 0x2866]; 4],
+            system_palettes: vec![0; 512 * 4],
+            attributes: [0; 20 * 18],
+            mask: Mask::None,
+            border_tiles: vec![0; 256 * 32],
+            border_map: vec![0; 32 * 32],
+            border_palettes: [[0; 16]; 4],
+        };
+    }
+
+    pub fn handle_command(&mut self, data: &[u8], memory: &Memory) {
+        let command = data[0] >> 3;
+        match command {
+            0x00 => self.set_palette_pair(0, 1, data),
+            0x01 => self.set_palette_pair(2, 3, data),
+            0x02 => self.set_palette_pair(0, 3, data),
+            0x03 => self.set_palette_pair(1, 2, data),
+            0x04 => self.attr_blk(data),
+            0x05 => self.attr_lin(data),
+            0x06 => self.attr_div(data),
+            0x07 => self.attr_chr(data),
+            0x0A => self.pal_set(data),
+            0x0B => {
+                // PAL_TRN
+                let vram = Sgb::vram_transfer(memory);
+                for i in 0..self.system_palettes.len() {
+                    self.system_palettes[i] = word(&vram, i * 2);
+                }
+            }
+            0x11 => {
+                // MLT_REQ is for the joypad
+            }
+            0x13 => {
+                // CHR_TRN
+                let vram = Sgb::vram_transfer(memory);
+                let start = if data[1] & 1 == 0 { 0 } else { 0x1000 };
+                self.border_tiles[start..start + 0x1000].copy_from_slice(&vram);
+            }
+            0x14 => {
+                // PCT_TRN
+                let vram = Sgb::vram_transfer(memory);
+                for i in 0..self.border_map.len() {
+                    self.border_map[i] = word(&vram, i * 2);
+                }
+                for palette in 0..4 {
+                    for color in 0..16 {
+                        self.border_palettes[palette][color] =
+                            word(&vram, 0x800 + palette * 32 + color * 2);
+                    }
+                }
+            }
+            0x17 => {
+                // MASK_EN
+                self.mask = match data[1] & 0b11 {
+                    0 => Mask::None,
+                    1 => Mask::Freeze,
+                    2 => Mask::Black,
+                    _ => Mask::Color0,
+                };
+            }
+            _ => {
+                // Sound, SNES-side code and the like, nothing we do anything with
+            }
+        }
+    }
+
+    // VRAM transfers send what's on screen: the SGB samples the BG, left to right and top to
+    // bottom, and the first 256 tiles of it make the 4KB block.
+    fn vram_transfer(memory: &Memory) -> std::vec::Vec<u8> {
+        let lcdc = memory[0xFF40];
+        let map: u16 = if lcdc & 0b1000 != 0 { 0x9C00 } else { 0x9800 };
+        let (scx, scy) = (memory[0xFF43] as u16 / 8, memory[0xFF42] as u16 / 8);
+
+        let mut ret = std::vec::Vec::with_capacity(0x1000);
+        for i in 0..256u16 {
+            let (x, y) = ((scx + i % 20) % 32, (scy + i / 20) % 32);
+            let tile_id = memory[map + y * 32 + x];
+            let tile_addr = if lcdc & 0b10000 != 0 {
+                0x8000 + tile_id as u16 * 16
+            } else {
+                // Tile 0 is at 0x9000, 128~255 come before it
+                0x8800 + tile_id.wrapping_add(128) as u16 * 16
+            };
+            ret.extend((0..16).map(|b| memory[tile_addr + b]));
+        }
+        return ret;
+    }
+
+    fn set_palette_pair(&mut self, first: usize, second: usize, data: &[u8]) {
+        let color0 = word(data, 1);
+        for palette in self.palettes.iter_mut() {
+            palette[0] = color0;
+        }
+        for i in 0..3 {
+            self.palettes[first][i + 1] = word(data, 3 + i * 2);
+            self.palettes[second][i + 1] = word(data, 9 + i * 2);
+        }
+    }
+
+    fn set_attribute(&mut self, x: usize, y: usize, palette: u8) {
+        if x < 20 && y < 18 {
+            self.attributes[y * 20 + x] = palette & 0b11;
+        }
+    }
+
+    fn attr_blk(&mut self, data: &[u8]) {
+        let count = (data[1] & 0x1F) as usize;
+        for set in data[2..].chunks(6).take(count) {
+            if set.len() < 6 {
+                break;
+            }
+
+            let inside = set[0] & 0b001 != 0;
+            let mut border = set[0] & 0b010 != 0;
+            let outside = set[0] & 0b100 != 0;
+            let inside_palette = set[1] & 0b11;
+            let mut border_palette = (set[1] >> 2) & 0b11;
+            let outside_palette = (set[1] >> 4) & 0b11;
+
+            // With only the inside or the outside set, the border goes along with it
+            if inside && !border && !outside {
+                border = true;
+                border_palette = inside_palette;
+            } else if outside && !border && !inside {
+                border = true;
+                border_palette = outside_palette;
+            }
+
+            let (x1, y1, x2, y2) = (
+                (set[2] & 0x1F) as usize,
+                (set[3] & 0x1F) as usize,
+                (set[4] & 0x1F) as usize,
+                (set[5] & 0x1F) as usize,
+            );
+            for y in 0..18 {
+                for x in 0..20 {
+                    let in_x = x >= x1 && x <= x2;
+                    let in_y = y >= y1 && y <= y2;
+                    let on_border =
+                        (in_x && (y == y1 || y == y2)) || (in_y && (x == x1 || x == x2));
+                    if on_border {
+                        if border {
+                            self.set_attribute(x, y, border_palette);
+                        }
+                    } else if in_x && in_y {
+                        if inside {
+                            self.set_attribute(x, y, inside_palette);
+                        }
+                    } else if outside {
+                        self.set_attribute(x, y, outside_palette);
+                    }
+                }
+            }
+        }
+    }
+
+    fn attr_lin(&mut self, data: &[u8]) {
+        let count = data[1] as usize;
+        for &line in data[2..].iter().take(count) {
+            let idx = (line & 0x1F) as usize;
+            let palette = (line >> 5) & 0b11;
+            if line & 0x80 != 0 {
+                // Horizontal line, so a row of cells
+                for x in 0..20 {
+                    self.set_attribute(x, idx, palette);
+                }
+            } else {
+                for y in 0..18 {
+                    self.set_attribute(idx, y, palette);
+                }
+            }
+        }
+    }
+
+    fn attr_div(&mut self, data: &[u8]) {
+        let after = data[1] & 0b11;
+        let before = (data[1] >> 2) & 0b11;
+        let on = (data[1] >> 4) & 0b11;
+        let horizontal = data[1] & 0x40 != 0;
+        let coord = (data[2] & 0x1F) as usize;
+
+        for y in 0..18 {
+            for x in 0..20 {
+                let pos = if horizontal { y } else { x };
+                let palette = if pos < coord {
+                    before
+                } else if pos == coord {
+                    on
+                } else {
+                    after
+                };
+                self.set_attribute(x, y, palette);
+            }
+        }
+    }
+
+    fn attr_chr(&mut self, data: &[u8]) {
+        let mut x = (data[1] & 0x1F) as usize;
+        let mut y = (data[2] & 0x1F) as usize;
+        let count = (data[3] as usize) | ((data[4] as usize) << 8);
+        let vertical = data[5] & 1 != 0;
+
+        for i in 0..std::cmp::min(count, 20 * 18) {
+            let byte = match data.get(6 + i / 4) {
+                Some(b) => *b,
+                None => break,
+            };
+            let palette = (byte >> (6 - 2 * (i % 4))) & 0b11;
+            self.set_attribute(x, y, palette);
+
+            if vertical {
+                y += 1;
+                if y == 18 {
+                    y = 0;
+                    x += 1;
+                }
+            } else {
+                x += 1;
+                if x == 20 {
+                    x = 0;
+                    y += 1;
+                }
+            }
+        }
+    }
+
+    fn pal_set(&mut self, data: &[u8]) {
+        for palette in 0..4 {
+            let idx = (word(data, 1 + palette * 2) & 0x1FF) as usize;
+            for color in 0..4 {
+                self.palettes[palette][color] = self.system_palettes[idx * 4 + color];
+            }
+        }
+        // Like PAL01 and friends, color 0 comes from the first palette
+        for palette in 1..4 {
+            self.palettes[palette][0] = self.palettes[0][0];
+        }
+        if data[9] & 0x40 != 0 {
+            self.mask = Mask::None;
+        }
+    }
+
+    // Color of a Game Boy screen pixel, |shade| being what the DMG palettes gave. None when the
+    // screen is frozen and shouldn't be drawn to.
+    pub fn pixel_color(&self, x: u8, y: u8, shade: u8) -> Option<u32> {
+        let color = match self.mask {
+            Mask::None => {
+                let palette = self.attributes[(y as usize / 8) * 20 + x as usize / 8];
+                self.palettes[palette as usize][shade as usize]
+            }
+            Mask::Freeze => {
+                return None;
+            }
+            Mask::Black => 0,
+            Mask::Color0 => self.palettes[0][0],
+        };
+
+        return Some(rgb555_to_rgb(color));
+    }
+
+    // The whole SGB picture: the Game Boy screen surrounded by the border. Transparent border
+    // pixels show color 0.
+    pub fn compose(&self, gb_frame: &[u32]) -> std::vec::Vec<u32> {
+        let mut pixels = vec![0; (SCREEN_WIDTH * SCREEN_HEIGHT) as usize];
+
+        for y in 0..SCREEN_HEIGHT as usize {
+            for x in 0..SCREEN_WIDTH as usize {
+                let gb_x = x.wrapping_sub(GB_SCREEN_X as usize);
+                let gb_y = y.wrapping_sub(GB_SCREEN_Y as usize);
+                if gb_x < 160 && gb_y < 144 {
+                    pixels[y * SCREEN_WIDTH as usize + x] = gb_frame[gb_y * 160 + gb_x];
+                    continue;
+                }
+
+                let entry = self.border_map[(y / 8) * 32 + x / 8];
+                let tile = (entry & 0xFF) as usize;
+                let palette = ((entry >> 10) & 0b111) as usize;
+                let h_flip = entry & 0x4000 != 0;
+                let v_flip = entry & 0x8000 != 0;
+
+                let row = if v_flip { 7 - y % 8 } else { y % 8 };
+                let col = if h_flip { x % 8 } else { 7 - x % 8 };
+                let tile_addr = tile * 32 + row * 2;
+                let mut color_idx = 0;
+                for (plane, offset) in [0, 1, 16, 17].iter().enumerate() {
+                    let bit = (self.border_tiles[tile_addr + offset] >> col) & 1;
+                    color_idx |= bit << plane;
+                }
+
+                // Border palettes are 4 to 7
+                let color = if color_idx == 0 || palette < 4 {
+                    self.palettes[0][0]
+                } else {
+                    self.border_palettes[palette - 4][color_idx as usize]
+                };
+                pixels[y * SCREEN_WIDTH as usize + x] = rgb555_to_rgb(color);
+            }
+        }
+
+        return pixels;
+    }
+}
+
+fn word(data: &[u8], idx: usize) -> u16 {
+    return (data[idx] as u16) | ((data[idx + 1] as u16) << 8);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::Memory;
+    use crate::sgb::PacketReader;
+    use crate::sgb::Sgb;
+
+    fn send_packet(reader: &mut PacketReader, packet: &[u8; 16]) -> Option<Vec<u8>> {
+        reader.update(0x00);
+        reader.update(0x30);
+        for i in 0..128 {
+            let bit = (packet[i / 8] >> (i % 8)) & 1;
+            reader.update(if bit == 1 { 0x10 } else { 0x20 });
+            reader.update(0x30);
+        }
+        let ret = reader.update(0x20);
+        reader.update(0x30);
+        return ret;
+    }
+
+    #[test]
+    fn packet_reader() {
+        let mut reader = PacketReader::new();
+        let mut packet = [0u8; 16];
+        packet[0] = (0x04 << 3) | 2;
+        packet[15] = 0xA5;
+        assert_eq!(None, send_packet(&mut reader, &packet));
+
+        let mut second = [0u8; 16];
+        second[0] = 0x5A;
+        let command = send_packet(&mut reader, &second).unwrap();
+        assert_eq!(32, command.len());
+        assert_eq!((0x04 << 3) | 2, command[0]);
+        assert_eq!(0xA5, command[15]);
+        assert_eq!(0x5A, command[16]);
+    }
+
+    #[test]
+    fn palettes_and_attributes() {
+        let memory = Memory::empty();
+        let mut sgb = Sgb::new();
+
+        // PAL23
+        let mut pal = [0u8; 16];
+        pal[0] = (0x01 << 3) | 1;
+        pal[1] = 0x1F;
+        pal[13] = 0xE0;
+        pal[14] = 0x03;
+        sgb.handle_command(&pal, &memory);
+        assert_eq!(0x001F, sgb.palettes[0][0]);
+        assert_eq!(0x03E0, sgb.palettes[3][3]);
+
+        // ATTR_BLK: palette 3 inside (and on the border of) cells 1,1 to 2,2
+        let mut blk = [0u8; 16];
+        blk[0] = (0x04 << 3) | 1;
+        blk[1] = 1;
+        blk[2] = 0b001;
+        blk[3] = 0b11;
+        blk[4] = 1;
+        blk[5] = 1;
+        blk[6] = 2;
+        blk[7] = 2;
+        sgb.handle_command(&blk, &memory);
+        assert_eq!(3, sgb.attributes[20 + 1]);
+        assert_eq!(3, sgb.attributes[2 * 20 + 2]);
+        assert_eq!(0, sgb.attributes[3 * 20 + 3]);
+
+        // ATTR_LIN: row 5 gets palette 2
+        let mut lin = [0u8; 16];
+        lin[0] = (0x05 << 3) | 1;
+        lin[1] = 1;
+        lin[2] = 0x80 | (2 << 5) | 5;
+        sgb.handle_command(&lin, &memory);
+        assert_eq!(2, sgb.attributes[5 * 20 + 19]);
+
+        // ATTR_DIV: left of column 10 palette 1, column 10 palette 2, right palette 3
+        let mut div = [0u8; 16];
+        div[0] = (0x06 << 3) | 1;
+        div[1] = 0b10_01_11;
+        div[2] = 10;
+        sgb.handle_command(&div, &memory);
+        assert_eq!(1, sgb.attributes[9]);
+        assert_eq!(2, sgb.attributes[10]);
+        assert_eq!(3, sgb.attributes[11]);
+
+        // ATTR_CHR: 4 cells from 18,0 going right, wrapping to the next row
+        let mut chr = [0u8; 16];
+        chr[0] = (0x07 << 3) | 1;
+        chr[1] = 18;
+        chr[3] = 4;
+        chr[6] = 0b00_01_10_11;
+        sgb.handle_command(&chr, &memory);
+        assert_eq!(0, sgb.attributes[18]);
+        assert_eq!(1, sgb.attributes[19]);
+        assert_eq!(2, sgb.attributes[20]);
+        assert_eq!(3, sgb.attributes[21]);
+    }
+
+    #[test]
+    fn mask_en() {
+        let memory = Memory::empty();
+        let mut sgb = Sgb::new();
+        let mut mask = [0u8; 16];
+        mask[0] = (0x17 << 3) | 1;
+
+        mask[1] = 1;
+        sgb.handle_command(&mask, &memory);
+        assert_eq!(None, sgb.pixel_color(0, 0, 3));
+
+        mask[1] = 2;
+        sgb.handle_command(&mask, &memory);
+        assert_eq!(Some(0x000000), sgb.pixel_color(0, 0, 0));
+    }
+
+    // Shows tiles 0~255 in order, like games do before a transfer.
+    fn show_tiles_in_order(memory: &mut Memory) {
+        for i in 0..256u16 {
+            memory[0x9800 + (i / 20) * 32 + i % 20] = i as u8;
+        }
+    }
+
+    #[test]
+    fn vram_transfer_signed_tiles() {
+        let mut memory = Memory::empty();
+        memory[0xFF40] = 0x00;
+        show_tiles_in_order(&mut memory);
+        memory[0x9000] = 0x12;
+        memory[0x8800] = 0x34;
+
+        let vram = Sgb::vram_transfer(&memory);
+        assert_eq!(0x1000, vram.len());
+        assert_eq!(0x12, vram[0]);
+        assert_eq!(0x34, vram[128 * 16]);
+    }
+
+    #[test]
+    fn pal_trn_and_pal_set() {
+        let mut memory = Memory::empty();
+        memory[0xFF40] = 0x10;
+        show_tiles_in_order(&mut memory);
+        // Palette 2 of the system palettes, color 1
+        memory[0x8000 + 2 * 8 + 2] = 0x34;
+        memory[0x8000 + 2 * 8 + 3] = 0x12;
+
+        let mut sgb = Sgb::new();
+        let mut trn = [0u8; 16];
+        trn[0] = (0x0B << 3) | 1;
+        sgb.handle_command(&trn, &memory);
+
+        let mut set = [0u8; 16];
+        set[0] = (0x0A << 3) | 1;
+        set[3] = 2;
+        sgb.handle_command(&set, &memory);
+        assert_eq!(0x1234, sgb.palettes[1][1]);
+    }
+}
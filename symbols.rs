@@ -0,0 +1,173 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// Where each memory area starts. Labels only ever apply to addresses in their own area, so a
+// label at the end of WRAM doesn't end up naming every IO register.
+const AREAS: [u16; 10] = [
+    0x8000, 0xA000, 0xC000, 0xD000, 0xE000, 0xFE00, 0xFEA0, 0xFF00, 0xFF80, 0xFFFF,
+];
+
+// Labels from a symbol file, as written by RGBDS (-n) or no$gmb/BGB: one "bank:addr label" per
+// line, ';' starting a comment.
+#[derive(Clone, Default)]
+pub struct Symbols {
+    // Keyed by area (see area()) then address
+    by_addr: BTreeMap<(usize, u16), String>,
+    by_name: HashMap<String, (u16, usize)>,
+}
+
+// ROM addresses are told apart by bank, everything else by where it is in the memory map. RAM
+// banks don't count, there's no way to know which one is mapped when looking at an old trace.
+fn area(addr: u16, bank: usize) -> usize {
+    if addr < 0x4000 {
+        return 0;
+    }
+    if addr < 0x8000 {
+        return 1 + AREAS.len() + bank;
+    }
+    return 1 + AREAS.iter().rposition(|&start| addr >= start).unwrap();
+}
+
+impl Symbols {
+    pub fn parse(text: &str) -> Result<Symbols, String> {
+        let mut symbols = Symbols::default();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = match line.find(';') {
+                Some(comment) => &line[..comment],
+                None => line,
+            };
+            let line = line.trim();
+            // Some tools put the labels under a [labels] section
+            if line.is_empty() || line.starts_with('[') {
+                continue;
+            }
+
+            let error = || format!("Invalid symbol on line {}: {}", i + 1, line);
+            let mut parts = line.split_whitespace();
+            let location = parts.next().ok_or_else(error)?;
+            let name = parts.next().ok_or_else(error)?;
+            let (bank, addr) = match location.split_once(':') {
+                Some((bank, addr)) => (bank, addr),
+                None => ("0", location),
+            };
+            let bank = usize::from_str_radix(bank, 16).map_err(|_| error())?;
+            let addr = u16::from_str_radix(addr, 16).map_err(|_| error())?;
+
+            symbols.add(name, addr, bank);
+        }
+
+        return Ok(symbols);
+    }
+
+    pub fn load(path: &Path) -> Result<Symbols, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        return Symbols::parse(&text);
+    }
+
+    pub fn add(&mut self, name: &str, addr: u16, bank: usize) {
+        self.by_addr
+            .insert((area(addr, bank), addr), name.to_string());
+        self.by_name.insert(name.to_string(), (addr, bank));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.by_name.is_empty();
+    }
+
+    // The closest label at or before |addr| and how far past it |addr| is. |bank| is the ROM
+    // bank mapped at |addr|, if it's in ROM.
+    pub fn lookup(&self, addr: u16, bank: Option<usize>) -> Option<(&str, u16)> {
+        let area = area(addr, bank.unwrap_or(0));
+        let ((label_area, label_addr), name) = self.by_addr.range(..=(area, addr)).next_back()?;
+        if *label_area != area {
+            return None;
+        }
+        return Some((name, addr - label_addr));
+    }
+
+    // "Main.loop" or "Main.loop+$3"
+    pub fn name(&self, addr: u16, bank: Option<usize>) -> Option<String> {
+        return self.lookup(addr, bank).map(|(name, offset)| {
+            if offset == 0 {
+                return name.to_string();
+            }
+            return format!("{}+${:X}", name, offset);
+        });
+    }
+
+    // " (Main.loop+$3)" when there's a label for |addr|, to put after an address.
+    pub fn suffix(&self, addr: u16, bank: Option<usize>) -> String {
+        return match self.name(addr, bank) {
+            Some(name) => format!(" ({})", name),
+            None => String::new(),
+        };
+    }
+
+    // Address of |name|, along with its bank when it's in switchable ROM.
+    pub fn resolve(&self, name: &str) -> Option<(u16, Option<usize>)> {
+        let &(addr, bank) = self.by_name.get(name)?;
+        if (0x4000..0x8000).contains(&addr) {
+            return Some((addr, Some(bank)));
+        }
+        return Some((addr, None));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::symbols::Symbols;
+
+    const SYM: &str = "; File generated by rgblink
+00:0150 Main
+00:0158 Main.loop
+03:4000 Bank3Start
+01:4000 Bank1Start
+00:C000 wBuffer
+00:FF80 hFrameCounter
+";
+
+    #[test]
+    fn lookup() {
+        let symbols = Symbols::parse(SYM).unwrap();
+
+        assert_eq!(Some("Main".to_string()), symbols.name(0x0150, Some(0)));
+        assert_eq!(Some("Main+$5".to_string()), symbols.name(0x0155, Some(0)));
+        assert_eq!(
+            Some("Main.loop+$2".to_string()),
+            symbols.name(0x015A, Some(0))
+        );
+        assert_eq!(None, symbols.name(0x0100, Some(0)));
+
+        assert_eq!(
+            Some("Bank3Start+$10".to_string()),
+            symbols.name(0x4010, Some(3))
+        );
+        assert_eq!(
+            Some("Bank1Start".to_string()),
+            symbols.name(0x4000, Some(1))
+        );
+        assert_eq!(None, symbols.name(0x4000, Some(2)));
+
+        assert_eq!(Some("wBuffer+$20".to_string()), symbols.name(0xC020, None));
+        // IO registers are a different area from WRAM
+        assert_eq!(None, symbols.name(0xFF40, None));
+        assert_eq!(
+            Some("hFrameCounter".to_string()),
+            symbols.name(0xFF80, None)
+        );
+    }
+
+    #[test]
+    fn resolve() {
+        let symbols = Symbols::parse(SYM).unwrap();
+        assert_eq!(Some((0x0158, None)), symbols.resolve("Main.loop"));
+        assert_eq!(Some((0x4000, Some(3))), symbols.resolve("Bank3Start"));
+        assert_eq!(None, symbols.resolve("Nope"));
+
+        assert!(Symbols::parse("00:XYZ0 Bad").is_err());
+        assert!(Symbols::parse("[labels]\n0150 NoBank").is_ok());
+    }
+}
@@ -0,0 +1,144 @@
+use crate::memory::Memory;
+use crate::registers::Registers;
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+// One line per instruction, in the format Gameboy Doctor compares against its reference logs:
+// "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02"
+fn line(registers: &Registers, pcmem: [u8; 4]) -> String {
+    return format!(
+        "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+        registers.af >> 8,
+        registers.af & 0xFF,
+        registers.bc >> 8,
+        registers.bc & 0xFF,
+        registers.de >> 8,
+        registers.de & 0xFF,
+        registers.hl >> 8,
+        registers.hl & 0xFF,
+        registers.sp,
+        registers.pc,
+        pcmem[0],
+        pcmem[1],
+        pcmem[2],
+        pcmem[3]
+    );
+}
+
+// Logs the CPU state before every instruction it runs.
+pub struct Trace {
+    out: BufWriter<File>,
+    path: PathBuf,
+    // Only instructions in this range get logged when set, both ends included.
+    range: Option<(u16, u16)>,
+    // Frames left to log, nothing more gets written once it gets to 0.
+    frames_left: Option<u64>,
+    // With a size, lines only go to the file when the trace is dropped, which also happens when
+    // the console thread panics. Keeps the file small when all that matters is how it got there.
+    last: Option<(usize, VecDeque<String>)>,
+}
+
+impl Trace {
+    pub fn new(
+        path: &Path,
+        range: Option<(u16, u16)>,
+        frames: Option<u64>,
+        last: Option<usize>,
+    ) -> Result<Trace, String> {
+        let file = File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        return Ok(Trace {
+            out: BufWriter::new(file),
+            path: path.to_path_buf(),
+            range: range,
+            frames_left: frames,
+            last: last.map(|size| (size, VecDeque::with_capacity(size))),
+        });
+    }
+
+    // Called with the registers as they are right before the instruction at PC runs.
+    pub fn instruction(&mut self, registers: &Registers, memory: &Memory) -> std::io::Result<()> {
+        let pc = registers.pc;
+        if self.frames_left == Some(0) {
+            return Ok(());
+        }
+        if let Some((start, end)) = self.range {
+            if pc < start || pc > end {
+                return Ok(());
+            }
+        }
+
+        let mut pcmem = [0u8; 4];
+        for (i, byte) in pcmem.iter_mut().enumerate() {
+            *byte = memory.fetch(pc.wrapping_add(i as u16));
+        }
+        let line = line(registers, pcmem);
+        match self.last.as_mut() {
+            Some((size, lines)) => {
+                if lines.len() == *size {
+                    lines.pop_front();
+                }
+                lines.push_back(line);
+            }
+            None => writeln!(self.out, "{}", line)?,
+        }
+        return Ok(());
+    }
+
+    pub fn end_frame(&mut self) {
+        if let Some(frames_left) = self.frames_left.as_mut() {
+            *frames_left = frames_left.saturating_sub(1);
+        }
+    }
+
+    // Writes out whatever is held back and flushes. Also done when dropped.
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        if let Some((_, lines)) = self.last.as_mut() {
+            for line in lines.drain(..) {
+                writeln!(self.out, "{}", line)?;
+            }
+        }
+        return self.out.flush();
+    }
+}
+
+impl Drop for Trace {
+    fn drop(&mut self) {
+        let held_back = self.last.as_ref().map_or(0, |(_, lines)| lines.len());
+        match self.finish() {
+            Ok(()) if std::thread::panicking() && held_back > 0 => println!(
+                "Wrote the last {} instructions to {}",
+                held_back,
+                self.path.display()
+            ),
+            Ok(()) => {}
+            Err(e) => println!("Finishing trace failed: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::registers::Registers;
+    use crate::trace;
+
+    #[test]
+    fn line() {
+        let registers = Registers {
+            af: 0x01B0,
+            bc: 0x0013,
+            de: 0x00D8,
+            hl: 0x014D,
+            sp: 0xFFFE,
+            pc: 0x0100,
+        };
+        assert_eq!(
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02",
+            trace::line(&registers, [0x00, 0xC3, 0x13, 0x02])
+        );
+    }
+}
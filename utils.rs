@@ -9,3 +9,30 @@ pub fn le_to_be(v: u16) -> u16 {
 pub fn bytes_to_le_word(b1: u8, b2: u8) -> u16 {
     return ((b1 as u16) << 8) | (b2 as u16);
 }
+
+// Seconds since the Unix epoch as "YYYY-MM-DD-HH-MM-SS" in UTC, handy for file names.
+pub fn file_timestamp(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let time = secs % 86400;
+
+    // Days to a civil date, from http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    return format!(
+        "{:04}-{:02}-{:02}-{:02}-{:02}-{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        (time / 60) % 60,
+        time % 60
+    );
+}
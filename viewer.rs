@@ -0,0 +1,533 @@
+use crate::display::Display;
+use crate::memory::Memory;
+use crate::palette;
+use crate::palette::Palette;
+
+// Debug windows showing what's in VRAM, OAM and the palettes. Each one is opened and closed
+// with its own hotkey and redrawn once per frame.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum View {
+    BgMaps,
+    Tiles,
+    Oam,
+    Palettes,
+}
+
+// Windows are this many times bigger than the pictures they show.
+const SCALE: u32 = 2;
+
+const VIEWPORT_COLOR: u32 = 0xFF0000;
+const WINDOW_COLOR: u32 = 0x0000FF;
+const EMPTY_COLOR: u32 = 0x404040;
+
+impl View {
+    fn title(&self) -> &'static str {
+        return match self {
+            View::BgMaps => "BG maps",
+            View::Tiles => "Tiles",
+            View::Oam => "OAM",
+            View::Palettes => "Palettes",
+        };
+    }
+
+    fn size(&self) -> (u32, u32) {
+        return match self {
+            // Both maps side by side
+            View::BgMaps => (512, 256),
+            // 0x8000 and 0x8800 addressing side by side, VRAM bank 1 under bank 0
+            View::Tiles => (256, 256),
+            // 8 columns of 5 entries, 16x24 each
+            View::Oam => (128, 120),
+            // BG palettes on the left, object palettes on the right
+            View::Palettes => (128, 128),
+        };
+    }
+}
+
+struct Picture {
+    width: usize,
+    pixels: std::vec::Vec<u8>,
+}
+
+impl Picture {
+    fn new(width: u32, height: u32) -> Picture {
+        return Picture {
+            width: width as usize,
+            pixels: vec![0; (width * height * 3) as usize],
+        };
+    }
+
+    fn set(&mut self, x: usize, y: usize, rgb: u32) {
+        let idx = (y * self.width + x) * 3;
+        self.pixels[idx] = (rgb >> 16) as u8;
+        self.pixels[idx + 1] = (rgb >> 8) as u8;
+        self.pixels[idx + 2] = rgb as u8;
+    }
+}
+
+fn tile_color_idx(memory: &Memory, bank: usize, tile_addr: u16, x: u16, y: u16) -> u8 {
+    let lsb = memory.read_vram(bank, tile_addr + y * 2);
+    let msb = memory.read_vram(bank, tile_addr + y * 2 + 1);
+    return (((msb >> (7 - x)) & 1) << 1) | ((lsb >> (7 - x)) & 1);
+}
+
+// Where BG tile |tile_id| is with the addressing mode picked by LCDC bit 4.
+fn bg_tile_addr(memory: &Memory, tile_id: u8) -> u16 {
+    if memory[0xFF40] & 0b10000 != 0 {
+        return 0x8000 + tile_id as u16 * 16;
+    }
+    return 0x8800 + tile_id.wrapping_add(128) as u16 * 16;
+}
+
+// BG colors go through BGP, or CGB palette |cgb_palette| in CGB mode.
+fn bg_color(memory: &Memory, dmg_palette: &Palette, cgb_palette: u8, color_idx: u8) -> u32 {
+    if memory.cgb_mode() {
+        return palette::rgb555_to_rgb(memory.bg_palette_color(cgb_palette, color_idx));
+    }
+
+    let shade = (memory[0xFF47] >> (color_idx * 2)) & 0b11;
+    if memory.cgb_compat_mode() {
+        return palette::rgb555_to_rgb(memory.bg_palette_color(0, shade));
+    }
+    return dmg_palette.bg[shade as usize];
+}
+
+fn obj_color(memory: &Memory, dmg_palette: &Palette, attributes: u8, color_idx: u8) -> u32 {
+    if memory.cgb_mode() {
+        return palette::rgb555_to_rgb(memory.obj_palette_color(attributes & 0b111, color_idx));
+    }
+
+    let obp1 = attributes & 0b10000 != 0;
+    let obp = if obp1 { memory[0xFF49] } else { memory[0xFF48] };
+    let shade = (obp >> (color_idx * 2)) & 0b11;
+    if memory.cgb_compat_mode() {
+        return palette::rgb555_to_rgb(memory.obj_palette_color(obp1 as u8, shade));
+    }
+    return if obp1 {
+        dmg_palette.obj1[shade as usize]
+    } else {
+        dmg_palette.obj0[shade as usize]
+    };
+}
+
+// Outline of a |width|x|height| rectangle at |x|,|y| on a 256x256 map starting at |origin_x|,
+// wrapping around its edges like the viewport does.
+fn draw_wrapping_rect(
+    picture: &mut Picture,
+    origin_x: usize,
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+    rgb: u32,
+) {
+    for i in 0..width {
+        picture.set(origin_x + (x + i) % 256, y % 256, rgb);
+        picture.set(origin_x + (x + i) % 256, (y + height - 1) % 256, rgb);
+    }
+    for j in 0..height {
+        picture.set(origin_x + x % 256, (y + j) % 256, rgb);
+        picture.set(origin_x + (x + width - 1) % 256, (y + j) % 256, rgb);
+    }
+}
+
+fn draw_bg_maps(memory: &Memory, dmg_palette: &Palette, picture: &mut Picture) {
+    for map in 0..2 {
+        let map_addr = 0x9800 + map as u16 * 0x400;
+        for y in 0..256u16 {
+            for x in 0..256u16 {
+                let entry = map_addr + (y / 8) * 32 + x / 8;
+                let tile_id = memory.read_vram(0, entry);
+                let attributes = if memory.cgb_mode() {
+                    memory.read_vram(1, entry)
+                } else {
+                    0
+                };
+
+                let tile_x = if attributes & 0x20 != 0 {
+                    7 - x % 8
+                } else {
+                    x % 8
+                };
+                let tile_y = if attributes & 0x40 != 0 {
+                    7 - y % 8
+                } else {
+                    y % 8
+                };
+                let bank = ((attributes >> 3) & 1) as usize;
+                let color_idx =
+                    tile_color_idx(memory, bank, bg_tile_addr(memory, tile_id), tile_x, tile_y);
+
+                picture.set(
+                    map * 256 + x as usize,
+                    y as usize,
+                    bg_color(memory, dmg_palette, attributes & 0b111, color_idx),
+                );
+            }
+        }
+    }
+
+    // What's on screen from the BG map, and how much of the window map is shown.
+    let lcdc = memory[0xFF40];
+    let bg_map = if lcdc & 0b1000 != 0 { 1 } else { 0 };
+    draw_wrapping_rect(
+        picture,
+        bg_map * 256,
+        memory[0xFF43] as usize,
+        memory[0xFF42] as usize,
+        160,
+        144,
+        VIEWPORT_COLOR,
+    );
+
+    let wx = memory[0xFF4B] as usize;
+    let wy = memory[0xFF4A] as usize;
+    if lcdc & 0b100000 != 0 && wx <= 166 && wy <= 143 {
+        let window_map = if lcdc & 0b1000000 != 0 { 1 } else { 0 };
+        let width = 160 - wx.saturating_sub(7);
+        draw_wrapping_rect(
+            picture,
+            window_map * 256,
+            0,
+            0,
+            width,
+            144 - wy,
+            WINDOW_COLOR,
+        );
+    }
+}
+
+// Address of tile |idx| in the tiles view, |signed| being for 0x8800 addressing.
+fn tile_addr(idx: u8, signed: bool) -> u16 {
+    if signed {
+        return (0x9000i32 + (idx as i8) as i32 * 16) as u16;
+    }
+    return 0x8000 + idx as u16 * 16;
+}
+
+fn draw_tiles(memory: &Memory, dmg_palette: &Palette, picture: &mut Picture) {
+    let banks = if memory.cgb_mode() { 2 } else { 1 };
+    for bank in 0..2 {
+        for panel in 0..2 {
+            for idx in 0..256usize {
+                let addr = tile_addr(idx as u8, panel == 1);
+                let origin_x = panel * 128 + (idx % 16) * 8;
+                let origin_y = bank * 128 + (idx / 16) * 8;
+
+                for y in 0..8 {
+                    for x in 0..8 {
+                        let rgb = if bank < banks {
+                            let color_idx = tile_color_idx(memory, bank, addr, x, y);
+                            bg_color(memory, dmg_palette, 0, color_idx)
+                        } else {
+                            EMPTY_COLOR
+                        };
+                        picture.set(origin_x + x as usize, origin_y + y as usize, rgb);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn draw_oam(memory: &Memory, dmg_palette: &Palette, picture: &mut Picture) {
+    let obj_size_16 = memory[0xFF40] & 0b100 != 0;
+
+    for entry in 0..40usize {
+        let addr = 0xFE00 + entry as u16 * 4;
+        let tile = memory[addr + 2];
+        let attributes = memory[addr + 3];
+        let bank = if memory.cgb_mode() {
+            ((attributes >> 3) & 1) as usize
+        } else {
+            0
+        };
+
+        let origin_x = (entry % 8) * 16;
+        let origin_y = (entry / 8) * 24;
+        for y in 0..24 {
+            for x in 0..16 {
+                picture.set(origin_x + x, origin_y + y, EMPTY_COLOR);
+            }
+        }
+
+        let height = if obj_size_16 { 16 } else { 8 };
+        for y in 0..height {
+            for x in 0..8 {
+                let tile_id = if obj_size_16 {
+                    (tile & 0xFE) as u16 + y / 8
+                } else {
+                    tile as u16
+                };
+                let color_idx = tile_color_idx(memory, bank, 0x8000 + tile_id * 16, x, y % 8);
+                if color_idx != 0 {
+                    picture.set(
+                        origin_x + 4 + x as usize,
+                        origin_y + 4 + y as usize,
+                        obj_color(memory, dmg_palette, attributes, color_idx),
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn draw_palettes(memory: &Memory, dmg_palette: &Palette, picture: &mut Picture) {
+    for row in 0..8 {
+        for color in 0..4 {
+            let (bg, obj) = if memory.cgb_mode() {
+                (
+                    Some(palette::rgb555_to_rgb(memory.bg_palette_color(row, color))),
+                    Some(palette::rgb555_to_rgb(memory.obj_palette_color(row, color))),
+                )
+            } else {
+                // BGP, OBP0 and OBP1 as they show up on screen
+                (
+                    if row == 0 {
+                        Some(bg_color(memory, dmg_palette, 0, color))
+                    } else {
+                        None
+                    },
+                    if row < 2 {
+                        Some(obj_color(memory, dmg_palette, row << 4, color))
+                    } else {
+                        None
+                    },
+                )
+            };
+
+            for y in 0..16 {
+                for x in 0..16 {
+                    let px = color as usize * 16 + x;
+                    let py = row as usize * 16 + y;
+                    picture.set(px, py, bg.unwrap_or(EMPTY_COLOR));
+                    picture.set(64 + px, py, obj.unwrap_or(EMPTY_COLOR));
+                }
+            }
+        }
+    }
+}
+
+// What's under the mouse at |x|,|y| in the picture of |view|.
+fn describe(view: View, memory: &Memory, x: usize, y: usize) -> String {
+    return match view {
+        View::BgMaps => {
+            let map_addr = 0x9800 + (x / 256) as u16 * 0x400;
+            let entry = map_addr + (y / 8) as u16 * 32 + ((x % 256) / 8) as u16;
+            let tile_id = memory.read_vram(0, entry);
+            let mut ret = format!(
+                "Map {:04X} entry {:04X}: tile {:02X} at {:04X}",
+                map_addr,
+                entry,
+                tile_id,
+                bg_tile_addr(memory, tile_id)
+            );
+            if memory.cgb_mode() {
+                ret += &format!(", attributes {:02X}", memory.read_vram(1, entry));
+            }
+            ret
+        }
+        View::Tiles => {
+            let signed = x >= 128;
+            let bank = y / 128;
+            let idx = (((y % 128) / 8) * 16 + (x % 128) / 8) as u8;
+            format!(
+                "Tile {:02X} ({} addressing) at {}:{:04X}",
+                idx,
+                if signed { "0x8800" } else { "0x8000" },
+                bank,
+                tile_addr(idx, signed)
+            )
+        }
+        View::Oam => {
+            let entry = (y / 24) * 8 + x / 16;
+            if entry >= 40 {
+                return String::new();
+            }
+            let addr = 0xFE00 + entry as u16 * 4;
+            let attributes = memory[addr + 3];
+            let palette = if memory.cgb_mode() {
+                format!(
+                    "palette {} bank {}",
+                    attributes & 0b111,
+                    (attributes >> 3) & 1
+                )
+            } else {
+                format!("OBP{}", (attributes >> 4) & 1)
+            };
+            format!(
+                "OAM {} at {:04X}: Y={} X={} tile {:02X} {}{}{}{}",
+                entry,
+                addr,
+                memory[addr],
+                memory[addr + 1],
+                memory[addr + 2],
+                palette,
+                if attributes & 0x20 != 0 {
+                    " x-flip"
+                } else {
+                    ""
+                },
+                if attributes & 0x40 != 0 {
+                    " y-flip"
+                } else {
+                    ""
+                },
+                if attributes & 0x80 != 0 {
+                    " behind BG"
+                } else {
+                    ""
+                },
+            )
+        }
+        View::Palettes => {
+            let obj = x >= 64;
+            let row = (y / 16) as u8;
+            let color = ((x % 64) / 16) as u8;
+            if memory.cgb_mode() {
+                let rgb555 = if obj {
+                    memory.obj_palette_color(row, color)
+                } else {
+                    memory.bg_palette_color(row, color)
+                };
+                format!(
+                    "{} palette {} color {}: {:04X} (#{:06X})",
+                    if obj { "OBJ" } else { "BG" },
+                    row,
+                    color,
+                    rgb555,
+                    palette::rgb555_to_rgb(rgb555)
+                )
+            } else {
+                let register = match (obj, row) {
+                    (false, 0) => 0xFF47,
+                    (true, 0) => 0xFF48,
+                    (true, 1) => 0xFF49,
+                    _ => return String::new(),
+                };
+                format!(
+                    "{:04X} color {}: shade {}",
+                    register,
+                    color,
+                    (memory[register] >> (color * 2)) & 0b11
+                )
+            }
+        }
+    };
+}
+
+struct ViewWindow {
+    view: View,
+    display: Display,
+}
+
+pub struct Viewer {
+    video_subsystem: sdl2::VideoSubsystem,
+    windows: std::vec::Vec<ViewWindow>,
+}
+
+impl Viewer {
+    pub fn new(video_subsystem: sdl2::VideoSubsystem) -> Viewer {
+        return Viewer {
+            video_subsystem: video_subsystem,
+            windows: vec![],
+        };
+    }
+
+    pub fn toggle(&mut self, view: View) {
+        if let Some(idx) = self.windows.iter().position(|w| w.view == view) {
+            self.windows.remove(idx);
+            return;
+        }
+
+        let (width, height) = view.size();
+        let display = Display::new(
+            &self.video_subsystem,
+            view.title(),
+            width * SCALE,
+            height * SCALE,
+            SCALE as f32,
+            SCALE as f32,
+        );
+        self.windows.push(ViewWindow {
+            view: view,
+            display: display,
+        });
+    }
+
+    // Returns false if the window isn't one of ours.
+    pub fn close(&mut self, window_id: u32) -> bool {
+        let len = self.windows.len();
+        self.windows.retain(|w| w.display.window_id() != window_id);
+        return self.windows.len() != len;
+    }
+
+    // Shows what was clicked in the window title. Returns false if the window isn't ours.
+    pub fn click(&mut self, window_id: u32, x: i32, y: i32, memory: &Memory) -> bool {
+        let window = match self
+            .windows
+            .iter_mut()
+            .find(|w| w.display.window_id() == window_id)
+        {
+            Some(w) => w,
+            None => {
+                return false;
+            }
+        };
+
+        let (width, height) = window.view.size();
+        let x = (x.max(0) as u32 / SCALE).min(width - 1) as usize;
+        let y = (y.max(0) as u32 / SCALE).min(height - 1) as usize;
+        let description = describe(window.view, memory, x, y);
+        println!("{}", description);
+        window
+            .display
+            .set_title(&format!("{} - {}", window.view.title(), description));
+        return true;
+    }
+
+    pub fn update(&mut self, memory: &Memory, dmg_palette: &Palette) {
+        for window in self.windows.iter_mut() {
+            let (width, height) = window.view.size();
+            let mut picture = Picture::new(width, height);
+            match window.view {
+                View::BgMaps => draw_bg_maps(memory, dmg_palette, &mut picture),
+                View::Tiles => draw_tiles(memory, dmg_palette, &mut picture),
+                View::Oam => draw_oam(memory, dmg_palette, &mut picture),
+                View::Palettes => draw_palettes(memory, dmg_palette, &mut picture),
+            }
+            window.display.draw_rgb(width, height, &picture.pixels);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::Memory;
+    use crate::viewer;
+    use crate::viewer::View;
+
+    #[test]
+    fn describe() {
+        let mut memory = Memory::empty();
+        memory[0xFF40] = 0x80;
+        memory[0x9C21] = 0x05;
+        assert_eq!(
+            "Map 9C00 entry 9C21: tile 05 at 9050",
+            viewer::describe(View::BgMaps, &memory, 256 + 8, 8)
+        );
+        assert_eq!(
+            "Tile 81 (0x8800 addressing) at 0:8810",
+            viewer::describe(View::Tiles, &memory, 128 + 8, 64)
+        );
+
+        memory[0xFE04] = 16;
+        memory[0xFE05] = 8;
+        memory[0xFE06] = 0x12;
+        memory[0xFE07] = 0xB0;
+        assert_eq!(
+            "OAM 1 at FE04: Y=16 X=8 tile 12 OBP1 x-flip behind BG",
+            viewer::describe(View::Oam, &memory, 16, 0)
+        );
+    }
+}
@@ -0,0 +1,209 @@
+use crate::breakpoint;
+use crate::breakpoint::Condition;
+use crate::memory::Memory;
+use crate::registers::Registers;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WatchKind {
+    Read,
+    Write,
+    // Either one
+    Access,
+}
+
+impl WatchKind {
+    pub fn matches(&self, write: bool) -> bool {
+        return match self {
+            WatchKind::Read => !write,
+            WatchKind::Write => write,
+            WatchKind::Access => true,
+        };
+    }
+}
+
+// A CPU read or write that hit a watched address, recorded by |Memory|.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Access {
+    pub addr: u16,
+    pub value: u8,
+    // What was there before, same as |value| for reads.
+    pub old: u8,
+    pub write: bool,
+}
+
+impl Access {
+    pub fn describe(&self) -> String {
+        if self.write {
+            return format!(
+                "write {:02X} to {:04X} (was {:02X})",
+                self.value, self.addr, self.old
+            );
+        }
+        return format!("read {:02X} from {:04X}", self.value, self.addr);
+    }
+}
+
+// "C100" or "C100-C1FF", both ends included.
+pub fn parse_range(text: &str) -> Result<(u16, u16), String> {
+    let (start, end) = match text.split_once('-') {
+        Some((start, end)) => (
+            breakpoint::parse_address(start)?,
+            breakpoint::parse_address(end)?,
+        ),
+        None => {
+            let addr = breakpoint::parse_address(text)?;
+            (addr, addr)
+        }
+    };
+    if end < start {
+        return Err(format!("Invalid range {}", text));
+    }
+    return Ok((start, end));
+}
+
+#[derive(Clone, Debug)]
+pub struct Watchpoint {
+    // Given out by the console, from the same pool as breakpoints.
+    pub id: u32,
+    pub start: u16,
+    pub end: u16,
+    pub kind: WatchKind,
+    // Can use addr, value and old, e.g. "value & 0x80 == 0" to catch bit 7 getting cleared.
+    pub condition: Option<Condition>,
+    // Hits left to skip before actually stopping.
+    pub ignore_count: u32,
+    pub hit_count: u32,
+}
+
+impl Watchpoint {
+    pub fn new(start: u16, end: u16, kind: WatchKind, condition: Option<Condition>) -> Watchpoint {
+        return Watchpoint {
+            id: 0,
+            start: start,
+            end: end,
+            kind: kind,
+            condition: condition,
+            ignore_count: 0,
+            hit_count: 0,
+        };
+    }
+
+    // True when |access| should stop execution.
+    pub fn check(&mut self, access: &Access, registers: &Registers, memory: &Memory) -> bool {
+        if access.addr < self.start || access.addr > self.end || !self.kind.matches(access.write) {
+            return false;
+        }
+        if let Some(condition) = &self.condition {
+            if condition.expr.eval(registers, memory, Some(access)) == 0 {
+                return false;
+            }
+        }
+
+        self.hit_count += 1;
+        if self.ignore_count > 0 {
+            self.ignore_count -= 1;
+            return false;
+        }
+        return true;
+    }
+
+    pub fn describe(&self) -> String {
+        let kind = match self.kind {
+            WatchKind::Read => "read",
+            WatchKind::Write => "write",
+            WatchKind::Access => "access",
+        };
+        let mut ret = format!("{}: {} watch on {:04X}", self.id, kind, self.start);
+        if self.end != self.start {
+            ret += &format!("-{:04X}", self.end);
+        }
+        if let Some(condition) = &self.condition {
+            ret += &format!(" if {}", condition.text);
+        }
+        if self.ignore_count > 0 {
+            ret += &format!(", ignoring the next {}", self.ignore_count);
+        }
+        ret += &format!(", hit {} times", self.hit_count);
+        return ret;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::breakpoint::Condition;
+    use crate::memory::Memory;
+    use crate::registers::Registers;
+    use crate::watchpoint;
+    use crate::watchpoint::WatchKind;
+    use crate::watchpoint::Watchpoint;
+
+    #[test]
+    fn memory_records_watched_accesses() {
+        let mut memory = Memory::empty();
+        memory.set_watched_ranges(vec![(0xC100, 0xC1FF, WatchKind::Write)]);
+
+        memory.set(0xC0FF, 1);
+        memory.set(0xC100, 2);
+        memory.set(0xC100, 3);
+        memory.read(0xC100);
+        let accesses = memory.take_accesses();
+        assert_eq!(2, accesses.len());
+        assert_eq!(0xC100, accesses[1].addr);
+        assert_eq!(3, accesses[1].value);
+        assert_eq!(2, accesses[1].old);
+        assert!(memory.take_accesses().is_empty());
+
+        memory.set_watched_ranges(vec![(0xC100, 0xC100, WatchKind::Read)]);
+        memory.read(0xC100);
+        let accesses = memory.take_accesses();
+        assert_eq!(1, accesses.len());
+        assert!(!accesses[0].write);
+
+        // Writes to VRAM while the PPU is drawing get dropped, so there's nothing to catch
+        memory.set_watched_ranges(vec![(0x8000, 0x8000, WatchKind::Write)]);
+        memory[0xFF40] = 0x80;
+        memory[0xFF41] = 0x03;
+        memory.set(0x8000, 1);
+        assert!(memory.take_accesses().is_empty());
+        memory[0xFF41] = 0x00;
+        memory.set(0x8000, 1);
+        assert_eq!(1, memory.take_accesses().len());
+    }
+
+    #[test]
+    fn value_conditions() {
+        let mut memory = Memory::empty();
+        let registers = Registers::default();
+        let (start, end) = watchpoint::parse_range("FF40").unwrap();
+        let condition = Condition::parse("old & 0x80 && !(value & 0x80)").unwrap();
+        let mut watchpoint = Watchpoint::new(start, end, WatchKind::Write, Some(condition));
+        memory.set_watched_ranges(vec![(start, end, WatchKind::Write)]);
+
+        memory.set(0xFF40, 0x91);
+        memory.set(0xFF40, 0x93);
+        memory.set(0xFF40, 0x13);
+        let hits: std::vec::Vec<bool> = memory
+            .take_accesses()
+            .iter()
+            .map(|access| watchpoint.check(access, &registers, &memory))
+            .collect();
+        assert_eq!(vec![false, false, true], hits);
+        assert_eq!(1, watchpoint.hit_count);
+
+        watchpoint.ignore_count = 1;
+        memory.set(0xFF40, 0x93);
+        memory.set(0xFF40, 0x13);
+        memory.set(0xFF40, 0x93);
+        memory.set(0xFF40, 0x13);
+        let hits: std::vec::Vec<bool> = memory
+            .take_accesses()
+            .iter()
+            .map(|access| watchpoint.check(access, &registers, &memory))
+            .collect();
+        assert_eq!(vec![false, false, false, true], hits);
+        assert_eq!(3, watchpoint.hit_count);
+
+        assert_eq!(Ok((0xC100, 0xC1FF)), watchpoint::parse_range("C100-C1FF"));
+        assert!(watchpoint::parse_range("C1FF-C100").is_err());
+    }
+}
@@ -0,0 +1,115 @@
+// Disassembles part of a ROM file without running it:
+//   yagbe-disasm rom.gb [--bank 3] [--symbols rom.sym] [0x4000..0x4200]
+// Without a range, the whole bank gets disassembled. Labels come from rom.sym next to the ROM
+// when there is one.
+// Shared with the emulator, which uses more of it than this does.
+#[allow(dead_code)]
+mod disasm;
+mod disasm_table;
+#[allow(dead_code)]
+mod symbols;
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use symbols::Symbols;
+
+// Hex with a 0x or $ prefix, decimal otherwise.
+fn parse_number(text: &str) -> Result<usize, String> {
+    let hex = text.strip_prefix("0x").or_else(|| text.strip_prefix("$"));
+    let parsed = match hex {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => text.parse::<usize>(),
+    };
+    return parsed.map_err(|_| format!("Invalid number {}", text));
+}
+
+fn main() -> Result<(), String> {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        return Err(
+            "Usage: yagbe-disasm rom.gb [--bank N] [--symbols file] [start..end]".to_string(),
+        );
+    }
+
+    let rom = fs::read(&args[1]).map_err(|e| format!("{}: {}", args[1], e))?;
+    let mut bank = 1;
+    let mut range = None;
+    let mut symbols = None;
+
+    let mut i = 2;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--bank" => {
+                i += 1;
+                bank = parse_number(args.get(i).ok_or("--bank needs a value")?)?;
+            }
+            "--symbols" => {
+                i += 1;
+                let path = args.get(i).ok_or("--symbols needs a path")?;
+                symbols = Some(Symbols::load(Path::new(path))?);
+            }
+            other => {
+                let (start, end) = other
+                    .split_once("..")
+                    .ok_or(format!("Unknown argument: {}", other))?;
+                range = Some((parse_number(start)?, parse_number(end)?));
+            }
+        }
+        i += 1;
+    }
+
+    let sym_path = Path::new(&args[1]).with_extension("sym");
+    let symbols = match symbols {
+        Some(symbols) => symbols,
+        None if sym_path.exists() => Symbols::load(&sym_path)?,
+        None => Symbols::default(),
+    };
+
+    if bank * 0x4000 >= rom.len() {
+        return Err(format!("Bank {} is past the end of the ROM", bank));
+    }
+
+    // Bank 0 is always at 0x0000, the others get switched in at 0x4000.
+    let (start, end) = range.unwrap_or(if bank == 0 {
+        (0x0000, 0x4000)
+    } else {
+        (0x4000, 0x8000)
+    });
+    if start > end || end > 0x8000 {
+        return Err(format!("Invalid range {:04X}..{:04X}", start, end));
+    }
+
+    let read = |addr: u16| {
+        let offset = match addr {
+            0x0000..=0x3FFF => addr as usize,
+            _ => bank * 0x4000 + addr as usize - 0x4000,
+        };
+        return rom.get(offset).copied().unwrap_or(0xFF);
+    };
+
+    let labels = |addr: u16| {
+        let rom_bank = match addr {
+            0x0000..=0x3FFF => Some(0),
+            0x4000..=0x7FFF => Some(bank),
+            _ => None,
+        };
+        return symbols
+            .lookup(addr, rom_bank)
+            .map(|(name, offset)| (name.to_string(), offset));
+    };
+
+    let mut addr = start;
+    while addr < end {
+        let instruction = disasm::decode(addr as u16, &read, &labels);
+        let rom_bank = if addr < 0x4000 { 0 } else { bank };
+        if let Some(label) = &instruction.label {
+            println!("{}:", label);
+        }
+        println!("{:02X}:{}", rom_bank, instruction.describe());
+        addr += instruction.len() as usize;
+    }
+
+    return Ok(());
+}